@@ -0,0 +1,56 @@
+//! Measures the CPU time the server burns while 1,000 users are connected but nobody chats.
+//!
+//! Run with `cargo bench --bench idle`. Only Linux is supported, as CPU time is read from
+//! `/proc/self/stat`.
+
+use std::fs;
+use std::{thread, time};
+
+use conver::client::Client;
+use conver::server::Server;
+use conver::store::MemoryStore;
+
+const HOST: &str = "127.0.0.1";
+
+const USERS: usize = 1000;
+const IDLE_DURATION: time::Duration = time::Duration::from_secs(5);
+
+/// Clock ticks per second of `/proc/self/stat` times, which is 100 on virtually every Linux.
+const CLOCK_TICKS: f64 = 100.0;
+
+fn cpu_time() -> time::Duration {
+    let stat = fs::read_to_string("/proc/self/stat").unwrap();
+    // the command name may contain spaces, so fields are counted after its closing parenthesis
+    let fields: Vec<&str> = stat[stat.rfind(')').unwrap() + 2..]
+        .split_whitespace()
+        .collect();
+    let utime: f64 = fields[11].parse().unwrap();
+    let stime: f64 = fields[12].parse().unwrap();
+    time::Duration::from_secs_f64((utime + stime) / CLOCK_TICKS)
+}
+
+fn main() {
+    let server = Server::new(HOST, "0", Box::new(MemoryStore::new()))
+        .spawn()
+        .unwrap();
+    let port = server.get_address().port().to_string();
+
+    let clients: Vec<Client> = (0..USERS)
+        .map(|i| Client::register(HOST, &port, &format!("user{}", i), "password").unwrap())
+        .collect();
+
+    // let every connection settle into waiting before measuring
+    thread::sleep(time::Duration::from_secs(1));
+
+    let start = cpu_time();
+    thread::sleep(IDLE_DURATION);
+    let used = cpu_time() - start;
+
+    println!(
+        "{} idle users: {:?} of CPU time over {:?} ({:.2}% of one core)",
+        clients.len(),
+        used,
+        IDLE_DURATION,
+        100.0 * used.as_secs_f64() / IDLE_DURATION.as_secs_f64()
+    );
+}
@@ -1,17 +1,36 @@
+use std::env;
 use std::error::Error;
+use std::fs::{self, OpenOptions};
 use std::io::{self, prelude::*};
+use std::path::Path;
+use std::process;
 use std::sync::mpsc::{self, TryRecvError};
+use std::sync::{Arc, Mutex};
 use std::thread;
+use std::time::Duration;
 
+use chrono::{Local, TimeZone};
 use clap::{App, Arg};
 
 use conver::client::Client;
-use conver::people::People;
+use conver::e2e::{KeyPair, KEY_SIZE};
+use conver::message::{
+    Chat, Credentials, Handshake, Message, Presence, ServerMessage, Session, Status, Timestamp,
+    Typing,
+};
+use conver::people::{People, User};
+use conver::stream::Stream;
+use conver::tls;
 
 mod parser;
 
 use parser::Parser;
 
+const KEY_FETCH_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Where the password is read from, if set, rather than prompted for.
+const PASSWORD_VAR: &str = "CONVER_PASSWORD";
+
 fn main() {
     let matches = App::new("Point Client")
         .version("0.1.0")
@@ -35,26 +54,115 @@ fn main() {
                 .long("user")
                 .value_name("USERNAME")
                 .help("Your username")
-                .required(true),
+                .required_unless("token"),
+        )
+        .arg(
+            Arg::with_name("token")
+                .short("t")
+                .long("token")
+                .value_name("TOKEN")
+                .help("Resumes a session instead of logging in")
+                .conflicts_with_all(&["username", "register"]),
+        )
+        .arg(
+            Arg::with_name("ca")
+                .long("ca")
+                .value_name("PATH")
+                .help("Connects over TLS, trusting the CA certificates of a PEM file"),
+        )
+        .arg(
+            Arg::with_name("pin")
+                .long("pin")
+                .value_name("SHA256")
+                .help("Connects over TLS, trusting only the certificate of this fingerprint")
+                .conflicts_with("ca"),
+        )
+        .arg(
+            Arg::with_name("key")
+                .long("key")
+                .value_name("PATH")
+                .help("Encrypts direct chats end to end, with the key pair of this file"),
+        )
+        .arg(
+            Arg::with_name("register")
+                .long("register")
+                .help("Creates your account, instead of logging in to it"),
         )
         .get_matches();
 
     let host = matches.value_of("host").unwrap_or("127.0.0.1");
     let port = matches.value_of("port").unwrap_or("7878");
-    let username = matches.value_of("username").unwrap().to_string();
-
-    let client = Client::new(host, port, &username).unwrap();
+    let handshake = match matches.value_of("token") {
+        Some(token) => Handshake::Resume(token.into()),
+        None => {
+            let user = User::new(matches.value_of("username").unwrap().into());
+            let credentials = Credentials::new(user, read_password());
+            if matches.is_present("register") {
+                Handshake::Register(credentials)
+            } else {
+                Handshake::Login(credentials)
+            }
+        }
+    };
+    let tls = if let Some(ca_path) = matches.value_of("ca") {
+        Some(tls::load_client_config(ca_path))
+    } else {
+        matches.value_of("pin").map(tls::pinned_client_config)
+    };
+    let client = match tls {
+        Some(tls) => tls.and_then(|tls| Stream::connect_tls(host, port, tls)),
+        None => Stream::connect(host, port).map_err(|err| err.into()),
+    }
+    .and_then(|stream| Client::connect(stream, handshake));
+    let mut client = match client {
+        Ok(client) => client,
+        Err(err) => {
+            eprintln!("{}", err);
+            process::exit(1);
+        }
+    };
+    print_session(client.get_session());
+    if let Some(key_path) = matches.value_of("key") {
+        let key_pair = load_key_pair(key_path).unwrap();
+        println!(
+            "* encrypting direct chats, your key fingerprint is {}",
+            key_pair.get_public_key().fingerprint()
+        );
+        client.enable_encryption(key_pair).unwrap();
+    }
     handle_stream(client).unwrap();
 }
 
+/// Reads the password from `PASSWORD_VAR`, or else prompts for it without echoing it, so it never
+/// shows up in the shell history or the process list.
+fn read_password() -> String {
+    if let Ok(password) = env::var(PASSWORD_VAR) {
+        return password;
+    }
+    match rpassword::prompt_password("Password: ") {
+        Ok(password) => password,
+        Err(err) => {
+            eprintln!("{}", err);
+            process::exit(1);
+        }
+    }
+}
+
+/// The people chatted with last, either way, whose typing is shown.
+type Conversation = Arc<Mutex<Option<People>>>;
+
 fn handle_stream(client: Client) -> Result<(), Box<dyn Error>> {
     let (pulse_sender, pulse_receiver): (mpsc::Sender<()>, mpsc::Receiver<()>) = mpsc::channel();
+    let conversation = Conversation::default();
 
     let read_client = client.try_clone()?;
-    let read_handler = thread::spawn(move || handle_read_stream(read_client, pulse_sender));
+    let read_conversation = Arc::clone(&conversation);
+    let read_handler =
+        thread::spawn(move || handle_read_stream(read_client, read_conversation, pulse_sender));
 
     let write_client = client;
-    let write_handler = thread::spawn(move || handle_write_stream(write_client, pulse_receiver));
+    let write_handler =
+        thread::spawn(move || handle_write_stream(write_client, conversation, pulse_receiver));
 
     read_handler.join().unwrap();
     write_handler.join().unwrap();
@@ -62,19 +170,130 @@ fn handle_stream(client: Client) -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
-fn handle_read_stream(mut client: Client, _pulse_sender: mpsc::Sender<()>) {
+fn handle_read_stream(
+    mut client: Client,
+    conversation: Conversation,
+    _pulse_sender: mpsc::Sender<()>,
+) {
     loop {
-        let chat = client.read_chat().unwrap();
-        match chat.get_receiver() {
-            People::User(_) => println!("# {}: {}", chat.get_sender(), chat.get_body()),
-            People::Group(group) => {
-                println!("#[{}] {}: {}", group, chat.get_sender(), chat.get_body())
+        match client.read_event().unwrap() {
+            ServerMessage::Chat(chat) => {
+                let with = conversation_with(chat.get_sender(), chat.get_receiver());
+                *conversation.lock().unwrap() = Some(with);
+                print_chat(&chat);
+            }
+            ServerMessage::History(chats) => {
+                for chat in chats.iter() {
+                    print_chat(chat);
+                }
+                println!("* {} chats", chats.len());
+            }
+            ServerMessage::Ack(_) => {}
+            ServerMessage::Session(session) => print_session(&session),
+            ServerMessage::PublicKey(user, Some(public_key)) => {
+                println!(
+                    "* {}'s key fingerprint is {}",
+                    user,
+                    public_key.fingerprint()
+                )
+            }
+            ServerMessage::PublicKey(user, None) => println!("* {} has not published a key", user),
+            ServerMessage::Presence(presences) => {
+                for presence in presences.iter() {
+                    print_presence(presence);
+                }
+            }
+            ServerMessage::Typing(user, receiver) => {
+                let with = conversation_with(&user, &receiver);
+                if conversation.lock().unwrap().as_ref() == Some(&with) {
+                    println!("* {} is typing...", user);
+                }
             }
+            ServerMessage::Receipts(receipts) => {
+                for receipt in receipts.iter() {
+                    println!(
+                        "* #{} read by {} {}",
+                        receipt.get_message_id(),
+                        receipt.get_reader(),
+                        format_time(receipt.get_read_at())
+                    );
+                }
+            }
+            ServerMessage::Error(err) => println!("! {}", err),
+            ServerMessage::Notice(notice) => println!("* {}", notice),
         }
     }
 }
 
-fn handle_write_stream(mut client: Client, pulse_receiver: mpsc::Receiver<()>) {
+/// The people a chat or typing signal from `sender` to `receiver` is a conversation with, as
+/// seen by the receiver.
+fn conversation_with(sender: &User, receiver: &People) -> People {
+    match receiver {
+        People::User(_) => People::User(sender.clone()),
+        People::Group(group) => People::Group(group.clone()),
+    }
+}
+
+fn print_chat(chat: &Chat) {
+    let body = match chat.get_sealed_body() {
+        Some(_) => "<encrypted>",
+        None => chat.get_body(),
+    };
+    let time = chat.get_sent_at().map(format_time).unwrap_or_default();
+    let id = chat.get_id().map(|id| id.to_string()).unwrap_or_default();
+    match chat.get_receiver() {
+        People::User(_) => println!("#{} {} {}: {}", id, time, chat.get_sender(), body),
+        People::Group(group) => println!(
+            "#{}[{}] {} {}: {}",
+            id,
+            group,
+            time,
+            chat.get_sender(),
+            body
+        ),
+    }
+}
+
+fn print_presence(presence: &Presence) {
+    let user = presence.get_user();
+    match (presence.get_status(), presence.get_last_seen()) {
+        (Status::Online, _) => println!("* {} is online", user),
+        (Status::Away, _) => println!("* {} is away", user),
+        (Status::Offline, Some(last_seen)) => {
+            println!(
+                "* {} is offline, last seen {}",
+                user,
+                format_time(last_seen)
+            )
+        }
+        (Status::Offline, None) => println!("* {} is offline", user),
+    }
+}
+
+fn print_session(session: &Session) {
+    println!(
+        "* logged in as {}, resume until {} with --token {}",
+        session.get_user(),
+        format_time(session.get_expires_at()),
+        session.get_token()
+    );
+}
+
+fn format_time(timestamp: Timestamp) -> String {
+    match Local
+        .timestamp_millis_opt(timestamp.get_millis() as i64)
+        .single()
+    {
+        Some(time) => time.format("%Y-%m-%d %H:%M").to_string(),
+        None => String::new(),
+    }
+}
+
+fn handle_write_stream(
+    mut client: Client,
+    conversation: Conversation,
+    pulse_receiver: mpsc::Receiver<()>,
+) {
     let parser = Parser::new(client.get_user().clone());
 
     while is_pulsing(&pulse_receiver) {
@@ -84,6 +303,14 @@ fn handle_write_stream(mut client: Client, pulse_receiver: mpsc::Receiver<()>) {
         let body = match header.split_whitespace().next() {
             Some(method) => {
                 if method == "CHAT" {
+                    // the receiver is told while the body is being typed
+                    if let Ok(receiver) = parser.parse_receiver(&header) {
+                        *conversation.lock().unwrap() = Some(receiver.clone());
+                        let typing = Message::Typing(Typing::new(receiver));
+                        if let Err(err) = client.send_message(typing) {
+                            println!("! {}", err);
+                        }
+                    }
                     print!("> ");
                     io::stdout().flush().unwrap();
 
@@ -99,18 +326,54 @@ fn handle_write_stream(mut client: Client, pulse_receiver: mpsc::Receiver<()>) {
         };
 
         match parser.parse_message(header, body) {
-            Ok(message) => client.send_message(message).unwrap(),
+            Ok(message) => {
+                if let Err(err) = send_message(&mut client, message) {
+                    println!("! {}", err);
+                }
+            }
             Err(err) => println!("{}", err),
         };
         println!();
     }
 }
 
-fn is_pulsing(pulse_receiver: &mpsc::Receiver<()>) -> bool {
-    if let Err(pulse) = pulse_receiver.try_recv() {
-        if let TryRecvError::Disconnected = pulse {
-            return false;
+fn send_message(client: &mut Client, message: Message) -> Result<(), Box<dyn Error>> {
+    if let Message::Chat(ref chat) = message {
+        if let People::User(receiver) = chat.get_receiver() {
+            // the reply is read by the other thread
+            if client.get_key_pair().is_some() && client.get_public_key(receiver).is_none() {
+                client.request_public_key(receiver)?;
+                client.wait_public_key(receiver, KEY_FETCH_TIMEOUT);
+            }
         }
     }
-    true
+    client.send_message(message)
+}
+
+/// Loads the key pair saved in `path`, or saves a new one there.
+fn load_key_pair(path: &str) -> Result<KeyPair, Box<dyn Error>> {
+    if Path::new(path).exists() {
+        let secret = fs::read(path)?;
+        if secret.len() != KEY_SIZE {
+            return Err(format!("{} is not a key file", path).into());
+        }
+        let mut bytes = [0; KEY_SIZE];
+        bytes.copy_from_slice(&secret);
+        return KeyPair::from_secret(bytes);
+    }
+
+    let key_pair = KeyPair::generate()?;
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options.open(path)?.write_all(key_pair.get_secret())?;
+    Ok(key_pair)
+}
+
+fn is_pulsing(pulse_receiver: &mpsc::Receiver<()>) -> bool {
+    !matches!(pulse_receiver.try_recv(), Err(TryRecvError::Disconnected))
 }
@@ -8,7 +8,8 @@ pub struct ParseError {
 impl ParseError {
     pub fn method_type_not_found() -> ParseError {
         ParseError {
-            message: "method type (CHAT/JOIN/LEAVE) not found",
+            message:
+                "method type (CHAT/JOIN/LEAVE/HISTORY/REVOKE/FINGERPRINT/WHO/AWAY/BACK/READ/KICK/BAN/UNBAN/PROMOTE/DEMOTE) not found",
         }
     }
 
@@ -41,6 +42,42 @@ impl ParseError {
             message: "groupname not found",
         }
     }
+
+    pub fn token_not_found() -> ParseError {
+        ParseError {
+            message: "session token not found",
+        }
+    }
+
+    pub fn limit_not_found() -> ParseError {
+        ParseError {
+            message: "limit not found",
+        }
+    }
+
+    pub fn invalid_limit() -> ParseError {
+        ParseError {
+            message: "limit is not a number",
+        }
+    }
+
+    pub fn unknown_history_bound() -> ParseError {
+        ParseError {
+            message: "unknown history bound (BEFORE/AFTER)",
+        }
+    }
+
+    pub fn message_id_not_found() -> ParseError {
+        ParseError {
+            message: "message id not found",
+        }
+    }
+
+    pub fn invalid_message_id() -> ParseError {
+        ParseError {
+            message: "message id is not a number",
+        }
+    }
 }
 
 impl fmt::Display for ParseError {
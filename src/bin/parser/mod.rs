@@ -1,6 +1,9 @@
 use std::str::SplitWhitespace;
 
-use conver::message::{Chat, Join, Leave, Message};
+use conver::message::{
+    Chat, History, HistoryBound, Join, Leave, Message, MessageId, Moderate, Moderation, Read,
+    Timestamp,
+};
 use conver::people::{Group, People, User};
 
 mod error;
@@ -27,39 +30,114 @@ impl Parser {
             "CHAT" => Ok(Message::Chat(self.parse_chat(header, body.unwrap())?)),
             "JOIN" => Ok(Message::Join(self.parse_join(header)?)),
             "LEAVE" => Ok(Message::Leave(self.parse_leave(header)?)),
+            "HISTORY" => Ok(Message::History(self.parse_history(header)?)),
+            "REVOKE" => {
+                let token = header.next().ok_or(ParseError::token_not_found())?;
+                Ok(Message::RevokeSession(token.into()))
+            }
+            "FINGERPRINT" => {
+                let username = header.next().ok_or(ParseError::username_not_found())?;
+                Ok(Message::FetchKey(User::new(username.into())))
+            }
+            "WHO" => Ok(Message::Subscribe(self.parse_people(&mut header)?)),
+            "READ" => {
+                let id = header.next().ok_or(ParseError::message_id_not_found())?;
+                let id = MessageId::new(id.parse().map_err(|_| ParseError::invalid_message_id())?);
+                Ok(Message::Read(Read::new(id)))
+            }
+            "AWAY" => Ok(Message::SetAway(true)),
+            "BACK" => Ok(Message::SetAway(false)),
+            "KICK" => Ok(Message::Moderate(
+                self.parse_moderate(header, Moderation::Kick)?,
+            )),
+            "BAN" => Ok(Message::Moderate(
+                self.parse_moderate(header, Moderation::Ban)?,
+            )),
+            "UNBAN" => Ok(Message::Moderate(
+                self.parse_moderate(header, Moderation::Unban)?,
+            )),
+            "PROMOTE" => Ok(Message::Moderate(
+                self.parse_moderate(header, Moderation::Promote)?,
+            )),
+            "DEMOTE" => Ok(Message::Moderate(
+                self.parse_moderate(header, Moderation::Demote)?,
+            )),
             _ => Err(ParseError::unknown_method_type()),
         }
     }
 
+    /// Parses the receiver of a `CHAT` header, before its body is typed.
+    pub fn parse_receiver(&self, header: &str) -> Result<People, ParseError> {
+        let mut header = header.split_whitespace();
+        header.next().ok_or(ParseError::method_type_not_found())?;
+        self.parse_people(&mut header)
+    }
+
     fn parse_chat(&self, mut header: SplitWhitespace, body: String) -> Result<Chat, ParseError> {
+        let receiver = self.parse_people(&mut header)?;
+        let mut chat = Chat::new(self.sender.clone(), receiver, body);
+        chat.set_claimed_at(Timestamp::now());
+        Ok(chat)
+    }
+
+    fn parse_people(&self, header: &mut SplitWhitespace) -> Result<People, ParseError> {
         let receiver_type = header.next().ok_or(ParseError::receiver_type_not_found())?;
-        let receiver = match receiver_type.trim() {
+        match receiver_type.trim() {
             "USER" => {
                 let username = header.next().ok_or(ParseError::username_not_found())?;
                 let username = username.trim().into();
 
-                People::User(User::new(username))
+                Ok(People::User(User::new(username)))
             }
             "GROUP" => {
                 let groupname = header.next().ok_or(ParseError::groupname_not_found())?;
                 let groupname = groupname.trim().into();
 
-                People::Group(Group::new(groupname))
+                Ok(People::Group(Group::new(groupname)))
             }
-            _ => return Err(ParseError::unknown_receiver_type()),
-        };
-        Ok(Chat::new(self.sender.clone(), receiver, body))
+            _ => Err(ParseError::unknown_receiver_type()),
+        }
     }
 
     fn parse_join(&self, mut header: SplitWhitespace) -> Result<Join, ParseError> {
         let groupname = header.next().ok_or(ParseError::groupname_not_found())?;
         let group = Group::new(groupname.into());
-        Ok(Join::new(self.sender.clone(), group))
+        Ok(Join::new(group))
     }
 
     fn parse_leave(&self, mut header: SplitWhitespace) -> Result<Leave, ParseError> {
         let groupname = header.next().ok_or(ParseError::groupname_not_found())?;
         let group = Group::new(groupname.into());
-        Ok(Leave::new(self.sender.clone(), group))
+        Ok(Leave::new(group))
+    }
+
+    fn parse_moderate(
+        &self,
+        mut header: SplitWhitespace,
+        moderation: Moderation,
+    ) -> Result<Moderate, ParseError> {
+        let groupname = header.next().ok_or(ParseError::groupname_not_found())?;
+        let username = header.next().ok_or(ParseError::username_not_found())?;
+        let group = Group::new(groupname.into());
+        let user = User::new(username.into());
+        Ok(Moderate::new(group, user, moderation))
+    }
+
+    fn parse_history(&self, mut header: SplitWhitespace) -> Result<History, ParseError> {
+        let with = self.parse_people(&mut header)?;
+        let limit = header.next().ok_or(ParseError::limit_not_found())?;
+        let limit = limit.parse().map_err(|_| ParseError::invalid_limit())?;
+        let mut history = History::new(with, limit);
+
+        if let Some(direction) = header.next() {
+            let id = header.next().ok_or(ParseError::message_id_not_found())?;
+            let id = MessageId::new(id.parse().map_err(|_| ParseError::invalid_message_id())?);
+            match direction {
+                "BEFORE" => history.set_bound(HistoryBound::Before(id)),
+                "AFTER" => history.set_bound(HistoryBound::After(id)),
+                _ => return Err(ParseError::unknown_history_bound()),
+            }
+        }
+        Ok(history)
     }
 }
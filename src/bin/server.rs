@@ -1,12 +1,22 @@
+use std::fmt;
+use std::process;
+use std::time::Duration;
+
 use clap::{App, Arg};
 
+#[cfg(feature = "async")]
+use conver::server::AsyncServer;
 use conver::server::Server;
+use conver::store::log::{FsyncPolicy, LogStore};
 use conver::store::memory::MemoryStore;
 use conver::store::redis::RedisStore;
+#[cfg(feature = "sqlite")]
+use conver::store::sqlite::SqliteStore;
 use conver::store::Store;
+use conver::tls;
 
 fn main() {
-    let matches = App::new("Point Client")
+    let app = App::new("Point Client")
         .version("0.1.0")
         .arg(
             Arg::with_name("host")
@@ -27,9 +37,71 @@ fn main() {
                 .short("s")
                 .long("store")
                 .value_name("STORE")
-                .help("Store kind"),
+                .help("Store kind: memory, redis, sqlite, or log"),
+        )
+        .arg(
+            Arg::with_name("redis_url")
+                .long("redis-url")
+                .value_name("URL")
+                .help("URL of the Redis server of the redis store"),
+        )
+        .arg(
+            Arg::with_name("log_dir")
+                .long("log-dir")
+                .value_name("DIR")
+                .help("Directory of the log store, created if missing"),
+        )
+        .arg(
+            Arg::with_name("log_fsync")
+                .long("log-fsync")
+                .value_name("POLICY")
+                .help("When the log store flushes to disk: always, never, or every MILLIS"),
+        )
+        .arg(
+            Arg::with_name("log_compaction_threshold")
+                .long("log-compaction-threshold")
+                .value_name("OPERATIONS")
+                .help("Operations the log store holds before compacting them"),
+        )
+        .arg(
+            Arg::with_name("sqlite_path")
+                .long("sqlite-path")
+                .value_name("PATH")
+                .help("SQLite database file of the sqlite store, created if missing"),
+        )
+        .arg(
+            Arg::with_name("max_frame_size")
+                .long("max-frame-size")
+                .value_name("BYTES")
+                .help("Maximum size of a single frame"),
+        )
+        .arg(
+            Arg::with_name("tls_cert")
+                .long("tls-cert")
+                .value_name("PATH")
+                .help("PEM file of the certificate chain to serve TLS with")
+                .requires("tls_key"),
         )
-        .get_matches();
+        .arg(
+            Arg::with_name("tls_key")
+                .long("tls-key")
+                .value_name("PATH")
+                .help("PEM file of the private key of the TLS certificate")
+                .requires("tls_cert"),
+        )
+        .arg(
+            Arg::with_name("session_ttl")
+                .long("session-ttl")
+                .value_name("SECONDS")
+                .help("How long sessions last after logging in"),
+        );
+    #[cfg(feature = "async")]
+    let app = app.arg(
+        Arg::with_name("async")
+            .long("async")
+            .help("Serve connections with Tokio tasks instead of threads"),
+    );
+    let matches = app.get_matches();
 
     let host = matches.value_of("host").unwrap_or("127.0.0.1");
     let port = matches.value_of("port").unwrap_or("7878");
@@ -40,11 +112,173 @@ fn main() {
             let url = matches
                 .value_of("redis_url")
                 .unwrap_or("redis://127.0.0.1/");
-            Box::new(RedisStore::new(url).unwrap())
+            Box::new(or_exit(RedisStore::new(url), "can't open the redis store"))
+        }
+        "log" => {
+            let dir = matches.value_of("log_dir").unwrap_or("conver-log");
+            let mut store = or_exit(LogStore::new(dir), "can't open the log store");
+            if let Some(fsync_policy) = matches.value_of("log_fsync") {
+                store.set_fsync_policy(match fsync_policy {
+                    "always" => FsyncPolicy::Always,
+                    "never" => FsyncPolicy::Never,
+                    millis => FsyncPolicy::Interval(Duration::from_millis(or_exit(
+                        millis.parse(),
+                        "--log-fsync must be always, never, or a number of milliseconds",
+                    ))),
+                });
+            }
+            if let Some(compaction_threshold) = matches.value_of("log_compaction_threshold") {
+                store.set_compaction_threshold(or_exit(
+                    compaction_threshold.parse(),
+                    "--log-compaction-threshold must be a number of operations",
+                ));
+            }
+            Box::new(store)
+        }
+        #[cfg(feature = "sqlite")]
+        "sqlite" => {
+            let path = matches.value_of("sqlite_path").unwrap_or("conver.db");
+            Box::new(or_exit(
+                SqliteStore::new(path),
+                "can't open the sqlite store",
+            ))
         }
-        _ => Box::new(MemoryStore::new()),
+        #[cfg(not(feature = "sqlite"))]
+        "sqlite" => exit("the sqlite store needs the sqlite feature"),
+        "memory" => Box::new(MemoryStore::new()),
+        store => exit(&format!("unknown store kind {}", store)),
     };
 
-    let server = Server::new(host, port, store);
-    server.start().unwrap();
+    let max_frame_size = matches.value_of("max_frame_size").map(|max_frame_size| {
+        or_exit(
+            max_frame_size.parse(),
+            "--max-frame-size must be a number of bytes",
+        )
+    });
+    let session_ttl = matches.value_of("session_ttl").map(|session_ttl| {
+        Duration::from_secs(or_exit(
+            session_ttl.parse(),
+            "--session-ttl must be a number of seconds",
+        ))
+    });
+    let tls = matches.value_of("tls_cert").map(|cert_path| {
+        // clap makes sure the key comes along with the certificate
+        let key_path = matches.value_of("tls_key").unwrap();
+        let fingerprint = or_exit(
+            tls::load_certificate_fingerprint(cert_path),
+            "can't load the TLS certificate",
+        );
+        eprintln!(
+            "serving TLS with certificate of fingerprint {}",
+            fingerprint
+        );
+        or_exit(
+            tls::load_server_config(cert_path, key_path),
+            "can't load the TLS certificate or key",
+        )
+    });
+
+    #[cfg(feature = "async")]
+    {
+        if matches.is_present("async") {
+            let mut server = AsyncServer::new(host, port, store);
+            if let Some(max_frame_size) = max_frame_size {
+                server.set_max_frame_size(max_frame_size);
+            }
+            if let Some(session_ttl) = session_ttl {
+                server.set_session_ttl(session_ttl);
+            }
+            if let Some(tls) = tls {
+                server.set_tls(tls);
+            }
+            let runtime = or_exit(tokio::runtime::Runtime::new(), "can't start the runtime");
+            runtime.block_on(async {
+                let server = or_exit(server.spawn().await, "can't start the server");
+                let signal = wait_for_async_signal().await;
+                eprintln!("received {}, shutting down", signal);
+                server.shutdown().await;
+            });
+            return;
+        }
+    }
+
+    // signals are blocked before any thread is spawned, as threads inherit the mask of their parent
+    #[cfg(unix)]
+    let signals = block_shutdown_signals();
+
+    let mut server = Server::new(host, port, store);
+    if let Some(max_frame_size) = max_frame_size {
+        server.set_max_frame_size(max_frame_size);
+    }
+    if let Some(session_ttl) = session_ttl {
+        server.set_session_ttl(session_ttl);
+    }
+    if let Some(tls) = tls {
+        server.set_tls(tls);
+    }
+
+    #[cfg(unix)]
+    {
+        let server = or_exit(server.spawn(), "can't start the server");
+        let signal = wait_for_signal(&signals);
+        eprintln!("received signal {}, shutting down", signal);
+        server.shutdown();
+    }
+    #[cfg(not(unix))]
+    or_exit(server.start(), "server stopped");
+}
+
+/// The value of `result`, or else exits after telling what went wrong, along with why.
+fn or_exit<T, E: fmt::Display>(result: Result<T, E>, what: &str) -> T {
+    match result {
+        Ok(value) => value,
+        Err(err) => exit(&format!("{}: {}", what, err)),
+    }
+}
+
+fn exit(reason: &str) -> ! {
+    eprintln!("{}", reason);
+    process::exit(1);
+}
+
+/// Blocks SIGINT and SIGTERM on the calling thread, so they wait for `wait_for_signal` rather
+/// than killing the process.
+#[cfg(unix)]
+fn block_shutdown_signals() -> libc::sigset_t {
+    unsafe {
+        let mut signals = std::mem::zeroed();
+        libc::sigemptyset(&mut signals);
+        libc::sigaddset(&mut signals, libc::SIGINT);
+        libc::sigaddset(&mut signals, libc::SIGTERM);
+        libc::pthread_sigmask(libc::SIG_BLOCK, &signals, std::ptr::null_mut());
+        signals
+    }
+}
+
+#[cfg(unix)]
+fn wait_for_signal(signals: &libc::sigset_t) -> i32 {
+    let mut signal = 0;
+    unsafe { libc::sigwait(signals, &mut signal) };
+    signal
+}
+
+/// Waits for SIGINT or SIGTERM, or for Ctrl-C where there are no signals, returning its name.
+#[cfg(feature = "async")]
+async fn wait_for_async_signal() -> &'static str {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut interrupt = signal(SignalKind::interrupt()).unwrap();
+        let mut terminate = signal(SignalKind::terminate()).unwrap();
+        tokio::select! {
+            _ = interrupt.recv() => "SIGINT",
+            _ = terminate.recv() => "SIGTERM",
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        tokio::signal::ctrl_c().await.unwrap();
+        "Ctrl-C"
+    }
 }
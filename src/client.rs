@@ -1,47 +1,127 @@
+use std::collections::{HashMap, VecDeque};
 use std::error::Error;
-use std::io::{self, prelude::*};
-use std::net::TcpStream;
 use std::str;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
 
 use bincode;
 
-use crate::buffer::{self, Buffer, BUFFER_SIZE};
-use crate::message::{Chat, Message};
+use crate::e2e::{self, KeyPair, PublicKey};
+use crate::frame::{Codec, FrameReader, FrameWriter};
+use crate::message::{Chat, Credentials, Handshake, Message, MessageId, ServerMessage, Session};
 use crate::people::{People, User};
+use crate::stream::Stream;
 
 pub struct Client {
     user: User,
-    stream: TcpStream,
+    session: Session,
+    reader: FrameReader<Stream>,
+    // shared between clones, so frames written from two threads never interleave
+    writer: Arc<Mutex<FrameWriter<Stream>>>,
+    codec: Codec,
+    last_chat_id: Option<MessageId>,
+    key_pair: Option<Arc<KeyPair>>,
+    // shared between clones, so keys fetched by the one reading are known to the one writing
+    keyring: Arc<Keyring>,
+    // events read while fetching the key of the sender of a chat, to be returned first
+    deferred: VecDeque<ServerMessage>,
+}
+
+/// The public keys fetched from the server, or the lack of one, by user.
+#[derive(Default)]
+struct Keyring {
+    keys: Mutex<HashMap<User, Option<PublicKey>>>,
+    fetched: Condvar,
 }
 
 impl Client {
-    pub fn new(host: &str, port: &str, username: &str) -> Result<Self, Box<dyn Error>> {
-        let address = [host, port].join(":");
-        let mut stream = TcpStream::connect(address)?;
+    /// Connects as a new user, creating their account with the given password.
+    pub fn register(
+        host: &str,
+        port: &str,
+        username: &str,
+        password: &str,
+    ) -> Result<Self, Box<dyn Error>> {
+        let credentials = Credentials::new(User::new(username.into()), password.into());
+        Client::connect(
+            Stream::connect(host, port)?,
+            Handshake::Register(credentials),
+        )
+    }
 
-        let user = User::new(username.into());
-        Client::write_user(&mut stream, &user)?;
+    pub fn login(
+        host: &str,
+        port: &str,
+        username: &str,
+        password: &str,
+    ) -> Result<Self, Box<dyn Error>> {
+        let credentials = Credentials::new(User::new(username.into()), password.into());
+        Client::connect(Stream::connect(host, port)?, Handshake::Login(credentials))
+    }
 
-        Ok(Client { user, stream })
+    /// Connects as the user of a session started by logging in before.
+    pub fn resume(host: &str, port: &str, token: &str) -> Result<Self, Box<dyn Error>> {
+        Client::connect(
+            Stream::connect(host, port)?,
+            Handshake::Resume(token.into()),
+        )
     }
 
-    fn write_user(stream: &mut TcpStream, user: &User) -> Result<(), Box<dyn Error>> {
-        let user = bincode::serialize(&user)?;
-        let buf = buffer::from_vec(user);
-        if stream.write(&buf)? != buf.len() {
-            return Err(Box::new(io::Error::new(
-                io::ErrorKind::ConnectionAborted,
-                "connection was aborted while initiating connection",
-            )));
-        }
+    /// Authenticates over a connection made beforehand, which is how to connect over TLS:
+    ///
+    /// ```no_run
+    /// # use conver::client::Client;
+    /// # use conver::message::Handshake;
+    /// # use conver::stream::Stream;
+    /// # use conver::tls;
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let config = tls::load_client_config("ca.pem")?;
+    /// let stream = Stream::connect_tls("localhost", "7878", config)?;
+    /// let client = Client::connect(stream, Handshake::Resume("<token>".into()))?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn connect(stream: Stream, handshake: Handshake) -> Result<Self, Box<dyn Error>> {
+        let codec = Codec::default();
 
-        Ok(())
+        let mut reader = FrameReader::new(stream.try_clone()?, codec);
+        let mut writer = FrameWriter::new(stream, codec);
+        writer.write_frame(&bincode::serialize(&handshake)?)?;
+
+        // nothing but the reply to the handshake can come before it
+        let session = match bincode::deserialize(&reader.read_frame()?)? {
+            ServerMessage::Session(session) => session,
+            ServerMessage::Error(err) => return Err(Box::new(err)),
+            event => return Err(format!("unexpected reply to handshake: {:?}", event).into()),
+        };
+
+        Ok(Client {
+            user: session.get_user().clone(),
+            session,
+            reader,
+            writer: Arc::new(Mutex::new(writer)),
+            codec,
+            last_chat_id: None,
+            key_pair: None,
+            keyring: Arc::new(Keyring::default()),
+            deferred: VecDeque::new(),
+        })
     }
 
+    /// Clones the underlying connection. Bytes of a partially read frame, and the chats already
+    /// read, are not shared with the clone, so only one of the two should be used for reading.
     pub fn try_clone(&self) -> Result<Self, Box<dyn Error>> {
+        let stream = self.reader.get_ref().try_clone()?;
         Ok(Client {
             user: self.user.clone(),
-            stream: self.stream.try_clone()?,
+            session: self.session.clone(),
+            reader: FrameReader::new(stream, self.codec),
+            writer: Arc::clone(&self.writer),
+            codec: self.codec,
+            last_chat_id: self.last_chat_id,
+            key_pair: self.key_pair.clone(),
+            keyring: Arc::clone(&self.keyring),
+            deferred: VecDeque::new(),
         })
     }
 
@@ -49,53 +129,195 @@ impl Client {
         &self.user
     }
 
-    pub fn read_chat(&mut self) -> Result<Chat, Box<dyn Error>> {
-        let mut buf: Buffer = [0; BUFFER_SIZE];
+    /// The session the server started, or resumed, for the connection.
+    pub fn get_session(&self) -> &Session {
+        &self.session
+    }
+
+    pub fn set_max_frame_size(&mut self, max_frame_size: usize) {
+        self.codec = Codec::new(max_frame_size);
+        self.reader.set_codec(self.codec);
+        self.writer.lock().unwrap().set_codec(self.codec);
+    }
+
+    /// Publishes the public key of `key_pair`, then encrypts every direct chat sent afterward for
+    /// the key its receiver published, and decrypts the chats sent encrypted for it. Clones made
+    /// afterward do the same.
+    ///
+    /// A direct chat is only sent once the key of its receiver is known, see
+    /// `request_public_key`.
+    pub fn enable_encryption(&mut self, key_pair: KeyPair) -> Result<(), Box<dyn Error>> {
+        self.write_message(&Message::PublishKey(*key_pair.get_public_key()))?;
+        self.key_pair = Some(Arc::new(key_pair));
+        Ok(())
+    }
+
+    pub fn get_key_pair(&self) -> Option<&KeyPair> {
+        self.key_pair.as_deref()
+    }
+
+    /// Asks the server for the public key of `user`. Once the reply is read, by this client or
+    /// any of its clones, the key is known to all of them.
+    pub fn request_public_key(&mut self, user: &User) -> Result<(), Box<dyn Error>> {
+        self.write_message(&Message::FetchKey(user.clone()))
+    }
+
+    /// The public key of `user`, which is `None` until fetched, or if they published none.
+    pub fn get_public_key(&self, user: &User) -> Option<PublicKey> {
+        let keys = self.keyring.keys.lock().unwrap();
+        keys.get(user).copied().flatten()
+    }
+
+    /// Waits until the reply to `request_public_key` is read by a clone reading on another
+    /// thread, returning the public key of `user`, if they published one.
+    pub fn wait_public_key(&self, user: &User, timeout: Duration) -> Option<PublicKey> {
+        let deadline = Instant::now() + timeout;
+        let mut keys = self.keyring.keys.lock().unwrap();
         loop {
-            let n = self.stream.read(&mut buf)?;
-            if n == 0 {
-                // disconnect
-                return Err(Box::new(io::Error::new(
-                    io::ErrorKind::ConnectionAborted,
-                    "connection was aborted while reading chat",
-                )));
+            if let Some(public_key) = keys.get(user) {
+                return *public_key;
             }
-            if n != buf.len() {
-                // retry
-                continue;
+            let now = Instant::now();
+            if now >= deadline {
+                return None;
             }
-            break;
+            keys = self
+                .keyring
+                .fetched
+                .wait_timeout(keys, deadline - now)
+                .unwrap()
+                .0;
         }
-        let chat: Chat = bincode::deserialize(&buf[..])?;
-        match chat.get_receiver() {
-            People::User(user) => assert_eq!(&self.user, user),
-            People::Group(_) => {}
-        };
-        Ok(chat)
     }
 
-    pub fn send_message(&mut self, message: Message) -> Result<(), Box<dyn Error>> {
-        match message {
-            Message::Chat(ref chat) => assert_eq!(&self.user, chat.get_sender()),
-            Message::Join(ref join) => assert_eq!(&self.user, join.get_sender()),
-            Message::Leave(ref leave) => assert_eq!(&self.user, leave.get_sender()),
+    /// Reads the next event from the server. Chats are acknowledged as soon as they are read, and
+    /// chats delivered again because an acknowledgement got lost are skipped.
+    ///
+    /// With encryption enabled, chats sealed for this client are decrypted, once the key of their
+    /// sender is fetched if it's not known yet, while those that can't be are left sealed.
+    pub fn read_event(&mut self) -> Result<ServerMessage, Box<dyn Error>> {
+        let mut message = match self.deferred.pop_front() {
+            Some(message) => message,
+            None => self.read_next_event()?,
         };
-        let message = bincode::serialize(&message)?;
-        let buf = buffer::from_vec(message);
+        match message {
+            ServerMessage::Chat(ref mut chat) => self.open_chat(chat)?,
+            ServerMessage::History(ref mut chats) => {
+                for chat in chats.iter_mut() {
+                    self.open_chat(chat)?;
+                }
+            }
+            _ => {}
+        }
+        Ok(message)
+    }
+
+    /// Reads the next event off the connection, keeping the public keys it tells and
+    /// acknowledging the chats it delivers.
+    fn read_next_event(&mut self) -> Result<ServerMessage, Box<dyn Error>> {
         loop {
-            let n = self.stream.write(&buf)?;
-            if n == 0 {
-                // disconnect
-                return Err(Box::new(io::Error::new(
-                    io::ErrorKind::ConnectionAborted,
-                    "connection was aborted while sending message",
-                )));
+            let buf = self.reader.read_frame()?;
+            let message: ServerMessage = bincode::deserialize(&buf)?;
+            match message {
+                ServerMessage::PublicKey(ref user, public_key) => {
+                    let mut keys = self.keyring.keys.lock().unwrap();
+                    keys.insert(user.clone(), public_key);
+                    self.keyring.fetched.notify_all();
+                }
+                ServerMessage::Chat(ref chat) => {
+                    match chat.get_receiver() {
+                        People::User(user) => assert_eq!(&self.user, user),
+                        People::Group(_) => {}
+                    };
+                    if let Some(id) = chat.get_id() {
+                        self.write_message(&Message::Ack(id))?;
+                        // the server delivers chats in increasing id order
+                        if self.last_chat_id.is_some_and(|last_id| id <= last_id) {
+                            continue;
+                        }
+                        self.last_chat_id = Some(id);
+                    }
+                }
+                _ => {}
             }
-            if n == buf.len() {
-                // retry
-                break;
+            return Ok(message);
+        }
+    }
+
+    /// Reads events until the next chat, skipping other replies and notices along the way. An
+    /// error frame from the server is returned as an error.
+    pub fn read_chat(&mut self) -> Result<Chat, Box<dyn Error>> {
+        loop {
+            match self.read_event()? {
+                ServerMessage::Chat(chat) => return Ok(chat),
+                ServerMessage::Error(err) => return Err(Box::new(err)),
+                ServerMessage::Ack(_)
+                | ServerMessage::Session(_)
+                | ServerMessage::History(_)
+                | ServerMessage::PublicKey(..)
+                | ServerMessage::Presence(_)
+                | ServerMessage::Typing(..)
+                | ServerMessage::Receipts(_)
+                | ServerMessage::Notice(_) => {}
             }
         }
+    }
+
+    /// Sends a message to the server. With encryption enabled, a direct chat fails to be sent
+    /// unless the public key of its receiver is known.
+    pub fn send_message(&mut self, mut message: Message) -> Result<(), Box<dyn Error>> {
+        if let Message::Chat(ref mut chat) = message {
+            assert_eq!(&self.user, chat.get_sender());
+            self.seal_chat(chat)?;
+        }
+        self.write_message(&message)
+    }
+
+    fn seal_chat(&self, chat: &mut Chat) -> Result<(), Box<dyn Error>> {
+        let (key_pair, receiver) = match (&self.key_pair, chat.get_receiver()) {
+            (Some(key_pair), People::User(receiver)) => (key_pair, receiver),
+            _ => return Ok(()),
+        };
+        let keys = self.keyring.keys.lock().unwrap();
+        let public_key = match keys.get(receiver) {
+            Some(Some(public_key)) => *public_key,
+            Some(None) => return Err(format!("{} has not published a public key", receiver).into()),
+            None => return Err(format!("public key of {} is not fetched yet", receiver).into()),
+        };
+        let sealed_body = e2e::seal(key_pair, &public_key, chat.get_body())?;
+        chat.set_sealed_body(sealed_body);
+        Ok(())
+    }
+
+    /// Decrypts a chat sealed for this client by its sender, whose key is fetched first unless
+    /// it's known. The events read in the meantime are returned by `read_event` afterward.
+    fn open_chat(&mut self, chat: &mut Chat) -> Result<(), Box<dyn Error>> {
+        let key_pair = match (&self.key_pair, chat.get_sealed_body()) {
+            // chats this client sealed were sealed for others
+            (Some(key_pair), Some(_)) if chat.get_sender() != &self.user => Arc::clone(key_pair),
+            _ => return Ok(()),
+        };
+        let sender = chat.get_sender();
+        if !self.keyring.keys.lock().unwrap().contains_key(sender) {
+            self.request_public_key(sender)?;
+            while !self.keyring.keys.lock().unwrap().contains_key(sender) {
+                let message = self.read_next_event()?;
+                self.deferred.push_back(message);
+            }
+        }
+        if let (Some(public_key), Some(sealed_body)) =
+            (self.get_public_key(sender), chat.get_sealed_body())
+        {
+            if let Ok(body) = key_pair.open(&public_key, sealed_body) {
+                chat.set_body(body);
+            }
+        }
+        Ok(())
+    }
+
+    fn write_message(&mut self, message: &Message) -> Result<(), Box<dyn Error>> {
+        let message = bincode::serialize(message)?;
+        self.writer.lock().unwrap().write_frame(&message)?;
 
         Ok(())
     }
@@ -0,0 +1,181 @@
+use std::error::Error;
+use std::fmt;
+
+use ring::aead::{self, Aad, LessSafeKey, Nonce, UnboundKey, CHACHA20_POLY1305};
+use ring::digest;
+use ring::hkdf::{Salt, HKDF_SHA256};
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::{Deserialize, Serialize};
+use x25519_dalek::{SharedSecret, StaticSecret};
+
+pub const KEY_SIZE: usize = 32;
+
+const KDF_INFO: &[u8] = b"conver e2e chat";
+
+/// An X25519 public key, which users publish for others to encrypt direct chats to them.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct PublicKey([u8; KEY_SIZE]);
+
+impl PublicKey {
+    pub fn new(bytes: [u8; KEY_SIZE]) -> Self {
+        PublicKey(bytes)
+    }
+
+    pub fn as_bytes(&self) -> &[u8; KEY_SIZE] {
+        &self.0
+    }
+
+    /// The SHA-256 digest of the key, in groups of hex digits, for users to compare out of band,
+    /// making sure the server handed out the key its owner published.
+    pub fn fingerprint(&self) -> String {
+        let fingerprint = digest::digest(&digest::SHA256, &self.0);
+        fingerprint
+            .as_ref()
+            .chunks(2)
+            .map(|pair| format!("{:02x}{:02x}", pair[0], pair[1]))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+impl fmt::Display for PublicKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.fingerprint())
+    }
+}
+
+/// The key pair a user decrypts the chats sent to them with. Only its public half ever leaves
+/// the client.
+pub struct KeyPair {
+    secret: [u8; KEY_SIZE],
+    public_key: PublicKey,
+}
+
+impl KeyPair {
+    pub fn generate() -> Result<Self, Box<dyn Error>> {
+        KeyPair::from_secret(random_secret()?)
+    }
+
+    /// Restores the key pair of a secret saved from `get_secret`.
+    pub fn from_secret(secret: [u8; KEY_SIZE]) -> Result<Self, Box<dyn Error>> {
+        let public_key = x25519_dalek::PublicKey::from(&StaticSecret::from(secret));
+        Ok(KeyPair {
+            secret,
+            public_key: PublicKey(public_key.to_bytes()),
+        })
+    }
+
+    pub fn get_secret(&self) -> &[u8; KEY_SIZE] {
+        &self.secret
+    }
+
+    pub fn get_public_key(&self) -> &PublicKey {
+        &self.public_key
+    }
+
+    /// Decrypts a body sealed by `seal` for this key pair, which fails unless it was sealed by
+    /// the owner of `sender`.
+    pub fn open(&self, sender: &PublicKey, sealed: &[u8]) -> Result<String, Box<dyn Error>> {
+        if sealed.len() < KEY_SIZE + CHACHA20_POLY1305.tag_len() {
+            return Err("sealed body is too short".into());
+        }
+        let (ephemeral_public_key, ciphertext) = sealed.split_at(KEY_SIZE);
+        let mut bytes = [0; KEY_SIZE];
+        bytes.copy_from_slice(ephemeral_public_key);
+        let ephemeral_secret =
+            agree(&self.secret, &PublicKey(bytes)).ok_or("invalid ephemeral key")?;
+        let static_secret = agree(&self.secret, sender).ok_or("invalid sender key")?;
+        let key = derive_key(
+            &ephemeral_secret,
+            &static_secret,
+            ephemeral_public_key,
+            sender,
+            &self.public_key,
+        )?;
+
+        let mut in_out = ciphertext.to_vec();
+        let plaintext = key
+            .open_in_place(
+                Nonce::assume_unique_for_key([0; aead::NONCE_LEN]),
+                Aad::empty(),
+                &mut in_out,
+            )
+            .map_err(|_| "sealed body could not be decrypted")?;
+        Ok(String::from_utf8(plaintext.to_vec())?)
+    }
+}
+
+// keeps the secret out of logs
+impl fmt::Debug for KeyPair {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("KeyPair")
+            .field("public_key", &self.public_key)
+            .finish()
+    }
+}
+
+/// Encrypts a chat body so that only the owner of `receiver` can read it, and know `sender`
+/// sealed it, with a key agreed between a fresh ephemeral key pair and theirs, along with one
+/// agreed between the key pair of the sender and theirs. The ephemeral public key is sent along.
+pub fn seal(sender: &KeyPair, receiver: &PublicKey, body: &str) -> Result<Vec<u8>, Box<dyn Error>> {
+    let ephemeral_key = KeyPair::generate()?;
+    let ephemeral_public_key = ephemeral_key.get_public_key().as_bytes();
+    let ephemeral_secret =
+        agree(ephemeral_key.get_secret(), receiver).ok_or("invalid public key")?;
+    let static_secret = agree(sender.get_secret(), receiver).ok_or("invalid public key")?;
+    let key = derive_key(
+        &ephemeral_secret,
+        &static_secret,
+        ephemeral_public_key,
+        sender.get_public_key(),
+        receiver,
+    )?;
+
+    let mut in_out = body.as_bytes().to_vec();
+    // every key seals a single body, so the nonce never repeats for a key
+    key.seal_in_place_append_tag(
+        Nonce::assume_unique_for_key([0; aead::NONCE_LEN]),
+        Aad::empty(),
+        &mut in_out,
+    )
+    .map_err(|_| "body could not be encrypted")?;
+
+    let mut sealed = ephemeral_public_key.to_vec();
+    sealed.extend(in_out);
+    Ok(sealed)
+}
+
+fn random_secret() -> Result<[u8; KEY_SIZE], Box<dyn Error>> {
+    let mut secret = [0; KEY_SIZE];
+    SystemRandom::new()
+        .fill(&mut secret)
+        .map_err(|_| "could not generate a key pair")?;
+    Ok(secret)
+}
+
+/// The X25519 secret shared between `secret` and `public_key`, or `None` if the public key is one
+/// of the few that would make it known to anyone.
+fn agree(secret: &[u8; KEY_SIZE], public_key: &PublicKey) -> Option<SharedSecret> {
+    let public_key = x25519_dalek::PublicKey::from(public_key.0);
+    let shared_secret = StaticSecret::from(*secret).diffie_hellman(&public_key);
+    Some(shared_secret).filter(SharedSecret::was_contributory)
+}
+
+/// Derives the key of a single body from both secrets agreed for it, bound to the keys of all
+/// three key pairs involved.
+fn derive_key(
+    ephemeral_secret: &SharedSecret,
+    static_secret: &SharedSecret,
+    ephemeral_public_key: &[u8],
+    sender: &PublicKey,
+    receiver: &PublicKey,
+) -> Result<LessSafeKey, Box<dyn Error>> {
+    let mut shared_secret = ephemeral_secret.as_bytes().to_vec();
+    shared_secret.extend_from_slice(static_secret.as_bytes());
+    let info = [KDF_INFO, ephemeral_public_key, &sender.0, &receiver.0];
+    let prk = Salt::new(HKDF_SHA256, &[]).extract(&shared_secret);
+    let key = prk
+        .expand(&info, &CHACHA20_POLY1305)
+        .map_err(|_| "could not derive a key")?;
+    Ok(LessSafeKey::new(UnboundKey::from(key)))
+}
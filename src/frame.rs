@@ -0,0 +1,282 @@
+use std::error::Error;
+use std::fmt;
+use std::io::{self, prelude::*};
+
+#[cfg(feature = "async")]
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// Size of the big-endian length prefix preceding every frame payload.
+pub const HEADER_SIZE: usize = 4;
+
+pub const DEFAULT_MAX_FRAME_SIZE: usize = 1024 * 1024;
+
+#[derive(Debug)]
+pub enum FrameError {
+    TooLarge { size: usize, max_frame_size: usize },
+    Io(io::Error),
+}
+
+impl fmt::Display for FrameError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FrameError::TooLarge {
+                size,
+                max_frame_size,
+            } => write!(
+                f,
+                "FrameError: frame of {} bytes exceeds the maximum of {} bytes",
+                size, max_frame_size
+            ),
+            FrameError::Io(err) => write!(f, "FrameError: {}", err),
+        }
+    }
+}
+
+impl Error for FrameError {}
+
+impl From<io::Error> for FrameError {
+    fn from(err: io::Error) -> Self {
+        FrameError::Io(err)
+    }
+}
+
+/// Length-prefixed framing: every frame is a `u32` payload length followed by the payload itself.
+#[derive(Clone, Copy, Debug)]
+pub struct Codec {
+    max_frame_size: usize,
+}
+
+impl Codec {
+    pub fn new(max_frame_size: usize) -> Self {
+        Codec { max_frame_size }
+    }
+
+    pub fn get_max_frame_size(&self) -> usize {
+        self.max_frame_size
+    }
+
+    pub fn encode(&self, payload: &[u8]) -> Result<Vec<u8>, FrameError> {
+        self.check_size(payload.len())?;
+
+        let mut frame = Vec::with_capacity(HEADER_SIZE + payload.len());
+        frame.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+        frame.extend_from_slice(payload);
+        Ok(frame)
+    }
+
+    pub fn decode_header(&self, header: [u8; HEADER_SIZE]) -> Result<usize, FrameError> {
+        let size = u32::from_be_bytes(header) as usize;
+        self.check_size(size)?;
+        Ok(size)
+    }
+
+    /// Removes the first complete frame from `buf` and returns its payload, or `None` if `buf`
+    /// does not hold a complete frame yet.
+    pub fn decode(&self, buf: &mut Vec<u8>) -> Result<Option<Vec<u8>>, FrameError> {
+        if buf.len() < HEADER_SIZE {
+            return Ok(None);
+        }
+        let mut header = [0; HEADER_SIZE];
+        header.copy_from_slice(&buf[..HEADER_SIZE]);
+        let size = self.decode_header(header)?;
+
+        if buf.len() < HEADER_SIZE + size {
+            return Ok(None);
+        }
+        let payload = buf[HEADER_SIZE..HEADER_SIZE + size].to_vec();
+        buf.drain(..HEADER_SIZE + size);
+        Ok(Some(payload))
+    }
+
+    fn check_size(&self, size: usize) -> Result<(), FrameError> {
+        if size > self.max_frame_size || size > u32::MAX as usize {
+            return Err(FrameError::TooLarge {
+                size,
+                max_frame_size: self.max_frame_size,
+            });
+        }
+        Ok(())
+    }
+}
+
+impl Default for Codec {
+    fn default() -> Self {
+        Codec::new(DEFAULT_MAX_FRAME_SIZE)
+    }
+}
+
+const READ_CHUNK_SIZE: usize = 8 * 1024;
+
+/// Reads frames from a byte stream, accumulating partial reads until a whole frame has arrived.
+///
+/// Bytes of an incomplete frame are kept across calls, so a `read_frame` interrupted by a timeout
+/// or a non-blocking `WouldBlock` can simply be retried.
+pub struct FrameReader<R> {
+    inner: R,
+    codec: Codec,
+    buf: Vec<u8>,
+}
+
+impl<R: Read> FrameReader<R> {
+    pub fn new(inner: R, codec: Codec) -> Self {
+        FrameReader {
+            inner,
+            codec,
+            buf: Vec::new(),
+        }
+    }
+
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    pub fn set_codec(&mut self, codec: Codec) {
+        self.codec = codec;
+    }
+
+    pub fn read_frame(&mut self) -> Result<Vec<u8>, FrameError> {
+        let mut chunk = [0; READ_CHUNK_SIZE];
+        loop {
+            if let Some(payload) = self.codec.decode(&mut self.buf)? {
+                return Ok(payload);
+            }
+            let n = match self.inner.read(&mut chunk) {
+                Ok(0) => {
+                    return Err(FrameError::Io(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "connection was closed while reading frame",
+                    )));
+                }
+                Ok(n) => n,
+                Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(FrameError::Io(err)),
+            };
+            self.buf.extend_from_slice(&chunk[..n]);
+        }
+    }
+}
+
+/// Writes frames to a byte stream, resuming after short writes.
+///
+/// A frame whose write failed halfway stays pending and is finished by the next `flush` or
+/// `write_frame`, so the stream never carries a torn frame followed by a new one.
+pub struct FrameWriter<W> {
+    inner: W,
+    codec: Codec,
+    pending: Vec<u8>,
+    written: usize,
+}
+
+impl<W: Write> FrameWriter<W> {
+    pub fn new(inner: W, codec: Codec) -> Self {
+        FrameWriter {
+            inner,
+            codec,
+            pending: Vec::new(),
+            written: 0,
+        }
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    pub fn set_codec(&mut self, codec: Codec) {
+        self.codec = codec;
+    }
+
+    /// Whether a previously started frame has yet to be completely written.
+    pub fn has_pending(&self) -> bool {
+        self.written < self.pending.len()
+    }
+
+    pub fn write_frame(&mut self, payload: &[u8]) -> Result<(), FrameError> {
+        self.flush()?;
+        self.pending = self.codec.encode(payload)?;
+        self.written = 0;
+        self.flush()
+    }
+
+    pub fn flush(&mut self) -> Result<(), FrameError> {
+        while self.written < self.pending.len() {
+            match self.inner.write(&self.pending[self.written..]) {
+                Ok(0) => {
+                    return Err(FrameError::Io(io::Error::new(
+                        io::ErrorKind::WriteZero,
+                        "connection was closed while writing frame",
+                    )));
+                }
+                Ok(n) => self.written += n,
+                Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(FrameError::Io(err)),
+            }
+        }
+        self.pending.clear();
+        self.written = 0;
+        self.inner.flush()?;
+        Ok(())
+    }
+}
+
+/// The async counterpart of `FrameReader`, for Tokio streams.
+#[cfg(feature = "async")]
+pub struct AsyncFrameReader<R> {
+    inner: R,
+    codec: Codec,
+    buf: Vec<u8>,
+}
+
+#[cfg(feature = "async")]
+impl<R: AsyncRead + Unpin> AsyncFrameReader<R> {
+    pub fn new(inner: R, codec: Codec) -> Self {
+        AsyncFrameReader {
+            inner,
+            codec,
+            buf: Vec::new(),
+        }
+    }
+
+    pub async fn read_frame(&mut self) -> Result<Vec<u8>, FrameError> {
+        let mut chunk = [0; READ_CHUNK_SIZE];
+        loop {
+            if let Some(payload) = self.codec.decode(&mut self.buf)? {
+                return Ok(payload);
+            }
+            let n = self.inner.read(&mut chunk).await?;
+            if n == 0 {
+                return Err(FrameError::Io(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "connection was closed while reading frame",
+                )));
+            }
+            self.buf.extend_from_slice(&chunk[..n]);
+        }
+    }
+}
+
+/// The async counterpart of `FrameWriter`, for Tokio streams.
+#[cfg(feature = "async")]
+pub struct AsyncFrameWriter<W> {
+    inner: W,
+    codec: Codec,
+}
+
+#[cfg(feature = "async")]
+impl<W: AsyncWrite + Unpin> AsyncFrameWriter<W> {
+    pub fn new(inner: W, codec: Codec) -> Self {
+        AsyncFrameWriter { inner, codec }
+    }
+
+    pub async fn write_frame(&mut self, payload: &[u8]) -> Result<(), FrameError> {
+        let frame = self.codec.encode(payload)?;
+        self.inner.write_all(&frame).await?;
+        self.inner.flush().await?;
+        Ok(())
+    }
+
+    /// Shuts down writing, so the peer sees the connection end.
+    pub async fn shutdown(&mut self) -> Result<(), FrameError> {
+        self.inner.shutdown().await?;
+        Ok(())
+    }
+}
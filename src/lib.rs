@@ -1,6 +1,9 @@
-pub mod buffer;
 pub mod client;
+pub mod e2e;
+pub mod frame;
 pub mod message;
 pub mod people;
 pub mod server;
 pub mod store;
+pub mod stream;
+pub mod tls;
@@ -1,30 +1,171 @@
+use std::error::Error;
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+
 use serde::{Deserialize, Serialize};
 
+use crate::e2e::PublicKey;
 use crate::people::{Group, People, User};
 
+/// The first frame of every connection, authenticating the user every message sent over the
+/// connection is from. The server replies with a `Session`, or an `Error` before closing the
+/// connection.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum Handshake {
+    /// Creates an account for a new user, and logs in as them.
+    Register(Credentials),
+    Login(Credentials),
+    /// Logs in with the token of a session started before, without the password.
+    Resume(String),
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Credentials {
+    user: User,
+    password: String,
+}
+
+impl Credentials {
+    pub fn new(user: User, password: String) -> Self {
+        Credentials { user, password }
+    }
+
+    pub fn get_user(&self) -> &User {
+        &self.user
+    }
+
+    pub fn get_password(&self) -> &str {
+        &self.password
+    }
+}
+
+// keeps passwords out of logs
+impl fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Credentials")
+            .field("user", &self.user)
+            .field("password", &"..")
+            .finish()
+    }
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum Message {
     Chat(Chat),
     Join(Join),
     Leave(Leave),
+    /// Confirms a delivered chat was received, so the server stops trying to deliver it.
+    Ack(MessageId),
+    History(History),
+    /// Ends the session of the given token, which must belong to the sender. Connections resumed
+    /// with it are sent an `Unauthenticated` error and closed, the sender's own once it's
+    /// acknowledged.
+    RevokeSession(String),
+    /// Publishes the key others encrypt direct chats to the sender with, replacing the one
+    /// published before, if any.
+    PublishKey(PublicKey),
+    /// Asks for the key the given user published. The server answers with
+    /// `ServerMessage::PublicKey`.
+    FetchKey(User),
+    /// Subscribes to the presence of a user, or of the members of a group the sender is a member
+    /// of. The server answers with `ServerMessage::Presence`, then sends it again whenever any of
+    /// them comes online, goes away, or goes offline, for as long as the connection lasts.
+    Subscribe(People),
+    /// Marks the connection away, or back. A user is away while every connection of theirs is.
+    SetAway(bool),
+    /// Tells the receiver the sender is typing a chat to them, see `Typing`.
+    Typing(Typing),
+    /// Tells the sender of a chat the sender of this message read it, see `Read`.
+    Read(Read),
+    /// Kicks, bans, unbans, promotes or demotes a member of a group, see `Moderate`.
+    Moderate(Moderate),
+}
+
+/// Assigned by the server to every chat it accepts, increasing in the order chats are queued.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize)]
+pub struct MessageId(u64);
+
+impl MessageId {
+    pub fn new(id: u64) -> Self {
+        MessageId(id)
+    }
+
+    pub fn get(self) -> u64 {
+        self.0
+    }
+}
+
+impl fmt::Display for MessageId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Milliseconds since the Unix epoch.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize)]
+pub struct Timestamp(u64);
+
+impl Timestamp {
+    pub fn new(millis: u64) -> Self {
+        Timestamp(millis)
+    }
+
+    pub fn now() -> Self {
+        let elapsed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        Timestamp(elapsed.as_millis() as u64)
+    }
+
+    pub fn get_millis(self) -> u64 {
+        self.0
+    }
 }
 
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Chat {
+    id: Option<MessageId>,
+    sent_at: Option<Timestamp>,
+    claimed_at: Option<Timestamp>,
     sender: User,
     receiver: People,
     body: String,
+    sealed_body: Option<Vec<u8>>,
 }
 
 impl Chat {
     pub fn new(sender: User, receiver: People, body: String) -> Self {
         Chat {
+            id: None,
+            sent_at: None,
+            claimed_at: None,
             sender,
             receiver,
             body,
+            sealed_body: None,
         }
     }
 
+    /// The id the server assigned to the chat, which is `None` until the chat is sent.
+    pub fn get_id(&self) -> Option<MessageId> {
+        self.id
+    }
+
+    /// When the server accepted the chat, which is `None` until the chat is sent.
+    pub fn get_sent_at(&self) -> Option<Timestamp> {
+        self.sent_at
+    }
+
+    /// When the sender claims to have written the chat. Unlike `get_sent_at`, this is taken on
+    /// the sender's word, and its clock may be off.
+    pub fn get_claimed_at(&self) -> Option<Timestamp> {
+        self.claimed_at
+    }
+
+    pub fn set_claimed_at(&mut self, claimed_at: Timestamp) {
+        self.claimed_at = Some(claimed_at);
+    }
+
     pub fn get_sender(&self) -> &User {
         &self.sender
     }
@@ -33,24 +174,52 @@ impl Chat {
         &self.receiver
     }
 
+    /// The body, which is empty while it's sealed.
     pub fn get_body(&self) -> &str {
         &self.body
     }
+
+    /// The body encrypted end to end for the receiver, which is all the server gets to see of it.
+    pub fn get_sealed_body(&self) -> Option<&[u8]> {
+        self.sealed_body.as_deref()
+    }
+
+    /// Replaces the body with its encrypted form.
+    pub fn set_sealed_body(&mut self, sealed_body: Vec<u8>) {
+        self.body = String::new();
+        self.sealed_body = Some(sealed_body);
+    }
+
+    /// Replaces the body, dropping its encrypted form, such as once it's decrypted.
+    pub fn set_body(&mut self, body: String) {
+        self.body = body;
+        self.sealed_body = None;
+    }
+
+    /// Sets the id the chat is known by from then on, which the server assigns once it accepts
+    /// the chat, overwriting any the sender set.
+    pub fn set_id(&mut self, id: MessageId) {
+        self.id = Some(id);
+    }
+
+    pub fn set_sent_at(&mut self, sent_at: Timestamp) {
+        self.sent_at = Some(sent_at);
+    }
+
+    /// Stamps the sender the server authenticated the connection as.
+    pub(crate) fn set_sender(&mut self, sender: User) {
+        self.sender = sender;
+    }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Join {
-    sender: User,
     group: Group,
 }
 
 impl Join {
-    pub fn new(sender: User, group: Group) -> Self {
-        Join { sender, group }
-    }
-
-    pub fn get_sender(&self) -> &User {
-        &self.sender
+    pub fn new(group: Group) -> Self {
+        Join { group }
     }
 
     pub fn get_group(&self) -> &Group {
@@ -60,20 +229,330 @@ impl Join {
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Leave {
-    sender: User,
     group: Group,
 }
 
 impl Leave {
-    pub fn new(sender: User, group: Group) -> Self {
-        Leave { sender, group }
+    pub fn new(group: Group) -> Self {
+        Leave { group }
     }
 
-    pub fn get_sender(&self) -> &User {
-        &self.sender
+    pub fn get_group(&self) -> &Group {
+        &self.group
+    }
+}
+
+/// Where a member stands in a group, ordered from the least to the most powerful. Whoever starts a
+/// group owns it, and the owner makes admins of other members.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize)]
+pub enum Role {
+    Member,
+    Admin,
+    Owner,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub enum Moderation {
+    /// Removes the member from the group, who may join it again.
+    Kick,
+    /// Removes the user from the group, if a member, and keeps them from joining it again.
+    Ban,
+    Unban,
+    /// Makes an admin of the member.
+    Promote,
+    /// Makes a plain member of the admin.
+    Demote,
+}
+
+/// Moderates a user of a group. The sender must be the owner or an admin of the group, and outrank
+/// the user if a member, so admins only moderate plain members, and nobody moderates the owner.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Moderate {
+    group: Group,
+    user: User,
+    moderation: Moderation,
+}
+
+impl Moderate {
+    pub fn new(group: Group, user: User, moderation: Moderation) -> Self {
+        Moderate {
+            group,
+            user,
+            moderation,
+        }
     }
 
     pub fn get_group(&self) -> &Group {
         &self.group
     }
+
+    pub fn get_user(&self) -> &User {
+        &self.user
+    }
+
+    pub fn get_moderation(&self) -> Moderation {
+        self.moderation
+    }
+}
+
+/// Lets a user log in again without their password until it expires, or is revoked.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Session {
+    token: String,
+    user: User,
+    expires_at: Timestamp,
+}
+
+impl Session {
+    pub fn new(token: String, user: User, expires_at: Timestamp) -> Self {
+        Session {
+            token,
+            user,
+            expires_at,
+        }
+    }
+
+    /// An opaque secret, which is as good as the password until the session expires.
+    pub fn get_token(&self) -> &str {
+        &self.token
+    }
+
+    pub fn get_user(&self) -> &User {
+        &self.user
+    }
+
+    pub fn get_expires_at(&self) -> Timestamp {
+        self.expires_at
+    }
+
+    pub fn is_expired(&self) -> bool {
+        Timestamp::now() >= self.expires_at
+    }
+}
+
+/// Signals the sender is typing a chat to the receiver. The server only passes it on to the
+/// receivers connected at the time, and not more often than every so often, so clients should
+/// send it again while the user keeps typing.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Typing {
+    receiver: People,
 }
+
+impl Typing {
+    pub fn new(receiver: People) -> Self {
+        Typing { receiver }
+    }
+
+    pub fn get_receiver(&self) -> &People {
+        &self.receiver
+    }
+}
+
+/// Marks a chat received as read. Its sender is sent a `Receipt`, right away or once they're
+/// back, unless the chat was marked read before.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Read {
+    message_id: MessageId,
+}
+
+impl Read {
+    pub fn new(message_id: MessageId) -> Self {
+        Read { message_id }
+    }
+
+    pub fn get_message_id(&self) -> MessageId {
+        self.message_id
+    }
+}
+
+/// Tells the sender of a chat one of its receivers read it. A chat to a group gets a receipt of
+/// every member who read it.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Receipt {
+    message_id: MessageId,
+    reader: User,
+    read_at: Timestamp,
+}
+
+impl Receipt {
+    pub fn new(message_id: MessageId, reader: User, read_at: Timestamp) -> Self {
+        Receipt {
+            message_id,
+            reader,
+            read_at,
+        }
+    }
+
+    pub fn get_message_id(&self) -> MessageId {
+        self.message_id
+    }
+
+    pub fn get_reader(&self) -> &User {
+        &self.reader
+    }
+
+    pub fn get_read_at(&self) -> Timestamp {
+        self.read_at
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Status {
+    Online,
+    Away,
+    Offline,
+}
+
+/// Whether a user is connected, as the server tells those subscribed to it.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Presence {
+    user: User,
+    status: Status,
+    last_seen: Option<Timestamp>,
+}
+
+impl Presence {
+    pub fn new(user: User, status: Status, last_seen: Option<Timestamp>) -> Self {
+        Presence {
+            user,
+            status,
+            last_seen,
+        }
+    }
+
+    pub fn get_user(&self) -> &User {
+        &self.user
+    }
+
+    pub fn get_status(&self) -> Status {
+        self.status
+    }
+
+    /// When an offline user was last connected, unless it was before the server started.
+    pub fn get_last_seen(&self) -> Option<Timestamp> {
+        self.last_seen
+    }
+}
+
+/// Asks for a page of the chats exchanged with a user, or sent to a group the sender is a member
+/// of. The server answers with `ServerMessage::History`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct History {
+    with: People,
+    bound: Option<HistoryBound>,
+    limit: u32,
+}
+
+impl History {
+    /// Asks for the latest `limit` chats, unless a bound is set. The server may return fewer
+    /// chats than asked for, even when there are more.
+    pub fn new(with: People, limit: u32) -> Self {
+        History {
+            with,
+            bound: None,
+            limit,
+        }
+    }
+
+    pub fn get_with(&self) -> &People {
+        &self.with
+    }
+
+    pub fn get_bound(&self) -> Option<HistoryBound> {
+        self.bound
+    }
+
+    pub fn get_limit(&self) -> u32 {
+        self.limit
+    }
+
+    pub fn set_bound(&mut self, bound: HistoryBound) {
+        self.bound = Some(bound);
+    }
+}
+
+/// Where a page of history starts, exclusively. A page `Before` some point holds the chats right
+/// before it, while a page `After` it holds the chats right after it.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum HistoryBound {
+    Before(MessageId),
+    After(MessageId),
+    BeforeTime(Timestamp),
+    AfterTime(Timestamp),
+}
+
+/// Everything the server sends to a client.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum ServerMessage {
+    Chat(Chat),
+    /// The message the client sent before was handled successfully, replies come in order. For a
+    /// chat, it carries the id the server assigned to it.
+    ///
+    /// A client's `Message::Ack` is the one message the server doesn't reply to.
+    Ack(Option<MessageId>),
+    /// The message the client sent before was rejected, replies come in order.
+    Error(ServerError),
+    /// Replies to a successful `Handshake`, with the session the connection belongs to.
+    Session(Session),
+    /// Replies to a `Message::History` instead of an `Ack`, with chats in the order they were
+    /// sent.
+    History(Vec<Chat>),
+    /// Replies to a `Message::FetchKey` instead of an `Ack`, with the key the user published, if
+    /// any.
+    PublicKey(User, Option<PublicKey>),
+    /// Replies to a `Message::Subscribe` instead of an `Ack`, with the presence of every user
+    /// subscribed to. Sent again with a single user whenever their status changes.
+    Presence(Vec<Presence>),
+    /// The user is typing a chat to the people, which is either a group or the client's user.
+    Typing(User, People),
+    /// Receipts of chats the client's user sent, in the order they were read.
+    Receipts(Vec<Receipt>),
+    Notice(String),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum ErrorCode {
+    /// The frame was not a valid message.
+    Malformed,
+    /// The frame was larger than the server accepts, the connection is closed afterward.
+    TooLarge,
+    NotFound,
+    /// The user to register already exists.
+    AlreadyExists,
+    /// The handshake did not authenticate a user, the connection is closed afterward.
+    Unauthenticated,
+    /// The user may not do that, such as a member moderating a group, a banned user joining it, or
+    /// anyone but its members chatting or typing to it.
+    Forbidden,
+    /// The server could not store the message, it may be sent again later.
+    Unavailable,
+    Internal,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ServerError {
+    code: ErrorCode,
+    reason: String,
+}
+
+impl ServerError {
+    pub fn new(code: ErrorCode, reason: String) -> Self {
+        ServerError { code, reason }
+    }
+
+    pub fn get_code(&self) -> ErrorCode {
+        self.code
+    }
+
+    pub fn get_reason(&self) -> &str {
+        &self.reason
+    }
+}
+
+impl fmt::Display for ServerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "ServerError ({:?}): {}", self.code, self.reason)
+    }
+}
+
+impl Error for ServerError {}
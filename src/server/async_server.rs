@@ -0,0 +1,362 @@
+use std::error::Error;
+use std::net::SocketAddr;
+use std::sync::{Arc, PoisonError};
+use std::time::Duration;
+
+use bincode;
+use rustls::ServerConfig;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::{JoinError, JoinHandle};
+use tokio_rustls::TlsAcceptor;
+
+use crate::frame::{AsyncFrameReader, AsyncFrameWriter, Codec, FrameError};
+use crate::message::{ErrorCode, MessageId, ServerError, ServerMessage, Session};
+use crate::server::notifier::{ConnectionId, Mailbox};
+use crate::server::{device_of, Connection, ServerInner};
+use crate::store::Store;
+
+/// Serves the same protocol and store semantics as `Server`, but handles every connection with
+/// Tokio tasks rather than two OS threads, so idle connections are cheap to hold.
+///
+/// `start` must be run within a multi-threaded Tokio runtime.
+pub struct AsyncServer<'a> {
+    host: &'a str,
+    port: &'a str,
+    codec: Codec,
+    tls: Option<TlsAcceptor>,
+    inner: Arc<ServerInner>,
+}
+
+// plain and TLS connections are told apart only until they're split
+type ReadHalf = Box<dyn AsyncRead + Unpin + Send>;
+type WriteHalf = Box<dyn AsyncWrite + Unpin + Send>;
+
+impl<'a> AsyncServer<'a> {
+    pub fn new(host: &'a str, port: &'a str, store: Box<dyn Store + Send>) -> Self {
+        AsyncServer {
+            host,
+            port,
+            codec: Codec::default(),
+            tls: None,
+            inner: Arc::new(ServerInner::new(store)),
+        }
+    }
+
+    pub fn set_max_frame_size(&mut self, max_frame_size: usize) {
+        self.codec = Codec::new(max_frame_size);
+    }
+
+    /// Serves every connection over TLS, see `tls::load_server_config`.
+    pub fn set_tls(&mut self, config: Arc<ServerConfig>) {
+        self.tls = Some(TlsAcceptor::from(config));
+    }
+
+    /// Sets how long sessions last, counting from the login starting them.
+    pub fn set_session_ttl(&mut self, session_ttl: Duration) {
+        // nothing else holds on to the inner server until it's started
+        Arc::get_mut(&mut self.inner).unwrap().session_ttl = session_ttl;
+    }
+
+    pub async fn start(self) -> Result<(), Box<dyn Error>> {
+        let listener = self.bind().await?;
+        self.inner.serve_async(listener, self.tls, self.codec).await;
+        Ok(())
+    }
+
+    /// Starts serving on a task of its own, returning once the server is listening. Port `0`
+    /// binds to any free port, which the handle tells.
+    pub async fn spawn(self) -> Result<AsyncServerHandle, Box<dyn Error>> {
+        let listener = self.bind().await?;
+        let address = listener.local_addr()?;
+        let serve_inner = Arc::clone(&self.inner);
+        let serve = tokio::spawn(serve_inner.serve_async(listener, self.tls, self.codec));
+        Ok(AsyncServerHandle {
+            address,
+            inner: self.inner,
+            serve,
+        })
+    }
+
+    async fn bind(&self) -> Result<TcpListener, Box<dyn Error>> {
+        let address = [self.host, self.port].join(":");
+        Ok(TcpListener::bind(address).await?)
+    }
+}
+
+/// An `AsyncServer` serving on a task of its own, which stops accepting connections once the
+/// handle is dropped.
+pub struct AsyncServerHandle {
+    address: SocketAddr,
+    inner: Arc<ServerInner>,
+    serve: JoinHandle<()>,
+}
+
+impl AsyncServerHandle {
+    /// The address the server listens on, with the actual port if bound to port `0`.
+    pub fn get_address(&self) -> SocketAddr {
+        self.address
+    }
+
+    /// Stops accepting connections, tells the clients connected, and closes their connections once
+    /// they've been sent what was pending, then flushes the store.
+    pub async fn shutdown(mut self) {
+        self.serve.abort();
+        // the listener is closed once the task is done with
+        let _ = (&mut self.serve).await;
+        let inner = Arc::clone(&self.inner);
+        // waiting for the writers blocks, as does flushing the store
+        let _ = tokio::task::spawn_blocking(move || inner.shut_down()).await;
+    }
+}
+
+impl Drop for AsyncServerHandle {
+    fn drop(&mut self) {
+        self.serve.abort();
+    }
+}
+
+impl ServerInner {
+    async fn serve_async(
+        self: Arc<Self>,
+        listener: TcpListener,
+        tls: Option<TlsAcceptor>,
+        codec: Codec,
+    ) {
+        loop {
+            let stream = match listener.accept().await {
+                Ok((stream, _)) => stream,
+                Err(err) => {
+                    // a connection reset before it was accepted is no reason to stop accepting
+                    eprintln!("{}", err);
+                    continue;
+                }
+            };
+            let inner = Arc::clone(&self);
+            let tls = tls.clone();
+            tokio::spawn(async move {
+                let (read_half, write_half) = match split_stream(stream, tls).await {
+                    Ok(halves) => halves,
+                    Err(err) => {
+                        eprintln!("{}", err);
+                        return;
+                    }
+                };
+                inner
+                    .handle_async_stream(read_half, write_half, codec)
+                    .await
+            });
+        }
+    }
+
+    async fn handle_async_stream(
+        self: Arc<Self>,
+        read_half: ReadHalf,
+        write_half: WriteHalf,
+        codec: Codec,
+    ) {
+        let mut reader = AsyncFrameReader::new(read_half, codec);
+        let mut writer = AsyncFrameWriter::new(write_half, codec);
+
+        let buf = match reader.read_frame().await {
+            Ok(buf) => buf,
+            Err(_) => return,
+        };
+        // password hashing would hold up every task on the runtime thread
+        let handshake = self.run_blocking(move |inner| {
+            let session = inner.handle_handshake(&buf)?;
+            inner.add_device(&session)?;
+            Ok(session)
+        });
+        let session = match handshake.await {
+            Ok(Ok(session)) => session,
+            Ok(Err(err)) => {
+                if let Ok(buf) = bincode::serialize(&ServerMessage::Error(err)) {
+                    let _ = writer.write_frame(&buf).await;
+                }
+                let _ = writer.shutdown().await;
+                return;
+            }
+            Err(_) => return,
+        };
+
+        let (id, mailbox) = self
+            .notifier
+            .subscribe(session.get_user(), &device_of(&session));
+        mailbox.push(ServerMessage::Session(session.clone()));
+
+        let write_inner = Arc::clone(&self);
+        let write_mailbox = Arc::clone(&mailbox);
+        let write_session = session.clone();
+        *self.writers.lock().unwrap_or_else(PoisonError::into_inner) += 1;
+        tokio::spawn(async move {
+            write_inner
+                .handle_async_write_stream(writer, id, write_mailbox, write_session)
+                .await
+        });
+
+        let connection = match self
+            .run_blocking(move |inner| Arc::new(inner.connect(session, id)))
+            .await
+        {
+            Ok(connection) => connection,
+            Err(_) => {
+                mailbox.close();
+                return;
+            }
+        };
+        self.handle_async_read_stream(reader, mailbox, &connection)
+            .await;
+        self.disconnect(&connection);
+    }
+
+    /// Runs `op` on the blocking thread pool, since the store may block on I/O, or sleep between
+    /// retries, which would hold up every task on the runtime thread. Fails if `op` panicked.
+    async fn run_blocking<T, F>(self: &Arc<Self>, op: F) -> Result<T, JoinError>
+    where
+        F: FnOnce(&ServerInner) -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let inner = Arc::clone(self);
+        tokio::task::spawn_blocking(move || op(&inner)).await
+    }
+
+    async fn handle_async_read_stream(
+        self: &Arc<Self>,
+        mut reader: AsyncFrameReader<ReadHalf>,
+        mailbox: Arc<Mailbox>,
+        connection: &Arc<Connection>,
+    ) {
+        loop {
+            match reader.read_frame().await {
+                // same as a threaded connection, closed along with its session
+                Ok(_) if mailbox.is_closed() => break,
+                Ok(buf) => {
+                    let frame_connection = Arc::clone(connection);
+                    let reply = self
+                        .run_blocking(move |inner| inner.handle_frame(&frame_connection, &buf))
+                        .await;
+                    match reply {
+                        Ok(Some(reply)) => mailbox.push(reply),
+                        Ok(None) => {}
+                        // same as a panic on a threaded connection, only this one is closed
+                        Err(_) => {
+                            let user = connection.session.get_user();
+                            eprintln!("{}: closing the connection after a panic", user);
+                            break;
+                        }
+                    }
+                    if connection.is_revoked() {
+                        break;
+                    }
+                }
+                Err(err @ FrameError::TooLarge { .. }) => {
+                    // there's no resynchronizing after a frame that wasn't read whole
+                    let err = ServerError::new(ErrorCode::TooLarge, err.to_string());
+                    mailbox.push(ServerMessage::Error(err));
+                    break;
+                }
+                Err(FrameError::Io(_)) => break,
+            }
+        }
+        mailbox.close();
+    }
+
+    async fn handle_async_write_stream(
+        self: Arc<Self>,
+        mut writer: AsyncFrameWriter<WriteHalf>,
+        id: ConnectionId,
+        mailbox: Arc<Mailbox>,
+        session: Session,
+    ) {
+        let user = session.get_user();
+        // same as the threaded writer, replies pushed right before closing are still sent
+        let mut open = true;
+        let mut in_flight = None;
+        loop {
+            let mut result = self.send_async_replies(&mut writer, &mailbox).await;
+            if result.is_ok() && open {
+                result = self
+                    .send_async_chats(&mut writer, &session, &mut in_flight)
+                    .await;
+            }
+            if let Err(err) = result {
+                eprintln!("{}: {}", user, err);
+                break;
+            }
+            if !open {
+                break;
+            }
+            open = mailbox.notified().await;
+        }
+        let _ = writer.shutdown().await;
+        self.notifier.unsubscribe(user, id);
+        self.remove_writer();
+    }
+
+    async fn send_async_replies(
+        &self,
+        writer: &mut AsyncFrameWriter<WriteHalf>,
+        mailbox: &Mailbox,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        while let Some(message) = mailbox.pop() {
+            let buf = bincode::serialize(&message)?;
+            if let Err(FrameError::Io(err)) = writer.write_frame(&buf).await {
+                return Err(Box::new(err));
+            }
+        }
+        Ok(())
+    }
+
+    /// Sends pending chats one at a time, each only once the previous one is acknowledged.
+    async fn send_async_chats(
+        self: &Arc<Self>,
+        writer: &mut AsyncFrameWriter<WriteHalf>,
+        session: &Session,
+        in_flight: &mut Option<MessageId>,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        // the store is never locked across an await, so a slow peer doesn't block other tasks
+        loop {
+            let next_session = session.clone();
+            let after = *in_flight;
+            let chat = self
+                .run_blocking(move |inner| inner.next_chat(&next_session, after))
+                .await??;
+            let chat = match chat {
+                Some(chat) => chat,
+                None => return Ok(()),
+            };
+            let id = chat.get_id();
+            let buf = bincode::serialize(&ServerMessage::Chat(chat))?;
+            match writer.write_frame(&buf).await {
+                Ok(()) => *in_flight = id,
+                // a chat too large to ever be delivered is dropped rather than retried forever
+                Err(FrameError::TooLarge { .. }) => {
+                    if let Some(id) = id {
+                        let ack_session = session.clone();
+                        self.run_blocking(move |inner| inner.ack_chat(&ack_session, id))
+                            .await??;
+                    }
+                }
+                Err(FrameError::Io(err)) => return Err(Box::new(err)),
+            }
+        }
+    }
+}
+
+/// Does the TLS handshake, if TLS is on, then splits the stream to be read and written apart.
+async fn split_stream(
+    stream: TcpStream,
+    tls: Option<TlsAcceptor>,
+) -> Result<(ReadHalf, WriteHalf), Box<dyn Error + Send + Sync>> {
+    match tls {
+        Some(acceptor) => {
+            let (read_half, write_half) = tokio::io::split(acceptor.accept(stream).await?);
+            Ok((Box::new(read_half), Box::new(write_half)))
+        }
+        None => {
+            let (read_half, write_half) = stream.into_split();
+            Ok((Box::new(read_half), Box::new(write_half)))
+        }
+    }
+}
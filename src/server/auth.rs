@@ -0,0 +1,42 @@
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use argon2::password_hash::{PasswordHash, SaltString};
+use argon2::{Argon2, PasswordHasher, PasswordVerifier};
+use ring::digest;
+
+const TOKEN_SIZE: usize = 32;
+
+/// Generates a session token, hex-encoded from random bytes.
+pub fn generate_token() -> String {
+    let mut bytes = [0; TOKEN_SIZE];
+    OsRng.fill_bytes(&mut bytes);
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Hashes a session token into what the session is stored by, so the store never holds a token
+/// good for logging in. Tokens are random enough for a fast, unsalted hash to do.
+pub fn hash_token(token: &str) -> String {
+    let hash = digest::digest(&digest::SHA256, token.as_bytes());
+    hash.as_ref()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Hashes a password with a fresh random salt, into a string holding both the hash and the
+/// parameters needed to verify it later.
+pub fn hash_password(password: &str) -> Result<String, argon2::password_hash::Error> {
+    let salt = SaltString::generate(&mut OsRng);
+    let password_hash = Argon2::default().hash_password(password.as_bytes(), &salt)?;
+    Ok(password_hash.to_string())
+}
+
+/// Whether the password matches a hash made by `hash_password`. A hash that can't be parsed
+/// matches no password.
+pub fn verify_password(password: &str, password_hash: &str) -> bool {
+    match PasswordHash::new(password_hash) {
+        Ok(password_hash) => Argon2::default()
+            .verify_password(password.as_bytes(), &password_hash)
+            .is_ok(),
+        Err(_) => false,
+    }
+}
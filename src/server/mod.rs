@@ -0,0 +1,1121 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::io;
+use std::net::{Ipv4Addr, Ipv6Addr, Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::panic::{self, AssertUnwindSafe};
+use std::str;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex, PoisonError};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use bincode;
+use rustls::ServerConfig;
+
+use crate::frame::{Codec, FrameError, FrameReader, FrameWriter};
+use crate::message::{
+    Chat, Credentials, ErrorCode, Handshake, History, Join, Leave, Message, MessageId, Moderate,
+    Moderation, Presence, Receipt, Role, ServerError, ServerMessage, Session, Timestamp, Typing,
+};
+use crate::people::{Group, People, User};
+use crate::store::{Conversation, Store, StoreError};
+use crate::stream::Stream;
+
+#[cfg(feature = "async")]
+mod async_server;
+mod auth;
+mod notifier;
+mod presence;
+
+#[cfg(feature = "async")]
+pub use self::async_server::{AsyncServer, AsyncServerHandle};
+
+use self::notifier::{ConnectionId, Mailbox, Notifier};
+use self::presence::Presences;
+
+const STORE_ATTEMPTS: u32 = 3;
+const STORE_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+/// Most chats returned for a single history request, whatever the limit asked for.
+const MAX_HISTORY_LIMIT: usize = 100;
+
+pub const DEFAULT_SESSION_TTL: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// Least time between two typing signals of a connection to the same people that are passed on.
+const TYPING_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Longest a shutdown waits for connections to send what they have left.
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(5);
+
+pub struct Server<'a> {
+    host: &'a str,
+    port: &'a str,
+    codec: Codec,
+    tls: Option<Arc<ServerConfig>>,
+    inner: Arc<ServerInner>,
+}
+
+/// A connection past its handshake.
+struct Connection {
+    session: Session,
+    id: ConnectionId,
+    // when typing signals were last passed on, by receiver
+    typed_at: Mutex<HashMap<People, Instant>>,
+    // set once the connection revokes its own session, to be closed after the reply
+    revoked: AtomicBool,
+}
+
+impl Connection {
+    fn new(session: Session, id: ConnectionId) -> Self {
+        Connection {
+            session,
+            id,
+            typed_at: Mutex::new(HashMap::new()),
+            revoked: AtomicBool::new(false),
+        }
+    }
+
+    fn is_revoked(&self) -> bool {
+        self.revoked.load(Ordering::SeqCst)
+    }
+
+    /// Whether a typing signal to `receiver` is to be passed on, which it isn't if the one before
+    /// was less than `TYPING_INTERVAL` ago.
+    fn throttle_typing(&self, receiver: &People) -> bool {
+        let now = Instant::now();
+        let mut typed_at = self.typed_at.lock().unwrap_or_else(PoisonError::into_inner);
+        typed_at.retain(|_, &mut at| now.duration_since(at) < TYPING_INTERVAL);
+        if typed_at.contains_key(receiver) {
+            return false;
+        }
+        typed_at.insert(receiver.clone(), now);
+        true
+    }
+}
+
+struct ServerInner {
+    store: Mutex<Box<dyn Store + Send>>,
+    notifier: Notifier,
+    presences: Presences,
+    session_ttl: Duration,
+    // connections still writing to their peer, which a shutdown waits for
+    writers: Mutex<usize>,
+    drained: Condvar,
+}
+
+impl<'a> Server<'a> {
+    pub fn new(host: &'a str, port: &'a str, store: Box<dyn Store + Send>) -> Self {
+        Server {
+            host,
+            port,
+            codec: Codec::default(),
+            tls: None,
+            inner: Arc::new(ServerInner::new(store)),
+        }
+    }
+
+    pub fn set_max_frame_size(&mut self, max_frame_size: usize) {
+        self.codec = Codec::new(max_frame_size);
+    }
+
+    /// Serves every connection over TLS, see `tls::load_server_config`.
+    pub fn set_tls(&mut self, config: Arc<ServerConfig>) {
+        self.tls = Some(config);
+    }
+
+    /// Sets how long sessions last, counting from the login starting them.
+    pub fn set_session_ttl(&mut self, session_ttl: Duration) {
+        // nothing else holds on to the inner server until it's started
+        Arc::get_mut(&mut self.inner).unwrap().session_ttl = session_ttl;
+    }
+
+    pub fn start(self) -> Result<(), Box<dyn Error>> {
+        let listener = self.bind()?;
+        self.inner
+            .serve(listener, self.tls, self.codec, &AtomicBool::new(false));
+        Ok(())
+    }
+
+    /// Starts serving on a thread of its own, returning once the server is listening. Port `0`
+    /// binds to any free port, which the handle tells.
+    pub fn spawn(self) -> Result<ServerHandle, Box<dyn Error>> {
+        let listener = self.bind()?;
+        let address = listener.local_addr()?;
+        let stopped = Arc::new(AtomicBool::new(false));
+        let serve_inner = Arc::clone(&self.inner);
+        let serve_stopped = Arc::clone(&stopped);
+        let (tls, codec) = (self.tls, self.codec);
+        let thread = thread::spawn(move || serve_inner.serve(listener, tls, codec, &serve_stopped));
+        Ok(ServerHandle {
+            address,
+            inner: self.inner,
+            stopped,
+            thread: Some(thread),
+        })
+    }
+
+    fn bind(&self) -> Result<TcpListener, Box<dyn Error>> {
+        let address = [self.host, self.port].join(":");
+        Ok(TcpListener::bind(address)?)
+    }
+}
+
+/// A server running on a thread of its own, which is shut down once the handle is dropped.
+pub struct ServerHandle {
+    address: SocketAddr,
+    inner: Arc<ServerInner>,
+    stopped: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl ServerHandle {
+    /// The address the server listens on, with the actual port if bound to port `0`.
+    pub fn get_address(&self) -> SocketAddr {
+        self.address
+    }
+
+    /// Stops accepting connections, tells the clients connected, and closes their connections once
+    /// they've been sent what was pending, then flushes the store.
+    pub fn shutdown(mut self) {
+        self.stop();
+    }
+
+    fn stop(&mut self) {
+        let thread = match self.thread.take() {
+            Some(thread) => thread,
+            None => return,
+        };
+        self.stopped.store(true, Ordering::SeqCst);
+        // the server only notices once it accepts a connection, so it's sent one
+        let mut address = self.address;
+        if address.ip().is_unspecified() {
+            address.set_ip(match address {
+                SocketAddr::V4(_) => Ipv4Addr::LOCALHOST.into(),
+                SocketAddr::V6(_) => Ipv6Addr::LOCALHOST.into(),
+            });
+        }
+        if TcpStream::connect(address).is_ok() {
+            let _ = thread.join();
+        }
+        self.inner.shut_down();
+    }
+}
+
+impl Drop for ServerHandle {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+impl ServerInner {
+    fn new(store: Box<dyn Store + Send>) -> Self {
+        ServerInner {
+            store: Mutex::new(store),
+            notifier: Notifier::new(),
+            presences: Presences::new(),
+            session_ttl: DEFAULT_SESSION_TTL,
+            writers: Mutex::new(0),
+            drained: Condvar::new(),
+        }
+    }
+
+    /// Closes every connection, waiting a while for them to send what they have left, then
+    /// flushes the store.
+    fn shut_down(&self) {
+        let notice = ServerMessage::Notice("server is shutting down".into());
+        self.notifier.close_all(notice);
+
+        let deadline = Instant::now() + DRAIN_TIMEOUT;
+        let mut writers = self.writers.lock().unwrap_or_else(PoisonError::into_inner);
+        while *writers > 0 {
+            let now = Instant::now();
+            if now >= deadline {
+                eprintln!("closing {} connections still writing", *writers);
+                break;
+            }
+            writers = self
+                .drained
+                .wait_timeout(writers, deadline - now)
+                .unwrap_or_else(PoisonError::into_inner)
+                .0;
+        }
+        drop(writers);
+
+        if let Err(err) = self.with_store(|store| store.flush()) {
+            eprintln!("{}", err);
+        }
+    }
+
+    /// Accepts connections until `stopped` is set, handling each on threads of its own.
+    fn serve(
+        self: Arc<Self>,
+        listener: TcpListener,
+        tls: Option<Arc<ServerConfig>>,
+        codec: Codec,
+        stopped: &AtomicBool,
+    ) {
+        for stream in listener.incoming() {
+            if stopped.load(Ordering::SeqCst) {
+                break;
+            }
+            // a connection reset before it was accepted is no reason to stop accepting others
+            let stream = match stream.and_then(|stream| Stream::accept(stream, tls.as_ref())) {
+                Ok(stream) => stream,
+                Err(err) => {
+                    eprintln!("{}", err);
+                    continue;
+                }
+            };
+            let inner = Arc::clone(&self);
+            thread::spawn(move || inner.handle_stream(stream, codec));
+        }
+    }
+
+    fn handle_stream(self: Arc<Self>, stream: Stream, codec: Codec) {
+        // the reader may already hold bytes past the handshake, so it's kept for the stream
+        let mut reader = match stream.try_clone() {
+            Ok(read_stream) => FrameReader::new(read_stream, codec),
+            Err(_) => return,
+        };
+        let mut writer = FrameWriter::new(stream, codec);
+
+        let session = match reader.read_frame() {
+            Ok(buf) => match self.handle_handshake(&buf).and_then(|session| {
+                self.add_device(&session)?;
+                Ok(session)
+            }) {
+                Ok(session) => session,
+                Err(err) => {
+                    let _ = self.write_message(&mut writer, &ServerMessage::Error(err));
+                    let _ = writer.get_ref().shutdown(Shutdown::Both);
+                    return;
+                }
+            },
+            Err(_) => return,
+        };
+
+        let (id, mailbox) = self
+            .notifier
+            .subscribe(session.get_user(), &device_of(&session));
+        mailbox.push(ServerMessage::Session(session.clone()));
+
+        let write_inner = Arc::clone(&self);
+        let write_mailbox = Arc::clone(&mailbox);
+        let write_session = session.clone();
+        *self.writers.lock().unwrap_or_else(PoisonError::into_inner) += 1;
+        thread::spawn(move || {
+            write_inner.handle_write_stream(writer, id, write_mailbox, write_session)
+        });
+
+        let connection = self.connect(session, id);
+        self.handle_read_stream(reader, mailbox, &connection);
+        self.disconnect(&connection);
+    }
+
+    /// Authenticates the user a connection is for, from the first frame read from it.
+    fn handle_handshake(&self, buf: &[u8]) -> Result<Session, ServerError> {
+        let handshake = bincode::deserialize(buf).map_err(|err| {
+            let reason = format!("expected a handshake: {}", err);
+            ServerError::new(ErrorCode::Unauthenticated, reason)
+        })?;
+        match handshake {
+            Handshake::Register(credentials) => {
+                self.register(&credentials)?;
+                self.start_session(credentials.get_user())
+            }
+            Handshake::Login(credentials) => {
+                self.login(&credentials)?;
+                self.start_session(credentials.get_user())
+            }
+            Handshake::Resume(token) => self.resume_session(&token),
+        }
+    }
+
+    fn register(&self, credentials: &Credentials) -> Result<(), ServerError> {
+        let user = credentials.get_user();
+        // hashing is slow on purpose, so it's done before locking the store
+        let password_hash = auth::hash_password(credentials.get_password())
+            .map_err(|err| ServerError::new(ErrorCode::Internal, err.to_string()))?;
+        self.with_store(|store| store.create_password_hash(user, &password_hash))
+            .map_err(|err| store_error(user, err))
+    }
+
+    fn login(&self, credentials: &Credentials) -> Result<(), ServerError> {
+        let user = credentials.get_user();
+        let password_hash = self
+            .with_store(|store| store.get_password_hash(user))
+            .map_err(|err| store_error(user, err))?;
+        match password_hash {
+            Some(ref password_hash)
+                if auth::verify_password(credentials.get_password(), password_hash) =>
+            {
+                Ok(())
+            }
+            // unknown users and wrong passwords are told apart to nobody
+            _ => Err(ServerError::new(
+                ErrorCode::Unauthenticated,
+                "wrong username or password".into(),
+            )),
+        }
+    }
+
+    /// Starts a session of `user`, which is stored by the hash of its token, while the token
+    /// itself is only ever told to the user.
+    fn start_session(&self, user: &User) -> Result<Session, ServerError> {
+        let expires_at =
+            Timestamp::new(Timestamp::now().get_millis() + self.session_ttl.as_millis() as u64);
+        let token = auth::generate_token();
+        let stored = Session::new(auth::hash_token(&token), user.clone(), expires_at);
+        self.with_store(|store| store.create_session(stored.clone()))
+            .map_err(|err| store_error(user, err))?;
+        Ok(Session::new(token, user.clone(), expires_at))
+    }
+
+    fn resume_session(&self, token: &str) -> Result<Session, ServerError> {
+        let session = self
+            .with_store(|store| store.get_session(&auth::hash_token(token)))
+            .map_err(|err| {
+                eprintln!("{}", err);
+                ServerError::new(ErrorCode::Unavailable, err.to_string())
+            })?;
+        match session {
+            Some(session) if !session.is_expired() => Ok(Session::new(
+                token.into(),
+                session.get_user().clone(),
+                session.get_expires_at(),
+            )),
+            _ => Err(ServerError::new(
+                ErrorCode::Unauthenticated,
+                "invalid or expired session".into(),
+            )),
+        }
+    }
+
+    /// Adds the device a session connects from, if it's new, to the devices of its user. A new
+    /// device starts where the furthest device of the user is, so it's not sent chats read on
+    /// another device.
+    fn add_device(&self, session: &Session) -> Result<(), ServerError> {
+        let user = session.get_user();
+        let device = device_of(session);
+        self.with_store(|store| {
+            let read_cursors = store.get_read_cursors(user)?;
+            if read_cursors.contains_key(&device) {
+                return Ok(());
+            }
+            let cursor = read_cursors.values().max().cloned().flatten();
+            store.set_read_cursor(user, &device, cursor)
+        })
+        .map_err(|err| store_error(user, err))
+    }
+
+    /// Ends a session of the user of `connection`, along with its device, and closes the
+    /// connections resumed with it, `connection` itself only once it's told so. Sessions of other
+    /// users are as good as unknown.
+    fn revoke_session(&self, connection: &Connection, token: &str) -> Result<(), StoreError> {
+        let user = connection.session.get_user();
+        let device = auth::hash_token(token);
+        self.with_store(|store| match store.get_session(&device)? {
+            Some(ref session) if session.get_user() == user => {
+                store.remove_session(&device)?;
+                store.remove_read_cursor(user, &device)?;
+                trim_chats(store, user)
+            }
+            _ => Err(StoreError::NotFound("session".into())),
+        })?;
+
+        let err = ServerError::new(ErrorCode::Unauthenticated, "session revoked".into());
+        let farewell = ServerMessage::Error(err);
+        self.notifier
+            .close_device(user, &device, connection.id, &farewell);
+        if device == device_of(&connection.session) {
+            connection.revoked.store(true, Ordering::SeqCst);
+        }
+        Ok(())
+    }
+
+    /// Marks the user of `session` present for as long as the connection lasts, and sends them
+    /// the receipts that came while they were away.
+    fn connect(&self, session: Session, id: ConnectionId) -> Connection {
+        let user = session.get_user();
+        if let Some(presence) = self.presences.connect(user, id) {
+            self.publish_presence(presence);
+        }
+        if let Err(err) = self.send_receipts(user) {
+            eprintln!("{}: {}", user, err);
+        }
+        Connection::new(session, id)
+    }
+
+    fn disconnect(&self, connection: &Connection) {
+        let user = connection.session.get_user();
+        if let Some(presence) = self.presences.disconnect(user, connection.id) {
+            self.publish_presence(presence);
+        }
+    }
+
+    fn set_away(&self, connection: &Connection, away: bool) {
+        let user = connection.session.get_user();
+        if let Some(presence) = self.presences.set_away(user, connection.id, away) {
+            self.publish_presence(presence);
+        }
+    }
+
+    fn publish_presence(&self, presence: Presence) {
+        let subscribers = self.presences.get_subscribers(presence.get_user());
+        let message = ServerMessage::Presence(vec![presence]);
+        for (subscriber, id) in subscribers.iter() {
+            self.notifier.push_to(subscriber, *id, &message);
+        }
+    }
+
+    /// Records that `user` read a chat they received, and tells its sender. Chats received by
+    /// others are as good as unknown.
+    fn read_chat(&self, user: &User, id: MessageId) -> Result<(), StoreError> {
+        let sender = self.with_store(|store| {
+            let chat = store.get_chat(id)?;
+            let received = match chat {
+                Some(ref chat) if chat.get_sender() != user => match chat.get_receiver() {
+                    People::User(receiver) => receiver == user,
+                    People::Group(group) => store.get_group_members(group)?.contains(user),
+                },
+                _ => false,
+            };
+            let sender = match chat {
+                Some(chat) if received => chat.get_sender().clone(),
+                _ => return Err(StoreError::NotFound(format!("chat {}", id))),
+            };
+            let receipt = Receipt::new(id, user.clone(), Timestamp::now());
+            let added = store.add_receipt(&sender, &receipt)?;
+            Ok(Some(sender).filter(|_| added))
+        })?;
+        if let Some(sender) = sender {
+            self.send_receipts(&sender)?;
+        }
+        Ok(())
+    }
+
+    /// Sends the receipts queued for `user` to every connection of theirs, leaving them queued if
+    /// there's none.
+    fn send_receipts(&self, user: &User) -> Result<(), StoreError> {
+        if !self.notifier.is_connected(user) {
+            return Ok(());
+        }
+        let receipts = self.with_store(|store| store.take_receipts(user))?;
+        if !receipts.is_empty() {
+            self.notifier.push(user, &ServerMessage::Receipts(receipts));
+        }
+        Ok(())
+    }
+
+    /// Passes a typing signal on to the connected receivers, without storing it anywhere.
+    fn relay_typing(&self, connection: &Connection, typing: Typing) -> Result<(), ServerError> {
+        let sender = connection.session.get_user();
+        let receivers = match typing.get_receiver() {
+            People::User(user) => vec![user.clone()],
+            People::Group(group) => self.get_group_members_for(sender, group)?,
+        };
+        if !connection.throttle_typing(typing.get_receiver()) {
+            return Ok(());
+        }
+
+        let message = ServerMessage::Typing(sender.clone(), typing.get_receiver().clone());
+        for receiver in receivers.iter() {
+            if receiver != sender {
+                self.notifier.push(receiver, &message);
+            }
+        }
+        Ok(())
+    }
+
+    /// Subscribes the connection to the presence of the people asked for. The members of a group
+    /// are only open to its members, and only those who are members by now are subscribed to.
+    fn subscribe_presence(
+        &self,
+        connection: &Connection,
+        people: &People,
+    ) -> Result<Vec<Presence>, StoreError> {
+        let user = connection.session.get_user();
+        let users = match people {
+            People::User(other) => vec![other.clone()],
+            People::Group(group) => {
+                let mut group_members = self.with_store(|store| store.get_group_members(group))?;
+                if !group_members.contains(user) {
+                    return Err(StoreError::NotFound(format!("group {}", group)));
+                }
+                group_members.retain(|member| member != user);
+                group_members
+            }
+        };
+        Ok(self.presences.subscribe(user, connection.id, &users))
+    }
+
+    /// Handles a frame read from a connection, returning the reply to send back.
+    fn handle_frame(&self, connection: &Connection, buf: &[u8]) -> Option<ServerMessage> {
+        let user = connection.session.get_user();
+        let message = match bincode::deserialize(buf) {
+            Ok(message) => message,
+            Err(err) => {
+                let err = ServerError::new(ErrorCode::Malformed, err.to_string());
+                return Some(ServerMessage::Error(err));
+            }
+        };
+        match self.handle_message(connection, message) {
+            Ok(reply) => reply,
+            Err(err) => Some(ServerMessage::Error(store_error(user, err))),
+        }
+    }
+
+    fn handle_message(
+        &self,
+        connection: &Connection,
+        message: Message,
+    ) -> Result<Option<ServerMessage>, StoreError> {
+        let user = connection.session.get_user();
+        match message {
+            Message::Chat(chat) => match self.queue_chat(user, chat) {
+                Ok(id) => Ok(Some(ServerMessage::Ack(Some(id)))),
+                Err(err) => Ok(Some(ServerMessage::Error(err))),
+            },
+            Message::Join(join) => Ok(Some(acknowledge(self.join_group(user, join)))),
+            Message::Leave(leave) => {
+                self.leave_group(user, leave)?;
+                Ok(Some(ServerMessage::Ack(None)))
+            }
+            Message::Ack(id) => {
+                self.ack_chat(&connection.session, id)?;
+                Ok(None)
+            }
+            Message::History(history) => {
+                let chats = self.get_history(user, history)?;
+                Ok(Some(ServerMessage::History(chats)))
+            }
+            Message::RevokeSession(token) => {
+                self.revoke_session(connection, &token)?;
+                Ok(Some(ServerMessage::Ack(None)))
+            }
+            Message::PublishKey(public_key) => {
+                self.with_store(|store| store.set_public_key(user, &public_key))?;
+                Ok(Some(ServerMessage::Ack(None)))
+            }
+            Message::FetchKey(owner) => {
+                let public_key = self.with_store(|store| store.get_public_key(&owner))?;
+                Ok(Some(ServerMessage::PublicKey(owner, public_key)))
+            }
+            Message::Subscribe(people) => {
+                let presences = self.subscribe_presence(connection, &people)?;
+                Ok(Some(ServerMessage::Presence(presences)))
+            }
+            Message::SetAway(away) => {
+                self.set_away(connection, away);
+                Ok(Some(ServerMessage::Ack(None)))
+            }
+            Message::Typing(typing) => Ok(Some(acknowledge(self.relay_typing(connection, typing)))),
+            Message::Read(read) => {
+                self.read_chat(user, read.get_message_id())?;
+                Ok(Some(ServerMessage::Ack(None)))
+            }
+            Message::Moderate(moderate) => Ok(Some(acknowledge(self.moderate(user, moderate)))),
+        }
+    }
+
+    /// Runs `op` on the store, retrying it a few times while the store is unavailable. The store
+    /// may have failed halfway through, so `op` must come to the same whether run once or again.
+    fn with_store<T, F>(&self, mut op: F) -> Result<T, StoreError>
+    where
+        F: FnMut(&mut dyn Store) -> Result<T, StoreError>,
+    {
+        let mut attempt = 1;
+        loop {
+            let result = {
+                // a connection that panicked midway through an operation is no reason to refuse
+                // every other connection the store
+                let mut store = self.store.lock().unwrap_or_else(PoisonError::into_inner);
+                op(store.as_mut())
+            };
+            match result {
+                Err(ref err) if err.is_unavailable() && attempt < STORE_ATTEMPTS => {
+                    thread::sleep(STORE_RETRY_DELAY * attempt);
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    fn handle_read_stream(
+        &self,
+        mut reader: FrameReader<Stream>,
+        mailbox: Arc<Mailbox>,
+        connection: &Connection,
+    ) {
+        contain_panic(connection.session.get_user(), || loop {
+            match reader.read_frame() {
+                // closed along with its session, so its frames are no longer from its user
+                Ok(_) if mailbox.is_closed() => break,
+                Ok(buf) => {
+                    if let Some(reply) = self.handle_frame(connection, &buf) {
+                        mailbox.push(reply);
+                    }
+                    if connection.is_revoked() {
+                        break;
+                    }
+                }
+                Err(err @ FrameError::TooLarge { .. }) => {
+                    // there's no resynchronizing after a frame that wasn't read whole
+                    let err = ServerError::new(ErrorCode::TooLarge, err.to_string());
+                    mailbox.push(ServerMessage::Error(err));
+                    break;
+                }
+                Err(FrameError::Io(_)) => break,
+            }
+        });
+        mailbox.close();
+    }
+
+    fn queue_chat(&self, sender: &User, mut chat: Chat) -> Result<MessageId, ServerError> {
+        // whatever sender the peer claims, the chat is from the user it connected as
+        chat.set_sender(sender.clone());
+        match chat.get_receiver() {
+            People::User(user) => self
+                .queue_sole_chat(&user.clone(), chat)
+                .map_err(|err| store_error(sender, err)),
+            People::Group(group) => {
+                let group = group.clone();
+                self.get_group_members_for(sender, &group)?;
+                self.queue_group_chat(&group, chat)
+                    .map_err(|err| store_error(sender, err))
+            }
+        }
+    }
+
+    /// Finds the members of a group for `sender` to send to, who must be one of them, and not
+    /// banned from it. A group nobody is a member of isn't found.
+    fn get_group_members_for(
+        &self,
+        sender: &User,
+        group: &Group,
+    ) -> Result<Vec<User>, ServerError> {
+        let group_members = self
+            .with_store(|store| {
+                if store.is_banned(sender, group)? {
+                    return Ok(None);
+                }
+                let group_members = store.get_group_members(group)?;
+                if group_members.is_empty() {
+                    return Err(StoreError::NotFound(format!("group {}", group)));
+                }
+                Ok(Some(group_members).filter(|members| members.contains(sender)))
+            })
+            .map_err(|err| store_error(sender, err))?;
+        group_members.ok_or_else(|| {
+            let reason = format!("not a member of group {}", group);
+            ServerError::new(ErrorCode::Forbidden, reason)
+        })
+    }
+
+    /// Adds `sender` to a group they're not banned from, as its owner if they start it.
+    fn join_group(&self, sender: &User, join: Join) -> Result<(), ServerError> {
+        let group = join.get_group();
+        let banned = self
+            .with_store(|store| {
+                if store.is_banned(sender, group)? {
+                    return Ok(true);
+                }
+                // a retry finds the user joined, but still alone
+                let group_members = store.get_group_members(group)?;
+                let starts = group_members.iter().all(|member| member == sender);
+                store.add_group_member(sender.clone(), group)?;
+                if starts {
+                    store.set_group_role(sender, group, Role::Owner)?;
+                }
+                Ok(false)
+            })
+            .map_err(|err| store_error(sender, err))?;
+        if banned {
+            let reason = format!("banned from group {}", group);
+            return Err(ServerError::new(ErrorCode::Forbidden, reason));
+        }
+        Ok(())
+    }
+
+    /// Removes `sender` from a group. An owner leaving hands the group down, see `pass_ownership`.
+    fn leave_group(&self, sender: &User, leave: Leave) -> Result<(), StoreError> {
+        let group = leave.get_group();
+        self.with_store(|store| {
+            store.remove_group_member(sender, group)?;
+            pass_ownership(store, group)
+        })
+    }
+
+    /// Carries out a moderation of `sender`, who must be a member of the group outranking the user
+    /// moderated, if a member, and at least an admin. Users kicked or banned are told so.
+    fn moderate(&self, sender: &User, moderate: Moderate) -> Result<(), ServerError> {
+        let group = moderate.get_group();
+        let user = moderate.get_user();
+        let moderation = moderate.get_moderation();
+        let mut removed = false;
+        let allowed = self
+            .with_store(|store| {
+                let sender_role = match store.get_group_role(sender, group)? {
+                    Some(role) => role,
+                    None => return Err(StoreError::NotFound(format!("group {}", group))),
+                };
+                let role = store.get_group_role(user, group)?;
+                if sender_role < Role::Admin || role.is_some_and(|role| role >= sender_role) {
+                    return Ok(false);
+                }
+                let member_not_found =
+                    || StoreError::NotFound(format!("member {} of group {}", user, group));
+                match moderation {
+                    Moderation::Kick => {
+                        role.ok_or_else(member_not_found)?;
+                        store.remove_group_member(user, group)?;
+                    }
+                    Moderation::Ban => {
+                        store.set_banned(user, group, true)?;
+                        if role.is_some() {
+                            store.remove_group_member(user, group)?;
+                        }
+                    }
+                    Moderation::Unban => store.set_banned(user, group, false)?,
+                    Moderation::Promote | Moderation::Demote => {
+                        role.ok_or_else(member_not_found)?;
+                        let role = match moderation {
+                            Moderation::Promote => Role::Admin,
+                            _ => Role::Member,
+                        };
+                        store.set_group_role(user, group, role)?;
+                    }
+                }
+                removed =
+                    role.is_some() && [Moderation::Kick, Moderation::Ban].contains(&moderation);
+                Ok(true)
+            })
+            .map_err(|err| store_error(sender, err))?;
+        if !allowed {
+            let reason = format!("not allowed to moderate {} in group {}", user, group);
+            return Err(ServerError::new(ErrorCode::Forbidden, reason));
+        }
+        if removed {
+            let notice = format!("removed from group {} by {}", group, sender);
+            self.notifier.push(user, &ServerMessage::Notice(notice));
+        }
+        Ok(())
+    }
+
+    /// Gives the chat an id and sends it. The id is handed out along with the first attempt, so
+    /// chats are queued in id order, and kept for the retries, which find the chat already sent
+    /// rather than send it again.
+    fn send_chat(&self, chat: &mut Chat) -> Result<MessageId, StoreError> {
+        let mut handed_out = None;
+        self.with_store(|store| {
+            let id = match handed_out {
+                Some(id) => id,
+                None => {
+                    let id = store.next_message_id()?;
+                    chat.set_id(id);
+                    chat.set_sent_at(Timestamp::now());
+                    handed_out = Some(id);
+                    id
+                }
+            };
+            store.send_chat(chat)?;
+            Ok(id)
+        })
+    }
+
+    fn queue_sole_chat(&self, user: &User, mut chat: Chat) -> Result<MessageId, StoreError> {
+        let id = self.send_chat(&mut chat)?;
+        self.notifier.notify(user);
+        Ok(id)
+    }
+
+    fn queue_group_chat(&self, group: &Group, mut chat: Chat) -> Result<MessageId, StoreError> {
+        let id = self.send_chat(&mut chat)?;
+        let group_members = self.with_store(|store| store.get_group_members(group))?;
+        for member in group_members.iter() {
+            if member != chat.get_sender() {
+                self.notifier.notify(member);
+            }
+        }
+        Ok(id)
+    }
+
+    /// Finds a page of the history of `user` with the people asked for. The history of a group is
+    /// only open to its members.
+    fn get_history(&self, user: &User, history: History) -> Result<Vec<Chat>, StoreError> {
+        let conversation = Conversation::new(user, history.get_with());
+        let limit = (history.get_limit() as usize).min(MAX_HISTORY_LIMIT);
+        self.with_store(|store| {
+            if let Conversation::Group(ref group) = conversation {
+                if !store.get_group_members(group)?.contains(user) {
+                    return Err(StoreError::NotFound(format!("group {}", group)));
+                }
+            }
+            store.get_history(&conversation, history.get_bound(), limit)
+        })
+    }
+
+    /// Moves the read cursor of the device of `session` past the chat it was sent last, once
+    /// that chat is acknowledged, so the next one can be delivered. Acknowledgements of any other
+    /// chat, such as duplicates, are ignored.
+    fn ack_chat(&self, session: &Session, id: MessageId) -> Result<(), StoreError> {
+        let user = session.get_user();
+        let device = device_of(session);
+        let acked = self.with_store(|store| {
+            let cursor = read_cursor(store, user, &device)?;
+            match chat_after(store, user, cursor)? {
+                Some(ref chat) if chat.get_id() == Some(id) => {
+                    store.set_read_cursor(user, &device, Some(id))?;
+                    trim_chats(store, user)?;
+                    Ok(true)
+                }
+                _ => Ok(false),
+            }
+        })?;
+        if acked {
+            self.notifier.notify(user);
+        }
+        Ok(())
+    }
+
+    /// Finds the chat to deliver next to the device of `session`, which is `None` while the chat
+    /// after its read cursor is still `in_flight`, that is sent but not acknowledged yet.
+    fn next_chat(
+        &self,
+        session: &Session,
+        in_flight: Option<MessageId>,
+    ) -> Result<Option<Chat>, StoreError> {
+        let user = session.get_user();
+        let device = device_of(session);
+        loop {
+            let chat = self.with_store(|store| {
+                let cursor = read_cursor(store, user, &device)?;
+                chat_after(store, user, cursor)
+            });
+            match chat {
+                Ok(Some(chat)) => {
+                    if in_flight.is_some() && chat.get_id() == in_flight {
+                        return Ok(None);
+                    }
+                    return Ok(Some(chat));
+                }
+                Ok(None) => return Ok(None),
+                Err(StoreError::Serialization(err)) => {
+                    // a corrupt chat would otherwise block every chat queued after it
+                    eprintln!("{}: dropping undeliverable chat: {}", user, err);
+                    self.with_store(|store| {
+                        let cursor = read_cursor(store, user, &device)?;
+                        match store.drop_chat(user, cursor) {
+                            // dropped already by an attempt that failed to tell so
+                            Err(StoreError::NotFound(_)) => Ok(()),
+                            result => result,
+                        }
+                    })?;
+                }
+                Err(err) => return Err(err),
+            }
+        }
+    }
+}
+
+impl ServerInner {
+    fn handle_write_stream(
+        &self,
+        mut writer: FrameWriter<Stream>,
+        id: ConnectionId,
+        mailbox: Arc<Mailbox>,
+        session: Session,
+    ) {
+        let user = session.get_user();
+        // chats queued while the user was away are sent before waiting for the first wakeup, and
+        // replies pushed right before the mailbox closed are still sent afterward
+        let mut open = true;
+        let mut in_flight = None;
+        contain_panic(user, || loop {
+            let result = self.send_replies(&mut writer, &mailbox).and_then(|()| {
+                if open {
+                    self.send_chats(&mut writer, &session, &mut in_flight)
+                } else {
+                    Ok(())
+                }
+            });
+            if let Err(err) = result {
+                eprintln!("{}: {}", user, err);
+                break;
+            }
+            if !open {
+                break;
+            }
+            open = mailbox.wait();
+        });
+        let _ = writer.get_ref().shutdown(Shutdown::Both);
+        self.notifier.unsubscribe(user, id);
+        self.remove_writer();
+    }
+
+    /// Counts a connection out of those `shut_down` waits for to finish writing.
+    fn remove_writer(&self) {
+        let mut writers = self.writers.lock().unwrap_or_else(PoisonError::into_inner);
+        *writers -= 1;
+        if *writers == 0 {
+            self.drained.notify_all();
+        }
+    }
+
+    fn send_replies(
+        &self,
+        writer: &mut FrameWriter<Stream>,
+        mailbox: &Mailbox,
+    ) -> Result<(), Box<dyn Error>> {
+        while let Some(message) = mailbox.pop() {
+            if let Err(FrameError::Io(err)) = self.write_message(writer, &message) {
+                return Err(Box::new(err));
+            }
+        }
+        Ok(())
+    }
+
+    /// Sends pending chats one at a time, each only once the previous one is acknowledged.
+    fn send_chats(
+        &self,
+        writer: &mut FrameWriter<Stream>,
+        session: &Session,
+        in_flight: &mut Option<MessageId>,
+    ) -> Result<(), Box<dyn Error>> {
+        while let Some(chat) = self.next_chat(session, *in_flight)? {
+            let id = chat.get_id();
+            match self.write_message(writer, &ServerMessage::Chat(chat)) {
+                Ok(()) => *in_flight = id,
+                // a chat too large to ever be delivered is dropped rather than retried forever
+                Err(FrameError::TooLarge { .. }) => {
+                    if let Some(id) = id {
+                        self.ack_chat(session, id)?;
+                    }
+                }
+                Err(FrameError::Io(err)) => return Err(Box::new(err)),
+            }
+        }
+        Ok(())
+    }
+
+    fn write_message(
+        &self,
+        writer: &mut FrameWriter<Stream>,
+        message: &ServerMessage,
+    ) -> Result<(), FrameError> {
+        let message = bincode::serialize(message).map_err(io::Error::other)?;
+        writer.write_frame(&message)
+    }
+}
+
+/// The device `session` is from, named by the hash of its token like the session in the store.
+fn device_of(session: &Session) -> String {
+    auth::hash_token(session.get_token())
+}
+
+/// The id of the last chat the device of `user` acknowledged, if any.
+fn read_cursor(
+    store: &mut dyn Store,
+    user: &User,
+    device: &str,
+) -> Result<Option<MessageId>, StoreError> {
+    Ok(store.get_read_cursors(user)?.remove(device).flatten())
+}
+
+/// Finds the first pending chat of `user` past `cursor`.
+fn chat_after(
+    store: &mut dyn Store,
+    user: &User,
+    cursor: Option<MessageId>,
+) -> Result<Option<Chat>, StoreError> {
+    match cursor {
+        Some(cursor) => store.next_chat_after(user, cursor),
+        None => store.front_chat(user),
+    }
+}
+
+/// Removes the pending chats of `user` that every device has read. Devices whose session is gone
+/// are forgotten along the way, rather than holding chats back forever.
+fn trim_chats(store: &mut dyn Store, user: &User) -> Result<(), StoreError> {
+    // a device that has read nothing is before every chat, as None is before every id
+    let mut read: Option<Option<MessageId>> = None;
+    for (device, cursor) in store.get_read_cursors(user)? {
+        match store.get_session(&device)? {
+            Some(ref session) if !session.is_expired() => {}
+            _ => {
+                store.remove_read_cursor(user, &device)?;
+                continue;
+            }
+        }
+        read = Some(read.map_or(cursor, |read| read.min(cursor)));
+    }
+    let read = match read {
+        Some(Some(read)) => read,
+        _ => return Ok(()),
+    };
+    while let Some(chat) = store.front_chat(user)? {
+        if chat.get_id() > Some(read) {
+            break;
+        }
+        store.dequeue_chat(user)?;
+    }
+    Ok(())
+}
+
+/// Hands a group without an owner down to one of its admins, or to one of its plain members if it
+/// has no admin, whichever comes first by username.
+fn pass_ownership(store: &mut dyn Store, group: &Group) -> Result<(), StoreError> {
+    let mut heir: Option<(Role, User)> = None;
+    for member in store.get_group_members(group)? {
+        let role = store
+            .get_group_role(&member, group)?
+            .unwrap_or(Role::Member);
+        if role == Role::Owner {
+            return Ok(());
+        }
+        let better = match heir {
+            Some((heir_role, ref heir)) => {
+                (role, heir.get_username()) > (heir_role, member.get_username())
+            }
+            None => true,
+        };
+        if better {
+            heir = Some((role, member));
+        }
+    }
+    match heir {
+        Some((_, heir)) => store.set_group_role(&heir, group, Role::Owner),
+        None => Ok(()),
+    }
+}
+
+/// The reply to a message handled by `result`.
+fn acknowledge(result: Result<(), ServerError>) -> ServerMessage {
+    match result {
+        Ok(()) => ServerMessage::Ack(None),
+        Err(err) => ServerMessage::Error(err),
+    }
+}
+
+/// Runs `op`, which handles a connection of `user`, so that a panic only closes that connection,
+/// like any other failure would.
+fn contain_panic<F: FnOnce()>(user: &User, op: F) {
+    // nothing `op` borrows is used after it panicked, other than to close the connection
+    if panic::catch_unwind(AssertUnwindSafe(op)).is_err() {
+        eprintln!("{}: closing the connection after a panic", user);
+    }
+}
+
+/// Logs a store error that came up handling a message of `user`, and turns it into the error to
+/// reply with.
+fn store_error(user: &User, err: StoreError) -> ServerError {
+    eprintln!("{}: {}", user, err);
+    let code = match err {
+        StoreError::Unavailable(_) => ErrorCode::Unavailable,
+        StoreError::Serialization(_) => ErrorCode::Internal,
+        StoreError::NotFound(_) => ErrorCode::NotFound,
+        StoreError::AlreadyExists(_) => ErrorCode::AlreadyExists,
+    };
+    ServerError::new(code, err.to_string())
+}
@@ -0,0 +1,259 @@
+use std::collections::{vec_deque::VecDeque, HashMap};
+#[cfg(feature = "async")]
+use std::future::Future;
+#[cfg(feature = "async")]
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex, PoisonError};
+use std::task::Waker;
+#[cfg(feature = "async")]
+use std::task::{Context, Poll};
+
+use crate::message::ServerMessage;
+use crate::people::User;
+
+/// Tells apart the connections of a user, which each go away and come back on their own.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub struct ConnectionId(u64);
+
+/// Keeps track of the connections of every user, so they can be woken up once there is something
+/// new to deliver to them.
+#[derive(Default)]
+pub struct Notifier {
+    mailboxes: Mutex<HashMap<User, HashMap<ConnectionId, Subscription>>>,
+    next_id: AtomicU64,
+    // once set, connections are closed as soon as they subscribe
+    closed: AtomicBool,
+}
+
+impl Notifier {
+    pub fn new() -> Self {
+        Notifier::default()
+    }
+
+    /// Adds a connection of `user` from `device`, returning its id along with its mailbox.
+    pub fn subscribe(&self, user: &User, device: &str) -> (ConnectionId, Arc<Mailbox>) {
+        let id = ConnectionId(self.next_id.fetch_add(1, Ordering::SeqCst));
+        let mailbox = Arc::new(Mailbox::new());
+        let mut mailboxes = self
+            .mailboxes
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        if self.closed.load(Ordering::SeqCst) {
+            mailbox.close();
+        }
+        let subscription = Subscription {
+            device: device.into(),
+            mailbox: Arc::clone(&mailbox),
+        };
+        mailboxes
+            .entry(user.clone())
+            .or_default()
+            .insert(id, subscription);
+        (id, mailbox)
+    }
+
+    pub fn unsubscribe(&self, user: &User, id: ConnectionId) {
+        let mut mailboxes = self
+            .mailboxes
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        if let Some(user_mailboxes) = mailboxes.get_mut(user) {
+            user_mailboxes.remove(&id);
+            if user_mailboxes.is_empty() {
+                mailboxes.remove(user);
+            }
+        }
+    }
+
+    /// Closes the mailbox of every connection, so they all stop once they've sent `farewell`.
+    pub fn close_all(&self, farewell: ServerMessage) {
+        let mailboxes = self
+            .mailboxes
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        self.closed.store(true, Ordering::SeqCst);
+        for subscription in mailboxes.values().flat_map(HashMap::values) {
+            subscription.mailbox.push(farewell.clone());
+            subscription.mailbox.close();
+        }
+    }
+
+    /// Closes the mailbox of every connection of `user` from `device` but `except`, so they stop
+    /// once they've sent `farewell`.
+    pub fn close_device(
+        &self,
+        user: &User,
+        device: &str,
+        except: ConnectionId,
+        farewell: &ServerMessage,
+    ) {
+        let mailboxes = self
+            .mailboxes
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        if let Some(user_mailboxes) = mailboxes.get(user) {
+            for (id, subscription) in user_mailboxes.iter() {
+                if *id != except && subscription.device == device {
+                    subscription.mailbox.push(farewell.clone());
+                    subscription.mailbox.close();
+                }
+            }
+        }
+    }
+
+    pub fn is_connected(&self, user: &User) -> bool {
+        let mailboxes = self
+            .mailboxes
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        mailboxes.contains_key(user)
+    }
+
+    /// Pushes `message` into the mailbox of every connection of `user`.
+    pub fn push(&self, user: &User, message: &ServerMessage) {
+        let mailboxes = self
+            .mailboxes
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        if let Some(user_mailboxes) = mailboxes.get(user) {
+            for subscription in user_mailboxes.values() {
+                subscription.mailbox.push(message.clone());
+            }
+        }
+    }
+
+    /// Pushes `message` into the mailbox of the connection `id` of `user` alone, if it's still
+    /// there.
+    pub fn push_to(&self, user: &User, id: ConnectionId, message: &ServerMessage) {
+        let mailboxes = self
+            .mailboxes
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        if let Some(subscription) = mailboxes.get(user).and_then(|mailboxes| mailboxes.get(&id)) {
+            subscription.mailbox.push(message.clone());
+        }
+    }
+
+    pub fn notify(&self, user: &User) {
+        let mailboxes = self
+            .mailboxes
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        if let Some(user_mailboxes) = mailboxes.get(user) {
+            for subscription in user_mailboxes.values() {
+                subscription.mailbox.notify();
+            }
+        }
+    }
+}
+
+struct Subscription {
+    // the hash of the session token the connection logged in with
+    device: String,
+    mailbox: Arc<Mailbox>,
+}
+
+#[derive(Default)]
+struct MailboxState {
+    messages: VecDeque<ServerMessage>,
+    notified: bool,
+    closed: bool,
+    waker: Option<Waker>,
+}
+
+/// Wakes up the writer of a single connection, either to deliver chats from the store or to send
+/// the messages pushed into it. A wakeup stays pending until the writer waits for it.
+///
+/// Threads block on it with `wait`, while tasks of the async server await `notified` instead.
+#[derive(Default)]
+pub struct Mailbox {
+    state: Mutex<MailboxState>,
+    condvar: Condvar,
+}
+
+impl Mailbox {
+    pub fn new() -> Self {
+        Mailbox::default()
+    }
+
+    pub fn notify(&self) {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        state.notified = true;
+        self.wake(&mut state);
+    }
+
+    pub fn push(&self, message: ServerMessage) {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        state.messages.push_back(message);
+        state.notified = true;
+        self.wake(&mut state);
+    }
+
+    pub fn pop(&self) -> Option<ServerMessage> {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        state.messages.pop_front()
+    }
+
+    /// Stops the writer once it has sent the messages already pushed.
+    pub fn close(&self) {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        state.closed = true;
+        self.wake(&mut state);
+    }
+
+    pub fn is_closed(&self) -> bool {
+        let state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        state.closed
+    }
+
+    fn wake(&self, state: &mut MailboxState) {
+        self.condvar.notify_all();
+        if let Some(waker) = state.waker.take() {
+            waker.wake();
+        }
+    }
+
+    /// Blocks until notified, returning `false` instead once the mailbox has been closed.
+    pub fn wait(&self) -> bool {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        while !state.notified && !state.closed {
+            state = self
+                .condvar
+                .wait(state)
+                .unwrap_or_else(PoisonError::into_inner);
+        }
+        state.notified = false;
+        !state.closed
+    }
+
+    /// Resolves once notified, to `false` instead once the mailbox has been closed.
+    #[cfg(feature = "async")]
+    pub fn notified(&self) -> Notified<'_> {
+        Notified { mailbox: self }
+    }
+}
+
+#[cfg(feature = "async")]
+pub struct Notified<'a> {
+    mailbox: &'a Mailbox,
+}
+
+#[cfg(feature = "async")]
+impl<'a> Future for Notified<'a> {
+    type Output = bool;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<bool> {
+        let mut state = self
+            .mailbox
+            .state
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        if !state.notified && !state.closed {
+            state.waker = Some(cx.waker().clone());
+            return Poll::Pending;
+        }
+        state.notified = false;
+        Poll::Ready(!state.closed)
+    }
+}
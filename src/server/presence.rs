@@ -0,0 +1,136 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::{Mutex, PoisonError};
+
+use super::notifier::ConnectionId;
+use crate::message::{Presence, Status, Timestamp};
+use crate::people::User;
+
+#[derive(Default)]
+struct PresenceState {
+    // whether each connection of a user is away
+    connections: HashMap<User, HashMap<ConnectionId, bool>>,
+    last_seen: HashMap<User, Timestamp>,
+    // the connections subscribed to each user, along with whose they are
+    subscribers: HashMap<User, HashMap<ConnectionId, User>>,
+    // the users each connection is subscribed to, to be unsubscribed from once it's gone
+    subscriptions: HashMap<ConnectionId, HashSet<User>>,
+}
+
+/// Keeps track of the presence of every user from the lifecycle of their connections, along with
+/// the connections subscribed to it, which are unsubscribed once they're gone. Nothing of it
+/// outlives the server.
+///
+/// Every change returns the presence it brought about, if the status of the user changed, for the
+/// server to pass on to the subscribers.
+#[derive(Default)]
+pub struct Presences {
+    state: Mutex<PresenceState>,
+}
+
+impl Presences {
+    pub fn new() -> Self {
+        Presences::default()
+    }
+
+    pub fn connect(&self, user: &User, id: ConnectionId) -> Option<Presence> {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        state.change(user, |connections| {
+            connections.insert(id, false);
+        })
+    }
+
+    pub fn set_away(&self, user: &User, id: ConnectionId, away: bool) -> Option<Presence> {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        state.change(user, |connections| {
+            if let Some(connection) = connections.get_mut(&id) {
+                *connection = away;
+            }
+        })
+    }
+
+    pub fn disconnect(&self, user: &User, id: ConnectionId) -> Option<Presence> {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        let change = state.change(user, |connections| {
+            connections.remove(&id);
+        });
+        for other in state.subscriptions.remove(&id).unwrap_or_default() {
+            if let Some(subscribers) = state.subscribers.get_mut(&other) {
+                subscribers.remove(&id);
+                if subscribers.is_empty() {
+                    state.subscribers.remove(&other);
+                }
+            }
+        }
+        if !state.connections.contains_key(user) {
+            state.last_seen.insert(user.clone(), Timestamp::now());
+        }
+        change.map(|_| state.presence(user))
+    }
+
+    /// Subscribes the connection `id` of `subscriber` to the presence of `users`, until it
+    /// disconnects, returning where they are at.
+    pub fn subscribe(&self, subscriber: &User, id: ConnectionId, users: &[User]) -> Vec<Presence> {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        for user in users.iter() {
+            state
+                .subscribers
+                .entry(user.clone())
+                .or_default()
+                .insert(id, subscriber.clone());
+            state
+                .subscriptions
+                .entry(id)
+                .or_default()
+                .insert(user.clone());
+        }
+        users.iter().map(|user| state.presence(user)).collect()
+    }
+
+    /// The connections subscribed to `user`, along with whose they are.
+    pub fn get_subscribers(&self, user: &User) -> Vec<(User, ConnectionId)> {
+        let state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        match state.subscribers.get(user) {
+            Some(subscribers) => subscribers
+                .iter()
+                .map(|(&id, subscriber)| (subscriber.clone(), id))
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+}
+
+impl PresenceState {
+    fn status(&self, user: &User) -> Status {
+        match self.connections.get(user) {
+            None => Status::Offline,
+            Some(connections) if connections.values().all(|&away| away) => Status::Away,
+            Some(_) => Status::Online,
+        }
+    }
+
+    fn presence(&self, user: &User) -> Presence {
+        let status = self.status(user);
+        let last_seen = match status {
+            Status::Offline => self.last_seen.get(user).cloned(),
+            _ => None,
+        };
+        Presence::new(user.clone(), status, last_seen)
+    }
+
+    /// Runs `op` on the connections of `user`, returning their presence if it changed.
+    fn change<F>(&mut self, user: &User, op: F) -> Option<Presence>
+    where
+        F: FnOnce(&mut HashMap<ConnectionId, bool>),
+    {
+        let before = self.status(user);
+        let connections = self.connections.entry(user.clone()).or_default();
+        op(connections);
+        if connections.is_empty() {
+            self.connections.remove(user);
+        }
+        if self.status(user) == before {
+            return None;
+        }
+        Some(self.presence(user))
+    }
+}
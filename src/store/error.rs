@@ -0,0 +1,41 @@
+use std::error::Error;
+use std::fmt;
+
+#[derive(Debug)]
+pub enum StoreError {
+    /// The backend could not be reached, the operation may succeed if retried later.
+    Unavailable(String),
+    /// A value could not be converted to or from its stored form.
+    Serialization(String),
+    NotFound(String),
+    AlreadyExists(String),
+}
+
+impl StoreError {
+    pub fn is_unavailable(&self) -> bool {
+        matches!(self, StoreError::Unavailable(_))
+    }
+}
+
+impl fmt::Display for StoreError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StoreError::Unavailable(message) => write!(f, "StoreError: unavailable: {}", message),
+            StoreError::Serialization(message) => {
+                write!(f, "StoreError: serialization failed: {}", message)
+            }
+            StoreError::NotFound(message) => write!(f, "StoreError: not found: {}", message),
+            StoreError::AlreadyExists(message) => {
+                write!(f, "StoreError: already exists: {}", message)
+            }
+        }
+    }
+}
+
+impl Error for StoreError {}
+
+impl From<bincode::Error> for StoreError {
+    fn from(err: bincode::Error) -> Self {
+        StoreError::Serialization(err.to_string())
+    }
+}
@@ -0,0 +1,609 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, prelude::*, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use bincode;
+use serde::{Deserialize, Serialize};
+
+use crate::e2e::PublicKey;
+use crate::message::{Chat, HistoryBound, MessageId, Receipt, Role, Session};
+use crate::people::{Group, User};
+use crate::store::memory::MemoryStoreV1;
+use crate::store::{Conversation, MemoryStore, Store, StoreError};
+
+const LOG_FILE: &str = "log";
+const SNAPSHOT_FILE: &str = "snapshot";
+const SNAPSHOT_TMP_FILE: &str = "snapshot.tmp";
+
+// starts a snapshot, ahead of the version of its format
+const SNAPSHOT_MAGIC: &[u8] = b"conversn";
+/// The version of the format snapshots are written in, which goes up whenever the state they hold
+/// changes shape.
+const SNAPSHOT_VERSION: u32 = 2;
+
+// a length and a checksum
+const RECORD_HEADER_SIZE: usize = 8;
+
+pub const DEFAULT_COMPACTION_THRESHOLD: u64 = 10_000;
+
+/// When appended operations are flushed to disk. Operations written but not yet flushed survive
+/// the server crashing, but not the machine.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FsyncPolicy {
+    /// After every operation, before it's acknowledged.
+    Always,
+    /// Along with the first operation appended once the interval elapsed since the last flush.
+    Interval(Duration),
+    /// Whenever the operating system sees fit.
+    Never,
+}
+
+/// Every operation changing the store, as appended to the log. Records name operations by their
+/// index, so new ones only ever go at the end, for older logs to replay as they were written.
+#[derive(Serialize, Deserialize)]
+enum Operation {
+    NextMessageId,
+    QueueChat(User, Chat),
+    DequeueChat(User),
+    QueueGroupChat(Group, Chat),
+    ArchiveChat(Chat),
+    CreatePasswordHash(User, String),
+    CreateSession(Session),
+    RemoveSession(String),
+    SetPublicKey(User, PublicKey),
+    AddGroupMember(User, Group),
+    RemoveGroupMember(User, Group),
+    SendChat(Chat),
+    SetReadCursor(User, String, Option<MessageId>),
+    RemoveReadCursor(User, String),
+    DropChat(User, Option<MessageId>),
+    AddReceipt(User, Receipt),
+    TakeReceipts(User),
+    SetGroupRole(User, Group, Role),
+    SetBanned(User, Group, bool),
+}
+
+impl Operation {
+    fn apply(self, state: &mut MemoryStore) -> Result<(), StoreError> {
+        match self {
+            Operation::NextMessageId => state.next_message_id().map(|_| ()),
+            Operation::QueueChat(user, chat) => state.queue_chat(&user, chat),
+            Operation::DequeueChat(user) => state.dequeue_chat(&user),
+            Operation::QueueGroupChat(group, chat) => state.queue_group_chat(&group, chat),
+            Operation::ArchiveChat(chat) => state.archive_chat(&chat),
+            Operation::CreatePasswordHash(user, password_hash) => {
+                state.create_password_hash(&user, &password_hash)
+            }
+            Operation::CreateSession(session) => state.create_session(session),
+            Operation::RemoveSession(token) => state.remove_session(&token),
+            Operation::SetPublicKey(user, public_key) => state.set_public_key(&user, &public_key),
+            Operation::AddGroupMember(user, group) => state.add_group_member(user, &group),
+            Operation::RemoveGroupMember(user, group) => state.remove_group_member(&user, &group),
+            Operation::SendChat(chat) => state.send_chat(&chat),
+            Operation::SetReadCursor(user, device, cursor) => {
+                state.set_read_cursor(&user, &device, cursor)
+            }
+            Operation::RemoveReadCursor(user, device) => state.remove_read_cursor(&user, &device),
+            Operation::DropChat(user, after) => state.drop_chat(&user, after),
+            Operation::AddReceipt(sender, receipt) => {
+                state.add_receipt(&sender, &receipt).map(|_| ())
+            }
+            Operation::TakeReceipts(sender) => state.take_receipts(&sender).map(|_| ()),
+            Operation::SetGroupRole(user, group, role) => state.set_group_role(&user, &group, role),
+            Operation::SetBanned(user, group, banned) => state.set_banned(&user, &group, banned),
+        }
+    }
+}
+
+/// The state as of the operation of sequence number `seq`, which operations up to it need not
+/// be replayed onto.
+#[derive(Serialize, Deserialize)]
+struct Snapshot {
+    seq: u64,
+    state: MemoryStore,
+}
+
+/// A snapshot of version 1, from before read cursors.
+#[derive(Deserialize)]
+struct SnapshotV1 {
+    seq: u64,
+    state: MemoryStoreV1,
+}
+
+/// Keeps the state in memory like `MemoryStore`, while appending every operation changing it to
+/// a log file, which the state is rebuilt from on startup. Every so many operations, the state is
+/// written to a snapshot file and the log starts over.
+///
+/// A record the server crashed halfway through appending is discarded on startup, along with
+/// anything after it. A record that fails to be appended is cut off the log right away, and the
+/// state rebuilt from the snapshot and log to undo the operation, which may then be tried again.
+/// Should that fail as well, every further operation fails until the store is reopened.
+pub struct LogStore {
+    dir: PathBuf,
+    state: MemoryStore,
+    log: File,
+    // the length of the whole records in the log
+    log_len: u64,
+    seq: u64,
+    snapshot_seq: u64,
+    fsync_policy: FsyncPolicy,
+    last_fsync: Instant,
+    compaction_threshold: u64,
+    // set once the log couldn't be appended to, nor the state rebuilt, as it went ahead of the log
+    broken: bool,
+}
+
+impl LogStore {
+    /// Opens the store kept in the directory at `dir`, creating it if missing, and recovers its
+    /// state from the snapshot and log there.
+    pub fn new(dir: impl AsRef<Path>) -> Result<LogStore, Box<dyn Error>> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+
+        let mut log = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(dir.join(LOG_FILE))?;
+        let (snapshot, seq, log_len) = load(&dir, &mut log)?;
+
+        Ok(LogStore {
+            dir,
+            state: snapshot.state,
+            log,
+            log_len,
+            seq,
+            snapshot_seq: snapshot.seq,
+            fsync_policy: FsyncPolicy::Always,
+            last_fsync: Instant::now(),
+            compaction_threshold: DEFAULT_COMPACTION_THRESHOLD,
+            broken: false,
+        })
+    }
+
+    pub fn set_fsync_policy(&mut self, fsync_policy: FsyncPolicy) {
+        self.fsync_policy = fsync_policy;
+    }
+
+    /// Sets how many operations the log holds before it's compacted into a snapshot.
+    pub fn set_compaction_threshold(&mut self, compaction_threshold: u64) {
+        self.compaction_threshold = compaction_threshold;
+    }
+
+    /// Writes the state to a new snapshot, then empties the log.
+    pub fn compact(&mut self) -> Result<(), StoreError> {
+        let mut snapshot = SNAPSHOT_MAGIC.to_vec();
+        snapshot.extend_from_slice(&SNAPSHOT_VERSION.to_be_bytes());
+        bincode::serialize_into(
+            &mut snapshot,
+            &SnapshotRef {
+                seq: self.seq,
+                state: &self.state,
+            },
+        )?;
+        let tmp_path = self.dir.join(SNAPSHOT_TMP_FILE);
+        let mut tmp = File::create(&tmp_path).map_err(unavailable)?;
+        tmp.write_all(&snapshot).map_err(unavailable)?;
+        tmp.sync_all().map_err(unavailable)?;
+        fs::rename(&tmp_path, self.dir.join(SNAPSHOT_FILE)).map_err(unavailable)?;
+        sync_dir(&self.dir).map_err(unavailable)?;
+
+        // were the server to crash right here, the records up to the snapshot would be skipped
+        self.log.set_len(0).map_err(unavailable)?;
+        self.log.sync_all().map_err(unavailable)?;
+        self.log_len = 0;
+        self.snapshot_seq = self.seq;
+        Ok(())
+    }
+
+    /// Appends an operation already applied to the state.
+    fn append(&mut self, operation: Operation) -> Result<(), StoreError> {
+        if self.broken {
+            return Err(StoreError::Unavailable(
+                "log is behind the state, the store must be reopened".into(),
+            ));
+        }
+        let record = match encode_record(self.seq + 1, &operation) {
+            Ok(record) => record,
+            Err(err) => {
+                self.undo();
+                return Err(err);
+            }
+        };
+        if let Err(err) = self.write_record(&record) {
+            self.undo();
+            return Err(unavailable(err));
+        }
+        self.log_len += record.len() as u64;
+        self.seq += 1;
+
+        if self.seq - self.snapshot_seq >= self.compaction_threshold {
+            // the log still has every operation, so compaction may as well be tried again later
+            if let Err(err) = self.compact() {
+                eprintln!("log compaction failed: {}", err);
+            }
+        }
+        Ok(())
+    }
+
+    /// Undoes the operation last applied to the state, which didn't make it to the log, by cutting
+    /// off whatever part of its record was written and rebuilding the state.
+    fn undo(&mut self) {
+        let undone = self
+            .log
+            .set_len(self.log_len)
+            .map_err(|err| err.into())
+            .and_then(|_| load(&self.dir, &mut self.log));
+        match undone {
+            Ok((snapshot, seq, _)) => {
+                self.state = snapshot.state;
+                self.seq = seq;
+            }
+            Err(err) => {
+                eprintln!(
+                    "log: the store must be reopened, as it couldn't be undone: {}",
+                    err
+                );
+                self.broken = true;
+            }
+        }
+    }
+
+    fn write_record(&mut self, record: &[u8]) -> io::Result<()> {
+        self.log.write_all(record)?;
+        let fsync = match self.fsync_policy {
+            FsyncPolicy::Always => true,
+            FsyncPolicy::Interval(interval) => self.last_fsync.elapsed() >= interval,
+            FsyncPolicy::Never => false,
+        };
+        if fsync {
+            self.log.sync_data()?;
+            self.last_fsync = Instant::now();
+        }
+        Ok(())
+    }
+}
+
+#[derive(Serialize)]
+struct SnapshotRef<'a> {
+    seq: u64,
+    state: &'a MemoryStore,
+}
+
+impl Store for LogStore {
+    fn next_message_id(&mut self) -> Result<MessageId, StoreError> {
+        let id = self.state.next_message_id()?;
+        self.append(Operation::NextMessageId)?;
+        Ok(id)
+    }
+
+    fn front_chat(&self, user: &User) -> Result<Option<Chat>, StoreError> {
+        self.state.front_chat(user)
+    }
+
+    fn next_chat_after(&self, user: &User, after: MessageId) -> Result<Option<Chat>, StoreError> {
+        self.state.next_chat_after(user, after)
+    }
+
+    fn queue_chat(&mut self, user: &User, chat: Chat) -> Result<(), StoreError> {
+        self.state.queue_chat(user, chat.clone())?;
+        self.append(Operation::QueueChat(user.clone(), chat))
+    }
+
+    fn dequeue_chat(&mut self, user: &User) -> Result<(), StoreError> {
+        self.state.dequeue_chat(user)?;
+        self.append(Operation::DequeueChat(user.clone()))
+    }
+
+    fn drop_chat(&mut self, user: &User, after: Option<MessageId>) -> Result<(), StoreError> {
+        self.state.drop_chat(user, after)?;
+        self.append(Operation::DropChat(user.clone(), after))
+    }
+
+    fn queue_group_chat(&mut self, group: &Group, chat: Chat) -> Result<(), StoreError> {
+        self.state.queue_group_chat(group, chat.clone())?;
+        self.append(Operation::QueueGroupChat(group.clone(), chat))
+    }
+
+    fn archive_chat(&mut self, chat: &Chat) -> Result<(), StoreError> {
+        self.state.archive_chat(chat)?;
+        self.append(Operation::ArchiveChat(chat.clone()))
+    }
+
+    fn get_history(
+        &self,
+        conversation: &Conversation,
+        bound: Option<HistoryBound>,
+        limit: usize,
+    ) -> Result<Vec<Chat>, StoreError> {
+        self.state.get_history(conversation, bound, limit)
+    }
+
+    fn get_chat(&self, id: MessageId) -> Result<Option<Chat>, StoreError> {
+        self.state.get_chat(id)
+    }
+
+    fn send_chat(&mut self, chat: &Chat) -> Result<(), StoreError> {
+        if let Some(id) = chat.get_id() {
+            if self.state.get_chat(id)?.is_some() {
+                return Ok(());
+            }
+        }
+        self.state.send_chat(chat)?;
+        self.append(Operation::SendChat(chat.clone()))
+    }
+
+    fn add_receipt(&mut self, sender: &User, receipt: &Receipt) -> Result<bool, StoreError> {
+        if !self.state.add_receipt(sender, receipt)? {
+            return Ok(false);
+        }
+        self.append(Operation::AddReceipt(sender.clone(), receipt.clone()))?;
+        Ok(true)
+    }
+
+    fn take_receipts(&mut self, sender: &User) -> Result<Vec<Receipt>, StoreError> {
+        let receipts = self.state.take_receipts(sender)?;
+        if !receipts.is_empty() {
+            self.append(Operation::TakeReceipts(sender.clone()))?;
+        }
+        Ok(receipts)
+    }
+
+    fn create_password_hash(&mut self, user: &User, password_hash: &str) -> Result<(), StoreError> {
+        self.state.create_password_hash(user, password_hash)?;
+        self.append(Operation::CreatePasswordHash(
+            user.clone(),
+            password_hash.into(),
+        ))
+    }
+
+    fn get_password_hash(&self, user: &User) -> Result<Option<String>, StoreError> {
+        self.state.get_password_hash(user)
+    }
+
+    fn create_session(&mut self, session: Session) -> Result<(), StoreError> {
+        self.state.create_session(session.clone())?;
+        self.append(Operation::CreateSession(session))
+    }
+
+    fn get_session(&self, token: &str) -> Result<Option<Session>, StoreError> {
+        self.state.get_session(token)
+    }
+
+    fn remove_session(&mut self, token: &str) -> Result<(), StoreError> {
+        self.state.remove_session(token)?;
+        self.append(Operation::RemoveSession(token.into()))
+    }
+
+    fn get_read_cursors(
+        &self,
+        user: &User,
+    ) -> Result<HashMap<String, Option<MessageId>>, StoreError> {
+        self.state.get_read_cursors(user)
+    }
+
+    fn set_read_cursor(
+        &mut self,
+        user: &User,
+        device: &str,
+        cursor: Option<MessageId>,
+    ) -> Result<(), StoreError> {
+        self.state.set_read_cursor(user, device, cursor)?;
+        self.append(Operation::SetReadCursor(
+            user.clone(),
+            device.into(),
+            cursor,
+        ))
+    }
+
+    fn remove_read_cursor(&mut self, user: &User, device: &str) -> Result<(), StoreError> {
+        self.state.remove_read_cursor(user, device)?;
+        self.append(Operation::RemoveReadCursor(user.clone(), device.into()))
+    }
+
+    fn set_public_key(&mut self, user: &User, public_key: &PublicKey) -> Result<(), StoreError> {
+        self.state.set_public_key(user, public_key)?;
+        self.append(Operation::SetPublicKey(user.clone(), *public_key))
+    }
+
+    fn get_public_key(&self, user: &User) -> Result<Option<PublicKey>, StoreError> {
+        self.state.get_public_key(user)
+    }
+
+    fn get_group_members(&self, group: &Group) -> Result<Vec<User>, StoreError> {
+        self.state.get_group_members(group)
+    }
+
+    fn add_group_member(&mut self, user: User, group: &Group) -> Result<(), StoreError> {
+        self.state.add_group_member(user.clone(), group)?;
+        self.append(Operation::AddGroupMember(user, group.clone()))
+    }
+
+    fn remove_group_member(&mut self, user: &User, group: &Group) -> Result<(), StoreError> {
+        self.state.remove_group_member(user, group)?;
+        self.append(Operation::RemoveGroupMember(user.clone(), group.clone()))
+    }
+
+    fn get_group_role(&self, user: &User, group: &Group) -> Result<Option<Role>, StoreError> {
+        self.state.get_group_role(user, group)
+    }
+
+    fn set_group_role(&mut self, user: &User, group: &Group, role: Role) -> Result<(), StoreError> {
+        self.state.set_group_role(user, group, role)?;
+        self.append(Operation::SetGroupRole(user.clone(), group.clone(), role))
+    }
+
+    fn is_banned(&self, user: &User, group: &Group) -> Result<bool, StoreError> {
+        self.state.is_banned(user, group)
+    }
+
+    fn set_banned(&mut self, user: &User, group: &Group, banned: bool) -> Result<(), StoreError> {
+        self.state.set_banned(user, group, banned)?;
+        self.append(Operation::SetBanned(user.clone(), group.clone(), banned))
+    }
+
+    fn flush(&mut self) -> Result<(), StoreError> {
+        // whatever the fsync policy, the records appended since the last fsync are synced now
+        self.log.sync_data().map_err(unavailable)?;
+        self.last_fsync = Instant::now();
+        Ok(())
+    }
+}
+
+/// Reads the snapshot in `dir`, if any, then replays `log` onto it, returning the state along with
+/// the sequence number of the last record and the length of the whole records.
+fn load(dir: &Path, log: &mut File) -> Result<(Snapshot, u64, u64), Box<dyn Error>> {
+    let mut snapshot = match fs::read(dir.join(SNAPSHOT_FILE)) {
+        Ok(snapshot) => read_snapshot(&snapshot)?,
+        Err(ref err) if err.kind() == io::ErrorKind::NotFound => Snapshot {
+            seq: 0,
+            state: MemoryStore::new(),
+        },
+        Err(err) => return Err(err.into()),
+    };
+    let (seq, log_len) = replay(log, snapshot.seq, &mut snapshot.state)?;
+    Ok((snapshot, seq, log_len))
+}
+
+/// Reads a snapshot of any version this server knows, migrating older ones to the current state.
+fn read_snapshot(snapshot: &[u8]) -> Result<Snapshot, StoreError> {
+    let (version, mut body) = match snapshot.strip_prefix(SNAPSHOT_MAGIC) {
+        Some(rest) if rest.len() >= 4 => {
+            let mut version = [0; 4];
+            version.copy_from_slice(&rest[..4]);
+            (u32::from_be_bytes(version), &rest[4..])
+        }
+        _ => {
+            return Err(StoreError::Serialization(
+                "snapshot header is missing or cut short".into(),
+            ))
+        }
+    };
+    let snapshot = match version {
+        1 => {
+            let snapshot: SnapshotV1 = bincode::deserialize_from(&mut body)?;
+            Snapshot {
+                seq: snapshot.seq,
+                state: snapshot.state.into(),
+            }
+        }
+        SNAPSHOT_VERSION => bincode::deserialize_from(&mut body)?,
+        version => {
+            return Err(StoreError::Serialization(format!(
+                "snapshot version {} is unknown to this server",
+                version
+            )))
+        }
+    };
+    // a snapshot read as the wrong version may well decode, but not to its very end
+    if !body.is_empty() {
+        return Err(StoreError::Serialization(format!(
+            "snapshot is not of version {}, as {} bytes are left over",
+            version,
+            body.len()
+        )));
+    }
+    Ok(snapshot)
+}
+
+/// Replays the records of the log made after the snapshot of `snapshot_seq` onto `state`,
+/// returning the sequence number of the last one and the length of the whole ones. A torn record is cut off the log, along with
+/// anything after it, but a whole one this server can't make sense of fails the replay.
+fn replay(
+    log: &mut File,
+    snapshot_seq: u64,
+    state: &mut MemoryStore,
+) -> Result<(u64, u64), Box<dyn Error>> {
+    let mut buf = Vec::new();
+    log.seek(SeekFrom::Start(0))?;
+    log.read_to_end(&mut buf)?;
+
+    let mut seq = snapshot_seq;
+    let mut offset = 0;
+    while offset < buf.len() {
+        let (record_seq, operation, len) = match decode_record(&buf[offset..]) {
+            Ok(Some(record)) => record,
+            Ok(None) => {
+                eprintln!(
+                    "log: discarding {} bytes of a torn record",
+                    buf.len() - offset
+                );
+                log.set_len(offset as u64)?;
+                log.sync_all()?;
+                return Ok((seq, offset as u64));
+            }
+            Err(err) => {
+                return Err(format!("log: record at byte {} is unreadable: {}", offset, err).into())
+            }
+        };
+        offset += len;
+        // records already in the snapshot are left over from a compaction cut short
+        if record_seq <= seq {
+            continue;
+        }
+        // an operation failed the same way when it was first applied, if at all
+        let _ = operation.apply(state);
+        seq = record_seq;
+    }
+    Ok((seq, offset as u64))
+}
+
+fn encode_record(seq: u64, operation: &Operation) -> Result<Vec<u8>, StoreError> {
+    let payload = bincode::serialize(&(seq, operation))?;
+    let mut record = Vec::with_capacity(RECORD_HEADER_SIZE + payload.len());
+    record.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    record.extend_from_slice(&crc32(&payload).to_be_bytes());
+    record.extend_from_slice(&payload);
+    Ok(record)
+}
+
+/// Decodes the record at the start of `buf`, along with its length, unless it's torn: cut short,
+/// or not matching its checksum.
+fn decode_record(buf: &[u8]) -> Result<Option<(u64, Operation, usize)>, StoreError> {
+    if buf.len() < RECORD_HEADER_SIZE {
+        return Ok(None);
+    }
+    let mut len = [0; 4];
+    len.copy_from_slice(&buf[..4]);
+    let len = u32::from_be_bytes(len) as usize;
+    let mut checksum = [0; 4];
+    checksum.copy_from_slice(&buf[4..8]);
+
+    let payload = match buf.get(RECORD_HEADER_SIZE..RECORD_HEADER_SIZE + len) {
+        Some(payload) if crc32(payload) == u32::from_be_bytes(checksum) => payload,
+        _ => return Ok(None),
+    };
+    let (seq, operation) = bincode::deserialize(payload)?;
+    Ok(Some((seq, operation, RECORD_HEADER_SIZE + len)))
+}
+
+/// CRC-32 (IEEE), to tell a torn record from a whole one.
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in bytes {
+        crc ^= u32::from(*byte);
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+/// Makes a rename in `dir` durable.
+fn sync_dir(dir: &Path) -> io::Result<()> {
+    if cfg!(unix) {
+        File::open(dir)?.sync_all()?;
+    }
+    Ok(())
+}
+
+fn unavailable(err: io::Error) -> StoreError {
+    StoreError::Unavailable(err.to_string())
+}
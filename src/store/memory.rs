@@ -1,77 +1,379 @@
 use std::collections::{vec_deque::VecDeque, HashMap, HashSet};
 
-use crate::message::Chat;
+use serde::{Deserialize, Serialize};
+
+use crate::e2e::PublicKey;
+use crate::message::{Chat, HistoryBound, MessageId, Receipt, Role, Session};
 use crate::people::{Group, User};
-use crate::store::Store;
+use crate::store::{Conversation, Store, StoreError};
 
+#[derive(Default, Serialize, Deserialize)]
 pub struct MemoryStore {
+    last_message_id: u64,
     group_member_lists: HashMap<Group, HashSet<User>>,
+    // the members who are more than plain members
+    group_roles: HashMap<Group, HashMap<User, Role>>,
+    group_bans: HashMap<Group, HashSet<User>>,
     pending_chat_queues: HashMap<User, VecDeque<Chat>>,
+    histories: HashMap<Conversation, Vec<Chat>>,
+    // the conversation every archived chat is in, by id
+    archived_conversations: HashMap<MessageId, Conversation>,
+    readers: HashMap<MessageId, HashSet<User>>,
+    pending_receipts: HashMap<User, Vec<Receipt>>,
+    password_hashes: HashMap<User, String>,
+    sessions: HashMap<String, Session>,
+    read_cursors: HashMap<User, HashMap<String, Option<MessageId>>>,
+    public_keys: HashMap<User, PublicKey>,
 }
 
 impl MemoryStore {
     pub fn new() -> MemoryStore {
+        MemoryStore::default()
+    }
+}
+
+/// The state as `LogStore` first kept it, before read cursors, receipts and group roles.
+#[derive(Deserialize)]
+pub(super) struct MemoryStoreV1 {
+    last_message_id: u64,
+    group_member_lists: HashMap<Group, HashSet<User>>,
+    pending_chat_queues: HashMap<User, VecDeque<Chat>>,
+    histories: HashMap<Conversation, Vec<Chat>>,
+    password_hashes: HashMap<User, String>,
+    sessions: HashMap<String, Session>,
+    public_keys: HashMap<User, PublicKey>,
+}
+
+impl From<MemoryStoreV1> for MemoryStore {
+    fn from(state: MemoryStoreV1) -> Self {
+        let archived_conversations = state
+            .histories
+            .iter()
+            .flat_map(|(conversation, history)| {
+                history
+                    .iter()
+                    .filter_map(move |chat| Some((chat.get_id()?, conversation.clone())))
+            })
+            .collect();
+        // groups had no owner yet, so each is handed to its first member by username
+        let group_roles = state
+            .group_member_lists
+            .iter()
+            .filter_map(|(group, group_members)| {
+                let owner = group_members
+                    .iter()
+                    .min_by_key(|user| user.get_username())?;
+                let roles = [(owner.clone(), Role::Owner)].iter().cloned().collect();
+                Some((group.clone(), roles))
+            })
+            .collect();
         MemoryStore {
-            group_member_lists: HashMap::new(),
-            pending_chat_queues: HashMap::new(),
+            last_message_id: state.last_message_id,
+            group_member_lists: state.group_member_lists,
+            group_roles,
+            pending_chat_queues: state.pending_chat_queues,
+            histories: state.histories,
+            archived_conversations,
+            password_hashes: state.password_hashes,
+            sessions: state.sessions,
+            public_keys: state.public_keys,
+            ..MemoryStore::default()
         }
     }
 }
 
 impl Store for MemoryStore {
-    fn front_chat(&self, user: &User) -> Option<Chat> {
+    fn next_message_id(&mut self) -> Result<MessageId, StoreError> {
+        self.last_message_id += 1;
+        Ok(MessageId::new(self.last_message_id))
+    }
+
+    fn front_chat(&self, user: &User) -> Result<Option<Chat>, StoreError> {
         if let Some(pending_chats) = self.pending_chat_queues.get(user) {
             if let Some(chat) = pending_chats.front() {
-                return Some(chat.clone());
+                return Ok(Some(chat.clone()));
             }
         }
-        None
+        Ok(None)
+    }
+
+    fn next_chat_after(&self, user: &User, after: MessageId) -> Result<Option<Chat>, StoreError> {
+        let pending_chats = match self.pending_chat_queues.get(user) {
+            Some(pending_chats) => pending_chats,
+            None => return Ok(None),
+        };
+        Ok(pending_chats
+            .iter()
+            .find(|chat| chat.get_id() > Some(after))
+            .cloned())
     }
 
-    fn queue_chat(&mut self, user: &User, chat: Chat) {
-        let pending_chats = self
-            .pending_chat_queues
-            .entry(user.clone())
-            .or_insert_with(|| VecDeque::new());
+    fn queue_chat(&mut self, user: &User, chat: Chat) -> Result<(), StoreError> {
+        let pending_chats = self.pending_chat_queues.entry(user.clone()).or_default();
         pending_chats.push_back(chat);
+        Ok(())
     }
 
-    fn dequeue_chat(&mut self, user: &User) {
-        let pending_chats = self
-            .pending_chat_queues
-            .entry(user.clone())
-            .or_insert_with(|| VecDeque::new());
-        pending_chats.pop_front();
-    }
-
-    fn queue_group_chat(&mut self, group: &Group, chat: Chat) {
-        if let Some(group_members) = self.group_member_lists.get(group) {
-            for member in group_members.iter() {
-                if member == chat.get_sender() {
-                    continue;
-                }
-                let pending_chats = self
-                    .pending_chat_queues
-                    .entry(member.clone())
-                    .or_insert_with(|| VecDeque::new());
-                pending_chats.push_back(chat.clone());
-            }
-        }
+    fn dequeue_chat(&mut self, user: &User) -> Result<(), StoreError> {
+        self.pending_chat_queues
+            .get_mut(user)
+            .and_then(|pending_chats| pending_chats.pop_front())
+            .map(|_| ())
+            .ok_or_else(|| StoreError::NotFound(format!("pending chat of {}", user)))
     }
 
-    fn add_group_member(&mut self, user: User, group: &Group) {
+    fn drop_chat(&mut self, user: &User, after: Option<MessageId>) -> Result<(), StoreError> {
+        self.pending_chat_queues
+            .get_mut(user)
+            .and_then(|pending_chats| {
+                let position = match after {
+                    Some(after) => pending_chats
+                        .iter()
+                        .position(|chat| chat.get_id() > Some(after))?,
+                    None => 0,
+                };
+                pending_chats.remove(position)
+            })
+            .map(|_| ())
+            .ok_or_else(|| StoreError::NotFound(format!("pending chat of {}", user)))
+    }
+
+    fn queue_group_chat(&mut self, group: &Group, chat: Chat) -> Result<(), StoreError> {
         let group_members = self
             .group_member_lists
-            .entry(group.clone())
-            .or_insert_with(|| HashSet::new());
+            .get(group)
+            .ok_or_else(|| StoreError::NotFound(format!("group {}", group)))?;
+        for member in group_members.iter() {
+            if member == chat.get_sender() {
+                continue;
+            }
+            let pending_chats = self.pending_chat_queues.entry(member.clone()).or_default();
+            pending_chats.push_back(chat.clone());
+        }
+        Ok(())
+    }
+
+    fn archive_chat(&mut self, chat: &Chat) -> Result<(), StoreError> {
+        let conversation = Conversation::of(chat);
+        if let Some(id) = chat.get_id() {
+            self.archived_conversations.insert(id, conversation.clone());
+        }
+        let history = self.histories.entry(conversation).or_default();
+        history.push(chat.clone());
+        Ok(())
+    }
+
+    fn get_history(
+        &self,
+        conversation: &Conversation,
+        bound: Option<HistoryBound>,
+        limit: usize,
+    ) -> Result<Vec<Chat>, StoreError> {
+        let history = match self.histories.get(conversation) {
+            Some(history) => history,
+            None => return Ok(Vec::new()),
+        };
+        // ids and send times grow together, so both can be searched for
+        let (start, end) = match bound {
+            Some(HistoryBound::Before(id)) => {
+                (0, history.partition_point(|chat| chat.get_id() < Some(id)))
+            }
+            Some(HistoryBound::After(id)) => (
+                history.partition_point(|chat| chat.get_id() <= Some(id)),
+                history.len(),
+            ),
+            Some(HistoryBound::BeforeTime(time)) => (
+                0,
+                history.partition_point(|chat| chat.get_sent_at() < Some(time)),
+            ),
+            Some(HistoryBound::AfterTime(time)) => (
+                history.partition_point(|chat| chat.get_sent_at() <= Some(time)),
+                history.len(),
+            ),
+            None => (0, history.len()),
+        };
+        let page = match bound {
+            Some(HistoryBound::After(_)) | Some(HistoryBound::AfterTime(_)) => {
+                &history[start..end.min(start + limit)]
+            }
+            _ => &history[end.saturating_sub(limit).max(start)..end],
+        };
+        Ok(page.to_vec())
+    }
+
+    fn get_chat(&self, id: MessageId) -> Result<Option<Chat>, StoreError> {
+        let history = match self.archived_conversations.get(&id) {
+            Some(conversation) => &self.histories[conversation],
+            None => return Ok(None),
+        };
+        let i = history.partition_point(|chat| chat.get_id() < Some(id));
+        Ok(history.get(i).cloned())
+    }
+
+    fn add_receipt(&mut self, sender: &User, receipt: &Receipt) -> Result<bool, StoreError> {
+        let readers = self.readers.entry(receipt.get_message_id()).or_default();
+        if !readers.insert(receipt.get_reader().clone()) {
+            return Ok(false);
+        }
+        let pending_receipts = self.pending_receipts.entry(sender.clone()).or_default();
+        pending_receipts.push(receipt.clone());
+        Ok(true)
+    }
+
+    fn take_receipts(&mut self, sender: &User) -> Result<Vec<Receipt>, StoreError> {
+        Ok(self.pending_receipts.remove(sender).unwrap_or_default())
+    }
+
+    fn create_password_hash(&mut self, user: &User, password_hash: &str) -> Result<(), StoreError> {
+        if self.password_hashes.contains_key(user) {
+            return Err(StoreError::AlreadyExists(format!("user {}", user)));
+        }
+        self.password_hashes
+            .insert(user.clone(), password_hash.to_string());
+        Ok(())
+    }
+
+    fn get_password_hash(&self, user: &User) -> Result<Option<String>, StoreError> {
+        Ok(self.password_hashes.get(user).cloned())
+    }
+
+    fn create_session(&mut self, session: Session) -> Result<(), StoreError> {
+        // nothing else ever drops expired sessions
+        self.sessions.retain(|_, session| !session.is_expired());
+        self.sessions
+            .insert(session.get_token().to_string(), session);
+        Ok(())
+    }
+
+    fn get_session(&self, token: &str) -> Result<Option<Session>, StoreError> {
+        Ok(self.sessions.get(token).cloned())
+    }
+
+    fn remove_session(&mut self, token: &str) -> Result<(), StoreError> {
+        self.sessions
+            .remove(token)
+            .map(|_| ())
+            .ok_or_else(|| StoreError::NotFound("session".into()))
+    }
+
+    fn get_read_cursors(
+        &self,
+        user: &User,
+    ) -> Result<HashMap<String, Option<MessageId>>, StoreError> {
+        Ok(self.read_cursors.get(user).cloned().unwrap_or_default())
+    }
+
+    fn set_read_cursor(
+        &mut self,
+        user: &User,
+        device: &str,
+        cursor: Option<MessageId>,
+    ) -> Result<(), StoreError> {
+        let read_cursors = self.read_cursors.entry(user.clone()).or_default();
+        read_cursors.insert(device.to_string(), cursor);
+        Ok(())
+    }
+
+    fn remove_read_cursor(&mut self, user: &User, device: &str) -> Result<(), StoreError> {
+        if let Some(read_cursors) = self.read_cursors.get_mut(user) {
+            read_cursors.remove(device);
+            if read_cursors.is_empty() {
+                self.read_cursors.remove(user);
+            }
+        }
+        Ok(())
+    }
+
+    fn set_public_key(&mut self, user: &User, public_key: &PublicKey) -> Result<(), StoreError> {
+        self.public_keys.insert(user.clone(), *public_key);
+        Ok(())
+    }
+
+    fn get_public_key(&self, user: &User) -> Result<Option<PublicKey>, StoreError> {
+        Ok(self.public_keys.get(user).copied())
+    }
+
+    fn get_group_members(&self, group: &Group) -> Result<Vec<User>, StoreError> {
+        match self.group_member_lists.get(group) {
+            Some(group_members) => Ok(group_members.iter().cloned().collect()),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    fn add_group_member(&mut self, user: User, group: &Group) -> Result<(), StoreError> {
+        let group_members = self.group_member_lists.entry(group.clone()).or_default();
         group_members.insert(user);
+        Ok(())
     }
 
-    fn remove_group_member(&mut self, user: &User, group: &Group) {
-        let group_members = self
+    fn remove_group_member(&mut self, user: &User, group: &Group) -> Result<(), StoreError> {
+        if let Some(group_members) = self.group_member_lists.get_mut(group) {
+            group_members.remove(user);
+            // a group only exists as long as it has members
+            if group_members.is_empty() {
+                self.group_member_lists.remove(group);
+                self.group_bans.remove(group);
+            }
+        }
+        if let Some(group_roles) = self.group_roles.get_mut(group) {
+            group_roles.remove(user);
+            if group_roles.is_empty() {
+                self.group_roles.remove(group);
+            }
+        }
+        Ok(())
+    }
+
+    fn get_group_role(&self, user: &User, group: &Group) -> Result<Option<Role>, StoreError> {
+        let is_member = self
             .group_member_lists
-            .entry(group.clone())
-            .or_insert_with(|| HashSet::new());
-        group_members.remove(user);
+            .get(group)
+            .is_some_and(|group_members| group_members.contains(user));
+        if !is_member {
+            return Ok(None);
+        }
+        let role = self
+            .group_roles
+            .get(group)
+            .and_then(|group_roles| group_roles.get(user));
+        Ok(Some(role.cloned().unwrap_or(Role::Member)))
+    }
+
+    fn set_group_role(&mut self, user: &User, group: &Group, role: Role) -> Result<(), StoreError> {
+        if self.get_group_role(user, group)?.is_none() {
+            return Err(StoreError::NotFound(format!(
+                "member {} of group {}",
+                user, group
+            )));
+        }
+        let group_roles = self.group_roles.entry(group.clone()).or_default();
+        match role {
+            Role::Member => group_roles.remove(user),
+            _ => group_roles.insert(user.clone(), role),
+        };
+        if group_roles.is_empty() {
+            self.group_roles.remove(group);
+        }
+        Ok(())
+    }
+
+    fn is_banned(&self, user: &User, group: &Group) -> Result<bool, StoreError> {
+        Ok(self
+            .group_bans
+            .get(group)
+            .is_some_and(|group_bans| group_bans.contains(user)))
+    }
+
+    fn set_banned(&mut self, user: &User, group: &Group, banned: bool) -> Result<(), StoreError> {
+        let group_bans = self.group_bans.entry(group.clone()).or_default();
+        if banned {
+            group_bans.insert(user.clone());
+        } else {
+            group_bans.remove(user);
+        }
+        if group_bans.is_empty() {
+            self.group_bans.remove(group);
+        }
+        Ok(())
     }
 }
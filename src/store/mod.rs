@@ -1,18 +1,167 @@
-use crate::message::Chat;
-use crate::people::{Group, User};
+use std::collections::HashMap;
+use std::fmt;
 
+use serde::{Deserialize, Serialize};
+
+use crate::e2e::PublicKey;
+use crate::message::{Chat, HistoryBound, MessageId, Receipt, Role, Session};
+use crate::people::{Group, People, User};
+
+pub mod error;
+pub mod log;
 pub mod memory;
 pub mod redis;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
 
+pub use self::error::StoreError;
+pub use self::log::LogStore;
 pub use self::memory::MemoryStore;
 pub use self::redis::RedisStore;
+#[cfg(feature = "sqlite")]
+pub use self::sqlite::SqliteStore;
 
 pub trait Store {
-    fn front_chat(&self, user: &User) -> Option<Chat>;
-    fn queue_chat(&mut self, user: &User, chat: Chat);
-    fn dequeue_chat(&mut self, user: &User);
-    fn queue_group_chat(&mut self, group: &Group, chat: Chat);
+    /// Hands out a new id, greater than every id handed out before.
+    fn next_message_id(&mut self) -> Result<MessageId, StoreError>;
+
+    fn front_chat(&self, user: &User) -> Result<Option<Chat>, StoreError>;
+    /// Finds the first pending chat of the user with an id greater than `after`.
+    fn next_chat_after(&self, user: &User, after: MessageId) -> Result<Option<Chat>, StoreError>;
+    fn queue_chat(&mut self, user: &User, chat: Chat) -> Result<(), StoreError>;
+    /// Fails with `StoreError::NotFound` if the user has no pending chat.
+    fn dequeue_chat(&mut self, user: &User) -> Result<(), StoreError>;
+    /// Removes the pending chat `next_chat_after` would find, or `front_chat` if `after` is None,
+    /// even if it can't be read. Fails with `StoreError::NotFound` if there is no such chat.
+    fn drop_chat(&mut self, user: &User, after: Option<MessageId>) -> Result<(), StoreError>;
+    /// Fails with `StoreError::NotFound` if the group has no members.
+    fn queue_group_chat(&mut self, group: &Group, chat: Chat) -> Result<(), StoreError>;
+
+    /// Adds a chat to the history of its conversation. Chats are archived in id order.
+    fn archive_chat(&mut self, chat: &Chat) -> Result<(), StoreError>;
+    /// Finds up to `limit` archived chats of the conversation next to `bound`, or the latest ones
+    /// without a bound, in id order.
+    fn get_history(
+        &self,
+        conversation: &Conversation,
+        bound: Option<HistoryBound>,
+        limit: usize,
+    ) -> Result<Vec<Chat>, StoreError>;
+    /// Finds an archived chat by its id.
+    fn get_chat(&self, id: MessageId) -> Result<Option<Chat>, StoreError>;
+    /// Queues a chat for its receiver, or for every member of its group but its sender, and
+    /// archives it. A chat already archived under its id is not queued again, so sending it again
+    /// after a failure makes no duplicate.
+    ///
+    /// Stores that may fail halfway through should do it all at once.
+    fn send_chat(&mut self, chat: &Chat) -> Result<(), StoreError> {
+        if let Some(id) = chat.get_id() {
+            if self.get_chat(id)?.is_some() {
+                return Ok(());
+            }
+        }
+        match chat.get_receiver() {
+            People::User(user) => self.queue_chat(user, chat.clone())?,
+            People::Group(group) => self.queue_group_chat(group, chat.clone())?,
+        }
+        self.archive_chat(chat)
+    }
+
+    /// Records the receipt, and queues it for `sender`, unless its reader read the chat before.
+    /// Returns whether it was recorded.
+    fn add_receipt(&mut self, sender: &User, receipt: &Receipt) -> Result<bool, StoreError>;
+    /// Removes the receipts queued for `sender`, returning them in the order they were queued.
+    fn take_receipts(&mut self, sender: &User) -> Result<Vec<Receipt>, StoreError>;
+
+    /// Fails with `StoreError::AlreadyExists` if the user already has a password.
+    fn create_password_hash(&mut self, user: &User, password_hash: &str) -> Result<(), StoreError>;
+    fn get_password_hash(&self, user: &User) -> Result<Option<String>, StoreError>;
+
+    /// Sessions may be forgotten once expired. The server stores them by the hash of their token,
+    /// which stands in for the token throughout the store.
+    fn create_session(&mut self, session: Session) -> Result<(), StoreError>;
+    fn get_session(&self, token: &str) -> Result<Option<Session>, StoreError>;
+    /// Fails with `StoreError::NotFound` if there's no session of the token.
+    fn remove_session(&mut self, token: &str) -> Result<(), StoreError>;
+
+    /// Every device of the user, named by the hash of its session token, along with its read
+    /// cursor: the id of the last pending chat it acknowledged, if any.
+    fn get_read_cursors(
+        &self,
+        user: &User,
+    ) -> Result<HashMap<String, Option<MessageId>>, StoreError>;
+    /// Adds the device if missing.
+    fn set_read_cursor(
+        &mut self,
+        user: &User,
+        device: &str,
+        cursor: Option<MessageId>,
+    ) -> Result<(), StoreError>;
+    fn remove_read_cursor(&mut self, user: &User, device: &str) -> Result<(), StoreError>;
+
+    /// Replaces the public key the user published before, if any.
+    fn set_public_key(&mut self, user: &User, public_key: &PublicKey) -> Result<(), StoreError>;
+    fn get_public_key(&self, user: &User) -> Result<Option<PublicKey>, StoreError>;
+
+    fn get_group_members(&self, group: &Group) -> Result<Vec<User>, StoreError>;
+    /// New members are plain members, and members already there keep their role.
+    fn add_group_member(&mut self, user: User, group: &Group) -> Result<(), StoreError>;
+    /// The role goes along with the member, and the bans along with the last member.
+    fn remove_group_member(&mut self, user: &User, group: &Group) -> Result<(), StoreError>;
+    /// The role of the user in the group, if a member.
+    fn get_group_role(&self, user: &User, group: &Group) -> Result<Option<Role>, StoreError>;
+    /// Fails with `StoreError::NotFound` if the user is not a member.
+    fn set_group_role(&mut self, user: &User, group: &Group, role: Role) -> Result<(), StoreError>;
+    fn is_banned(&self, user: &User, group: &Group) -> Result<bool, StoreError>;
+    /// Bans the user from the group, or lifts the ban. Members stay members either way.
+    fn set_banned(&mut self, user: &User, group: &Group, banned: bool) -> Result<(), StoreError>;
+
+    /// Makes every change so far durable, before the server shuts down. Stores that write every
+    /// change through have nothing to do.
+    fn flush(&mut self) -> Result<(), StoreError> {
+        Ok(())
+    }
+}
+
+/// The chats exchanged between two users, whichever of them sent each chat, or sent to a group.
+#[derive(Clone, Debug, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub enum Conversation {
+    Direct(User, User),
+    Group(Group),
+}
+
+impl Conversation {
+    /// The conversation of `user` with `people`.
+    pub fn new(user: &User, people: &People) -> Self {
+        match people {
+            People::User(other) => {
+                if user.get_username() <= other.get_username() {
+                    Conversation::Direct(user.clone(), other.clone())
+                } else {
+                    Conversation::Direct(other.clone(), user.clone())
+                }
+            }
+            People::Group(group) => Conversation::Group(group.clone()),
+        }
+    }
+
+    pub fn of(chat: &Chat) -> Self {
+        Conversation::new(chat.get_sender(), chat.get_receiver())
+    }
+}
 
-    fn add_group_member(&mut self, user: User, group: &Group);
-    fn remove_group_member(&mut self, user: &User, group: &Group);
+impl fmt::Display for Conversation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            // the length keeps pairs apart when usernames contain the separator
+            Conversation::Direct(first, second) => write!(
+                f,
+                "direct:{}:{}:{}",
+                first.get_username().len(),
+                first,
+                second
+            ),
+            Conversation::Group(group) => write!(f, "group:{}", group),
+        }
+    }
 }
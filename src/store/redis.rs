@@ -1,85 +1,589 @@
 use std::cell::RefCell;
+use std::collections::HashMap;
 use std::error::Error;
 
 use bincode;
-use redis::{Commands, Connection, ErrorKind, RedisError, RedisResult, RedisWrite, Value};
+use redis::{
+    Commands, Connection, ErrorKind, PipelineCommands, RedisError, RedisResult, RedisWrite, Value,
+};
 
-use crate::buffer;
-use crate::message::Chat;
-use crate::people::{Group, User};
-use crate::store::Store;
+use crate::e2e::{PublicKey, KEY_SIZE};
+use crate::message::{Chat, HistoryBound, MessageId, Receipt, Role, Session};
+use crate::people::{Group, People, User};
+use crate::store::{Conversation, Store, StoreError};
 
 pub struct RedisStore {
     conn: RefCell<Connection>,
 }
 
 impl RedisStore {
+    /// Connects to the Redis server at `url`, and migrates the keys there to the latest layout.
     pub fn new(url: &str) -> Result<RedisStore, Box<dyn Error>> {
         let client = redis::Client::open(url)?;
-        let conn = client.get_connection()?;
+        let mut conn = client.get_connection()?;
+        migrate(&mut conn)?;
         Ok(RedisStore {
             conn: RefCell::new(conn),
         })
     }
+
+    /// Pages through the pending chats of `user` for the first one with an id greater than
+    /// `after`, or that can't be read, and returns it as stored.
+    fn find_chat_after(
+        &self,
+        user: &User,
+        after: MessageId,
+    ) -> Result<Option<Vec<u8>>, StoreError> {
+        let key = pending_chats_key(user);
+        let mut start = 0;
+        loop {
+            let chats: Vec<Vec<u8>> =
+                self.conn
+                    .borrow_mut()
+                    .lrange(&key, start, start + PENDING_CHATS_PAGE - 1)?;
+            let page_len = chats.len();
+            for chat in chats {
+                match bincode::deserialize::<Chat>(&chat) {
+                    Ok(ref read) if read.get_id() <= Some(after) => {}
+                    _ => return Ok(Some(chat)),
+                }
+            }
+            if page_len < PENDING_CHATS_PAGE as usize {
+                return Ok(None);
+            }
+            start += PENDING_CHATS_PAGE;
+        }
+    }
 }
 
 impl Store for RedisStore {
-    fn front_chat(&self, user: &User) -> Option<Chat> {
-        let chats: RedisResult<Vec<Chat>> = self.conn.borrow_mut().lrange(user, 0, 0);
-        if let Ok(chats) = chats {
-            if chats.len() >= 1 {
-                return Some(chats[0].clone());
+    fn next_message_id(&mut self) -> Result<MessageId, StoreError> {
+        let id: u64 = self.conn.borrow_mut().incr(LAST_MESSAGE_ID_KEY, 1)?;
+        Ok(MessageId::new(id))
+    }
+
+    fn front_chat(&self, user: &User) -> Result<Option<Chat>, StoreError> {
+        let chats: Vec<Chat> = self
+            .conn
+            .borrow_mut()
+            .lrange(pending_chats_key(user), 0, 0)?;
+        Ok(chats.into_iter().next())
+    }
+
+    fn next_chat_after(&self, user: &User, after: MessageId) -> Result<Option<Chat>, StoreError> {
+        let chat = self.find_chat_after(user, after)?;
+        Ok(chat.map(|chat| bincode::deserialize(&chat)).transpose()?)
+    }
+
+    fn queue_chat(&mut self, user: &User, chat: Chat) -> Result<(), StoreError> {
+        let _: () = self
+            .conn
+            .borrow_mut()
+            .rpush(pending_chats_key(user), chat)?;
+        Ok(())
+    }
+
+    fn dequeue_chat(&mut self, user: &User) -> Result<(), StoreError> {
+        let chat: Option<Vec<u8>> = self.conn.borrow_mut().lpop(pending_chats_key(user))?;
+        chat.map(|_| ())
+            .ok_or_else(|| StoreError::NotFound(format!("pending chat of {}", user)))
+    }
+
+    fn drop_chat(&mut self, user: &User, after: Option<MessageId>) -> Result<(), StoreError> {
+        let after = match after {
+            Some(after) => after,
+            None => return self.dequeue_chat(user),
+        };
+        let chat = self
+            .find_chat_after(user, after)?
+            .ok_or_else(|| StoreError::NotFound(format!("pending chat of {}", user)))?;
+        let _: () = self
+            .conn
+            .borrow_mut()
+            .lrem(pending_chats_key(user), 1, chat)?;
+        Ok(())
+    }
+
+    fn queue_group_chat(&mut self, group: &Group, chat: Chat) -> Result<(), StoreError> {
+        let group_members = self.get_group_members(group)?;
+        if group_members.is_empty() {
+            return Err(StoreError::NotFound(format!("group {}", group)));
+        }
+
+        // every member gets the chat, or none of them does
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+        for member in group_members.iter() {
+            if member == chat.get_sender() {
+                continue;
+            }
+            pipe.rpush(pending_chats_key(member), chat.clone()).ignore();
+        }
+        pipe.query::<()>(&mut *self.conn.borrow_mut())?;
+        Ok(())
+    }
+
+    fn archive_chat(&mut self, chat: &Chat) -> Result<(), StoreError> {
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+        archive_in(&mut pipe, chat);
+        pipe.query::<()>(&mut *self.conn.borrow_mut())?;
+        Ok(())
+    }
+
+    fn send_chat(&mut self, chat: &Chat) -> Result<(), StoreError> {
+        let id = chat.get_id().map(MessageId::get).unwrap_or_default();
+        let key = chat_key(id);
+        // a retry finds the chat sent even to a group everyone left since
+        let sent: bool = self.conn.borrow_mut().exists(&key)?;
+        if sent {
+            return Ok(());
+        }
+        let receivers = match chat.get_receiver() {
+            People::User(user) => vec![user.clone()],
+            People::Group(group) => {
+                let group_members = self.get_group_members(group)?;
+                if group_members.is_empty() {
+                    return Err(StoreError::NotFound(format!("group {}", group)));
+                }
+                group_members
+                    .into_iter()
+                    .filter(|member| member != chat.get_sender())
+                    .collect()
+            }
+        };
+
+        // a transaction that fails leaves none of it behind, and one racing a retry that archived
+        // the chat first is aborted, to find the chat sent when run again
+        redis::transaction(&mut *self.conn.borrow_mut(), &[&key], |conn, pipe| {
+            let sent: bool = conn.exists(&key)?;
+            if sent {
+                return Ok(Some(()));
+            }
+            for receiver in receivers.iter() {
+                pipe.rpush(pending_chats_key(receiver), chat.clone())
+                    .ignore();
+            }
+            archive_in(pipe, chat);
+            pipe.query(conn)
+        })?;
+        Ok(())
+    }
+
+    fn get_history(
+        &self,
+        conversation: &Conversation,
+        bound: Option<HistoryBound>,
+        limit: usize,
+    ) -> Result<Vec<Chat>, StoreError> {
+        if limit == 0 {
+            return Ok(Vec::new());
+        }
+        let key = history_key(conversation);
+        let times_key = history_times_key(conversation);
+        let limit = limit as isize;
+        let mut conn = self.conn.borrow_mut();
+
+        // ids and send times grow together, so a time bound is turned into the id next to it
+        let bound = match bound {
+            Some(HistoryBound::BeforeTime(time)) => {
+                let ids: Vec<u64> =
+                    conn.zrangebyscore_limit(&times_key, time.get_millis(), "+inf", 0, 1)?;
+                ids.first()
+                    .map(|&id| HistoryBound::Before(MessageId::new(id)))
+            }
+            Some(HistoryBound::AfterTime(time)) => {
+                let ids: Vec<u64> =
+                    conn.zrevrangebyscore_limit(&times_key, time.get_millis(), "-inf", 0, 1)?;
+                let id = ids.first().cloned().unwrap_or_default();
+                Some(HistoryBound::After(MessageId::new(id)))
+            }
+            bound => bound,
+        };
+        let mut chats: Vec<Chat> = match bound {
+            Some(HistoryBound::After(id)) => {
+                let min = format!("({}", id);
+                return Ok(conn.zrangebyscore_limit(&key, min, "+inf", 0, limit)?);
             }
+            Some(HistoryBound::Before(id)) => {
+                let max = format!("({}", id);
+                conn.zrevrangebyscore_limit(&key, max, "-inf", 0, limit)?
+            }
+            _ => conn.zrevrange(&key, 0, limit - 1)?,
+        };
+        chats.reverse();
+        Ok(chats)
+    }
+
+    fn get_chat(&self, id: MessageId) -> Result<Option<Chat>, StoreError> {
+        let chat: Option<Chat> = self.conn.borrow_mut().get(chat_key(id.get()))?;
+        Ok(chat)
+    }
+
+    fn add_receipt(&mut self, sender: &User, receipt: &Receipt) -> Result<bool, StoreError> {
+        let id = receipt.get_message_id().get();
+        // the reader is only added along with the receipt, so a retry finds neither added
+        let added: bool = redis::Script::new(ADD_RECEIPT_SCRIPT)
+            .key(readers_key(id))
+            .key(pending_receipts_key(sender))
+            .arg(receipt.get_reader().clone())
+            .arg(bincode::serialize(receipt)?)
+            .invoke(&mut *self.conn.borrow_mut())?;
+        Ok(added)
+    }
+
+    fn take_receipts(&mut self, sender: &User) -> Result<Vec<Receipt>, StoreError> {
+        let key = pending_receipts_key(sender);
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+        pipe.lrange(&key, 0, -1).del(&key).ignore();
+        let (receipts,): (Vec<Vec<u8>>,) = pipe.query(&mut *self.conn.borrow_mut())?;
+        receipts
+            .iter()
+            .map(|receipt| Ok(bincode::deserialize(receipt)?))
+            .collect()
+    }
+
+    fn create_password_hash(&mut self, user: &User, password_hash: &str) -> Result<(), StoreError> {
+        let created: bool = self
+            .conn
+            .borrow_mut()
+            .set_nx(password_hash_key(user), password_hash)?;
+        if !created {
+            return Err(StoreError::AlreadyExists(format!("user {}", user)));
         }
-        None
+        Ok(())
     }
 
-    fn queue_chat(&mut self, user: &User, chat: Chat) {
-        let _: RedisResult<()> = self.conn.borrow_mut().rpush(user, chat);
+    fn get_password_hash(&self, user: &User) -> Result<Option<String>, StoreError> {
+        let password_hash: Option<String> = self.conn.borrow_mut().get(password_hash_key(user))?;
+        Ok(password_hash)
     }
 
-    fn dequeue_chat(&mut self, user: &User) {
-        let _: RedisResult<()> = self.conn.borrow_mut().lpop(user);
+    fn create_session(&mut self, session: Session) -> Result<(), StoreError> {
+        let key = session_key(session.get_token());
+        let expires_at = session.get_expires_at().get_millis();
+
+        // redis drops the session by itself once it expires
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+        pipe.set(&key, bincode::serialize(&session)?).ignore();
+        pipe.cmd("PEXPIREAT").arg(&key).arg(expires_at).ignore();
+        pipe.query::<()>(&mut *self.conn.borrow_mut())?;
+        Ok(())
     }
 
-    fn queue_group_chat(&mut self, group: &Group, chat: Chat) {
-        let group_members: RedisResult<Vec<User>> =
-            self.conn.borrow_mut().smembers(group.get_groupname());
-        if let Ok(group_members) = group_members {
-            for member in group_members.iter() {
-                if member == chat.get_sender() {
-                    continue;
+    fn get_session(&self, token: &str) -> Result<Option<Session>, StoreError> {
+        let session: Option<Vec<u8>> = self.conn.borrow_mut().get(session_key(token))?;
+        match session {
+            Some(session) => Ok(Some(bincode::deserialize(&session)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn remove_session(&mut self, token: &str) -> Result<(), StoreError> {
+        let removed: u64 = self.conn.borrow_mut().del(session_key(token))?;
+        if removed == 0 {
+            return Err(StoreError::NotFound("session".into()));
+        }
+        Ok(())
+    }
+
+    fn get_read_cursors(
+        &self,
+        user: &User,
+    ) -> Result<HashMap<String, Option<MessageId>>, StoreError> {
+        let read_cursors: HashMap<String, u64> =
+            self.conn.borrow_mut().hgetall(read_cursors_key(user))?;
+        Ok(read_cursors
+            .into_iter()
+            .map(|(device, cursor)| {
+                (
+                    device,
+                    Some(cursor).filter(|&id| id > 0).map(MessageId::new),
+                )
+            })
+            .collect())
+    }
+
+    fn set_read_cursor(
+        &mut self,
+        user: &User,
+        device: &str,
+        cursor: Option<MessageId>,
+    ) -> Result<(), StoreError> {
+        // ids start from 1, leaving 0 for a device that acknowledged nothing yet
+        let cursor = cursor.map(MessageId::get).unwrap_or_default();
+        let _: () = self
+            .conn
+            .borrow_mut()
+            .hset(read_cursors_key(user), device, cursor)?;
+        Ok(())
+    }
+
+    fn remove_read_cursor(&mut self, user: &User, device: &str) -> Result<(), StoreError> {
+        let _: () = self
+            .conn
+            .borrow_mut()
+            .hdel(read_cursors_key(user), device)?;
+        Ok(())
+    }
+
+    fn set_public_key(&mut self, user: &User, public_key: &PublicKey) -> Result<(), StoreError> {
+        let _: () = self
+            .conn
+            .borrow_mut()
+            .set(public_key_key(user), &public_key.as_bytes()[..])?;
+        Ok(())
+    }
+
+    fn get_public_key(&self, user: &User) -> Result<Option<PublicKey>, StoreError> {
+        let public_key: Option<Vec<u8>> = self.conn.borrow_mut().get(public_key_key(user))?;
+        match public_key {
+            Some(public_key) => {
+                if public_key.len() != KEY_SIZE {
+                    return Err(StoreError::Serialization(format!(
+                        "public key of {} is {} bytes long",
+                        user,
+                        public_key.len()
+                    )));
                 }
-                let _: RedisResult<()> = self.conn.borrow_mut().rpush(member, chat.clone());
+                let mut bytes = [0; KEY_SIZE];
+                bytes.copy_from_slice(&public_key);
+                Ok(Some(PublicKey::new(bytes)))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn get_group_members(&self, group: &Group) -> Result<Vec<User>, StoreError> {
+        let group_members: Vec<User> = self.conn.borrow_mut().smembers(group_members_key(group))?;
+        Ok(group_members)
+    }
+
+    fn add_group_member(&mut self, user: User, group: &Group) -> Result<(), StoreError> {
+        let _: () = self
+            .conn
+            .borrow_mut()
+            .sadd(group_members_key(group), user)?;
+        Ok(())
+    }
+
+    fn remove_group_member(&mut self, user: &User, group: &Group) -> Result<(), StoreError> {
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+        pipe.srem(group_members_key(group), user.clone()).ignore();
+        pipe.hdel(group_roles_key(group), user.get_username())
+            .ignore();
+        pipe.scard(group_members_key(group));
+        let (member_count,): (u64,) = pipe.query(&mut *self.conn.borrow_mut())?;
+        if member_count == 0 {
+            let _: () = self.conn.borrow_mut().del(group_bans_key(group))?;
+        }
+        Ok(())
+    }
+
+    fn get_group_role(&self, user: &User, group: &Group) -> Result<Option<Role>, StoreError> {
+        let is_member: bool = self
+            .conn
+            .borrow_mut()
+            .sismember(group_members_key(group), user.clone())?;
+        if !is_member {
+            return Ok(None);
+        }
+        let role: Option<u8> = self
+            .conn
+            .borrow_mut()
+            .hget(group_roles_key(group), user.get_username())?;
+        Ok(Some(match role {
+            Some(2) => Role::Owner,
+            Some(1) => Role::Admin,
+            _ => Role::Member,
+        }))
+    }
+
+    fn set_group_role(&mut self, user: &User, group: &Group, role: Role) -> Result<(), StoreError> {
+        if self.get_group_role(user, group)?.is_none() {
+            return Err(StoreError::NotFound(format!(
+                "member {} of group {}",
+                user, group
+            )));
+        }
+        // plain members are left out of the hash
+        let role = match role {
+            Role::Member => None,
+            Role::Admin => Some(1),
+            Role::Owner => Some(2),
+        };
+        let key = group_roles_key(group);
+        let _: () = match role {
+            Some(role) => self
+                .conn
+                .borrow_mut()
+                .hset(key, user.get_username(), role)?,
+            None => self.conn.borrow_mut().hdel(key, user.get_username())?,
+        };
+        Ok(())
+    }
+
+    fn is_banned(&self, user: &User, group: &Group) -> Result<bool, StoreError> {
+        let banned: bool = self
+            .conn
+            .borrow_mut()
+            .sismember(group_bans_key(group), user.clone())?;
+        Ok(banned)
+    }
+
+    fn set_banned(&mut self, user: &User, group: &Group, banned: bool) -> Result<(), StoreError> {
+        let key = group_bans_key(group);
+        let _: () = if banned {
+            self.conn.borrow_mut().sadd(key, user.clone())?
+        } else {
+            self.conn.borrow_mut().srem(key, user.clone())?
+        };
+        Ok(())
+    }
+}
+
+const LAST_MESSAGE_ID_KEY: &str = "last_message_id";
+const SCHEMA_VERSION_KEY: &str = "schema_version";
+
+/// The version of the layout of the keys, which is missing from keys written before group roles.
+const SCHEMA_VERSION: u64 = 1;
+
+/// How many pending chats are fetched at once while looking for the next one to send.
+const PENDING_CHATS_PAGE: isize = 64;
+
+/// Adds the reader in `ARGV[1]` to the readers of a chat in `KEYS[1]`, and only if they weren't
+/// among them, pushes the receipt in `ARGV[2]` to the pending receipts in `KEYS[2]`.
+const ADD_RECEIPT_SCRIPT: &str = r"
+if redis.call('SADD', KEYS[1], ARGV[1]) == 0 then
+    return 0
+end
+redis.call('RPUSH', KEYS[2], ARGV[2])
+return 1
+";
+
+/// Brings the keys written by older servers to the latest layout, once.
+fn migrate(conn: &mut Connection) -> Result<(), StoreError> {
+    let version: Option<u64> = conn.get(SCHEMA_VERSION_KEY)?;
+    let version = version.unwrap_or(0);
+    if version > SCHEMA_VERSION {
+        return Err(StoreError::Unavailable(format!(
+            "schema version {} is newer than this server knows",
+            version
+        )));
+    }
+    if version < 1 {
+        // groups had no owner yet, so each is handed to its first member by username
+        let keys: Vec<String> = conn.scan_match("group_members:*")?.collect();
+        for key in keys {
+            let group_members: Vec<User> = conn.smembers(&key)?;
+            let roles_key = key.replacen("group_members:", "group_roles:", 1);
+            let roles: Vec<u8> = conn.hvals(&roles_key)?;
+            if roles.contains(&2) {
+                continue;
+            }
+            if let Some(owner) = group_members.iter().min_by_key(|user| user.get_username()) {
+                let _: () = conn.hset(&roles_key, owner.get_username(), 2)?;
             }
         }
     }
+    let _: () = conn.set(SCHEMA_VERSION_KEY, SCHEMA_VERSION)?;
+    Ok(())
+}
 
-    fn add_group_member(&mut self, user: User, group: &Group) {
-        let _: RedisResult<()> = self.conn.borrow_mut().sadd(group, user);
+/// Adds the commands archiving a chat to `pipe`.
+fn archive_in(pipe: &mut redis::Pipeline, chat: &Chat) {
+    let conversation = Conversation::of(chat);
+    let id = chat.get_id().map(MessageId::get).unwrap_or_default();
+    pipe.zadd(history_key(&conversation), chat.clone(), id)
+        .ignore();
+    pipe.set(chat_key(id), chat.clone()).ignore();
+    if let Some(sent_at) = chat.get_sent_at() {
+        pipe.zadd(history_times_key(&conversation), id, sent_at.get_millis())
+            .ignore();
     }
+}
+
+// users and groups may share names, so their keys are namespaced
+
+fn pending_chats_key(user: &User) -> String {
+    format!("pending_chats:{}", user)
+}
+
+fn pending_receipts_key(user: &User) -> String {
+    format!("pending_receipts:{}", user)
+}
+
+fn group_members_key(group: &Group) -> String {
+    format!("group_members:{}", group)
+}
+
+fn group_roles_key(group: &Group) -> String {
+    format!("group_roles:{}", group)
+}
+
+fn group_bans_key(group: &Group) -> String {
+    format!("group_bans:{}", group)
+}
+
+fn password_hash_key(user: &User) -> String {
+    format!("password_hash:{}", user)
+}
+
+fn session_key(token: &str) -> String {
+    format!("session:{}", token)
+}
+
+fn read_cursors_key(user: &User) -> String {
+    format!("read_cursors:{}", user)
+}
+
+fn public_key_key(user: &User) -> String {
+    format!("public_key:{}", user)
+}
+
+fn chat_key(id: u64) -> String {
+    format!("chat:{}", id)
+}
+
+fn readers_key(id: u64) -> String {
+    format!("readers:{}", id)
+}
 
-    fn remove_group_member(&mut self, user: &User, group: &Group) {
-        let _: RedisResult<()> = self.conn.borrow_mut().srem(group, user);
+fn history_key(conversation: &Conversation) -> String {
+    format!("history:{}", conversation)
+}
+
+fn history_times_key(conversation: &Conversation) -> String {
+    format!("history_times:{}", conversation)
+}
+
+impl From<RedisError> for StoreError {
+    fn from(err: RedisError) -> Self {
+        match err.kind() {
+            ErrorKind::TypeError => StoreError::Serialization(err.to_string()),
+            _ => StoreError::Unavailable(err.to_string()),
+        }
     }
 }
 
 impl redis::ToRedisArgs for Chat {
-    fn write_redis_args<W: ?Sized>(&self, out: &mut W)
+    fn write_redis_args<W>(&self, out: &mut W)
     where
-        W: RedisWrite,
+        W: ?Sized + RedisWrite,
     {
         let chat = bincode::serialize(self).unwrap();
-        let buf = buffer::from_vec(chat);
-        out.write_arg(&buf);
+        out.write_arg(&chat);
     }
 }
 
 impl redis::FromRedisValue for Chat {
     fn from_redis_value(v: &Value) -> RedisResult<Self> {
         if let Value::Data(v) = v {
-            let chat = bincode::deserialize(v).unwrap();
-            Ok(chat)
+            bincode::deserialize(v)
+                .map_err(|_| RedisError::from((ErrorKind::TypeError, "chat not deserializable")))
         } else {
             Err(RedisError::from((
                 ErrorKind::TypeError,
@@ -90,21 +594,20 @@ impl redis::FromRedisValue for Chat {
 }
 
 impl redis::ToRedisArgs for User {
-    fn write_redis_args<W: ?Sized>(&self, out: &mut W)
+    fn write_redis_args<W>(&self, out: &mut W)
     where
-        W: RedisWrite,
+        W: ?Sized + RedisWrite,
     {
         let user = bincode::serialize(self).unwrap();
-        let buf = buffer::from_vec(user);
-        out.write_arg(&buf);
+        out.write_arg(&user);
     }
 }
 
 impl redis::FromRedisValue for User {
     fn from_redis_value(v: &Value) -> RedisResult<Self> {
         if let Value::Data(v) = v {
-            let user = bincode::deserialize(v).unwrap();
-            Ok(user)
+            bincode::deserialize(v)
+                .map_err(|_| RedisError::from((ErrorKind::TypeError, "user not deserializable")))
         } else {
             Err(RedisError::from((
                 ErrorKind::TypeError,
@@ -113,25 +616,3 @@ impl redis::FromRedisValue for User {
         }
     }
 }
-
-impl redis::ToRedisArgs for &User {
-    fn write_redis_args<W: ?Sized>(&self, out: &mut W)
-    where
-        W: RedisWrite,
-    {
-        let user = bincode::serialize(self).unwrap();
-        let buf = buffer::from_vec(user);
-        out.write_arg(&buf);
-    }
-}
-
-impl redis::ToRedisArgs for &Group {
-    fn write_redis_args<W: ?Sized>(&self, out: &mut W)
-    where
-        W: RedisWrite,
-    {
-        let group = bincode::serialize(self).unwrap();
-        let buf = buffer::from_vec(group);
-        out.write_arg(&buf);
-    }
-}
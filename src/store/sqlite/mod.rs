@@ -0,0 +1,683 @@
+use std::collections::HashMap;
+use std::error::Error;
+
+use bincode;
+use rusqlite::types::Value;
+use rusqlite::{params, params_from_iter, Connection, OptionalExtension, Row, Transaction};
+use serde::de::DeserializeOwned;
+
+use crate::e2e::{PublicKey, KEY_SIZE};
+use crate::message::{Chat, HistoryBound, MessageId, Receipt, Role, Session, Timestamp};
+use crate::people::{Group, People, User};
+use crate::store::{Conversation, Store, StoreError};
+
+/// Every migration brings the schema one version further. SQLite keeps the version of a database
+/// as its `user_version`, which is the number of migrations applied to it.
+const MIGRATIONS: &[&str] = &[
+    "
+    CREATE TABLE counters (
+        name TEXT PRIMARY KEY,
+        value INTEGER NOT NULL
+    );
+    INSERT INTO counters (name, value) VALUES ('last_message_id', 0);
+
+    CREATE TABLE pending_chats (
+        seq INTEGER PRIMARY KEY AUTOINCREMENT,
+        user TEXT NOT NULL,
+        chat BLOB NOT NULL
+    );
+    CREATE INDEX pending_chats_user ON pending_chats (user, seq);
+
+    CREATE TABLE group_members (
+        grp TEXT NOT NULL,
+        user TEXT NOT NULL,
+        PRIMARY KEY (grp, user)
+    );
+
+    CREATE TABLE history (
+        conversation TEXT NOT NULL,
+        id INTEGER NOT NULL,
+        sent_at INTEGER,
+        chat BLOB NOT NULL,
+        PRIMARY KEY (conversation, id)
+    );
+    CREATE INDEX history_sent_at ON history (conversation, sent_at);
+
+    CREATE TABLE password_hashes (
+        user TEXT PRIMARY KEY,
+        password_hash TEXT NOT NULL
+    );
+
+    CREATE TABLE sessions (
+        token TEXT PRIMARY KEY,
+        expires_at INTEGER NOT NULL,
+        session BLOB NOT NULL
+    );
+
+    CREATE TABLE public_keys (
+        user TEXT PRIMARY KEY,
+        public_key BLOB NOT NULL
+    );
+",
+    "
+    CREATE TABLE read_cursors (
+        user TEXT NOT NULL,
+        device TEXT NOT NULL,
+        last_read INTEGER,
+        PRIMARY KEY (user, device)
+    );
+
+    ALTER TABLE pending_chats ADD COLUMN id INTEGER;
+    CREATE INDEX pending_chats_id ON pending_chats (user, id);
+",
+    "
+    CREATE INDEX history_id ON history (id);
+
+    CREATE TABLE readers (
+        id INTEGER NOT NULL,
+        user TEXT NOT NULL,
+        PRIMARY KEY (id, user)
+    );
+
+    CREATE TABLE pending_receipts (
+        seq INTEGER PRIMARY KEY AUTOINCREMENT,
+        user TEXT NOT NULL,
+        receipt BLOB NOT NULL
+    );
+    CREATE INDEX pending_receipts_user ON pending_receipts (user, seq);
+",
+    "
+    ALTER TABLE group_members ADD COLUMN role INTEGER NOT NULL DEFAULT 0;
+    -- groups had no owner yet, so each is handed to its first member by username
+    UPDATE group_members SET role = 2
+    WHERE user = (SELECT MIN(user) FROM group_members AS members WHERE members.grp = group_members.grp);
+
+    CREATE TABLE group_bans (
+        grp TEXT NOT NULL,
+        user TEXT NOT NULL,
+        PRIMARY KEY (grp, user)
+    );
+",
+];
+
+/// The version whose migration gives pending chats an id column, which the ids of the chats
+/// pending by then are copied to, see `copy_pending_ids`.
+const PENDING_IDS_VERSION: usize = 2;
+
+/// Keeps everything in a single SQLite database file, which survives restarts without running
+/// another service.
+pub struct SqliteStore {
+    conn: Connection,
+}
+
+impl SqliteStore {
+    /// Opens the database at `path`, creating it if missing, and migrates it to the latest
+    /// schema.
+    pub fn new(path: &str) -> Result<SqliteStore, Box<dyn Error>> {
+        let mut conn = Connection::open(path)?;
+        migrate(&mut conn)?;
+        Ok(SqliteStore { conn })
+    }
+
+    /// The version of the schema of the database, see `MIGRATIONS`.
+    pub fn get_schema_version(&self) -> Result<usize, StoreError> {
+        schema_version(&self.conn)
+    }
+}
+
+fn schema_version(conn: &Connection) -> Result<usize, StoreError> {
+    let version: i64 = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+    Ok(version as usize)
+}
+
+/// Applies the migrations the database is missing, each in its own transaction.
+fn migrate(conn: &mut Connection) -> Result<(), StoreError> {
+    let version = schema_version(conn)?;
+    if version > MIGRATIONS.len() {
+        return Err(StoreError::Unavailable(format!(
+            "schema version {} is newer than this server knows",
+            version
+        )));
+    }
+    for (i, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        let tx = conn.transaction()?;
+        tx.execute_batch(migration)?;
+        if i + 1 == PENDING_IDS_VERSION {
+            copy_pending_ids(&tx)?;
+        }
+        tx.pragma_update(None, "user_version", (i + 1) as i64)?;
+        tx.commit()?;
+    }
+    Ok(())
+}
+
+/// Copies the id of every pending chat out of the chat, where SQL can't get at it. Chats that
+/// can't be read have no id to be delivered in order of, so they're dropped.
+fn copy_pending_ids(tx: &Transaction) -> Result<(), StoreError> {
+    let mut stmt = tx.prepare("SELECT seq, chat FROM pending_chats")?;
+    let rows = stmt
+        .query_map([], |row| {
+            Ok((row.get::<_, i64>(0)?, row.get::<_, Vec<u8>>(1)?))
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut dropped = 0;
+    for (seq, chat) in rows {
+        match deserialize::<Chat>(&chat).map(|chat| chat.get_id()) {
+            Ok(Some(id)) => tx.execute(
+                "UPDATE pending_chats SET id = ?1 WHERE seq = ?2",
+                params![id.get() as i64, seq],
+            )?,
+            _ => {
+                dropped += 1;
+                tx.execute("DELETE FROM pending_chats WHERE seq = ?1", params![seq])?
+            }
+        };
+    }
+    if dropped > 0 {
+        eprintln!(
+            "sqlite: dropped {} pending chats that can't be read",
+            dropped
+        );
+    }
+    Ok(())
+}
+
+impl Store for SqliteStore {
+    fn next_message_id(&mut self) -> Result<MessageId, StoreError> {
+        let tx = self.conn.transaction()?;
+        tx.execute(
+            "UPDATE counters SET value = value + 1 WHERE name = 'last_message_id'",
+            [],
+        )?;
+        let id: i64 = tx.query_row(
+            "SELECT value FROM counters WHERE name = 'last_message_id'",
+            [],
+            |row| row.get(0),
+        )?;
+        tx.commit()?;
+        Ok(MessageId::new(id as u64))
+    }
+
+    fn front_chat(&self, user: &User) -> Result<Option<Chat>, StoreError> {
+        let chat: Option<Vec<u8>> = self
+            .conn
+            .query_row(
+                "SELECT chat FROM pending_chats WHERE user = ?1 ORDER BY seq LIMIT 1",
+                params![user.to_string()],
+                |row| row.get(0),
+            )
+            .optional()?;
+        chat.map(|chat| deserialize(&chat)).transpose()
+    }
+
+    fn next_chat_after(&self, user: &User, after: MessageId) -> Result<Option<Chat>, StoreError> {
+        let chat: Option<Vec<u8>> = self
+            .conn
+            .query_row(
+                "SELECT chat FROM pending_chats WHERE user = ?1 AND id > ?2 ORDER BY id LIMIT 1",
+                params![user.to_string(), after.get() as i64],
+                |row| row.get(0),
+            )
+            .optional()?;
+        chat.map(|chat| deserialize(&chat)).transpose()
+    }
+
+    fn queue_chat(&mut self, user: &User, chat: Chat) -> Result<(), StoreError> {
+        queue(&self.conn, user, &chat)
+    }
+
+    fn dequeue_chat(&mut self, user: &User) -> Result<(), StoreError> {
+        let dequeued = self.conn.execute(
+            "DELETE FROM pending_chats WHERE seq = \
+             (SELECT MIN(seq) FROM pending_chats WHERE user = ?1)",
+            params![user.to_string()],
+        )?;
+        if dequeued == 0 {
+            return Err(StoreError::NotFound(format!("pending chat of {}", user)));
+        }
+        Ok(())
+    }
+
+    fn drop_chat(&mut self, user: &User, after: Option<MessageId>) -> Result<(), StoreError> {
+        let after = match after {
+            Some(after) => after,
+            None => return self.dequeue_chat(user),
+        };
+        let dropped = self.conn.execute(
+            "DELETE FROM pending_chats WHERE seq = \
+             (SELECT seq FROM pending_chats WHERE user = ?1 AND id > ?2 ORDER BY id LIMIT 1)",
+            params![user.to_string(), after.get() as i64],
+        )?;
+        if dropped == 0 {
+            return Err(StoreError::NotFound(format!("pending chat of {}", user)));
+        }
+        Ok(())
+    }
+
+    fn queue_group_chat(&mut self, group: &Group, chat: Chat) -> Result<(), StoreError> {
+        let group_members = self.get_group_members(group)?;
+        if group_members.is_empty() {
+            return Err(StoreError::NotFound(format!("group {}", group)));
+        }
+
+        // every member gets the chat, or none of them does
+        let tx = self.conn.transaction()?;
+        for member in group_members.iter() {
+            if member != chat.get_sender() {
+                queue(&tx, member, &chat)?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn archive_chat(&mut self, chat: &Chat) -> Result<(), StoreError> {
+        archive(&self.conn, chat)
+    }
+
+    fn send_chat(&mut self, chat: &Chat) -> Result<(), StoreError> {
+        if let Some(id) = chat.get_id() {
+            if self.get_chat(id)?.is_some() {
+                return Ok(());
+            }
+        }
+        let receivers = match chat.get_receiver() {
+            People::User(user) => vec![user.clone()],
+            People::Group(group) => {
+                let group_members = self.get_group_members(group)?;
+                if group_members.is_empty() {
+                    return Err(StoreError::NotFound(format!("group {}", group)));
+                }
+                group_members
+                    .into_iter()
+                    .filter(|member| member != chat.get_sender())
+                    .collect()
+            }
+        };
+
+        let tx = self.conn.transaction()?;
+        for receiver in receivers.iter() {
+            queue(&tx, receiver, chat)?;
+        }
+        archive(&tx, chat)?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn get_history(
+        &self,
+        conversation: &Conversation,
+        bound: Option<HistoryBound>,
+        limit: usize,
+    ) -> Result<Vec<Chat>, StoreError> {
+        let mut params = vec![
+            Value::Text(conversation.to_string()),
+            Value::Integer(limit as i64),
+        ];
+        let (condition, order) = match bound {
+            Some(HistoryBound::Before(id)) => {
+                params.push(Value::Integer(id.get() as i64));
+                ("AND id < ?3", "DESC")
+            }
+            Some(HistoryBound::After(id)) => {
+                params.push(Value::Integer(id.get() as i64));
+                ("AND id > ?3", "ASC")
+            }
+            Some(HistoryBound::BeforeTime(time)) => {
+                params.push(Value::Integer(time.get_millis() as i64));
+                ("AND sent_at < ?3", "DESC")
+            }
+            Some(HistoryBound::AfterTime(time)) => {
+                params.push(Value::Integer(time.get_millis() as i64));
+                ("AND sent_at > ?3", "ASC")
+            }
+            None => ("", "DESC"),
+        };
+        let sql = format!(
+            "SELECT chat FROM history WHERE conversation = ?1 {} ORDER BY id {} LIMIT ?2",
+            condition, order
+        );
+        let mut stmt = self.conn.prepare(&sql)?;
+        let mut chats = stmt
+            .query_map(params_from_iter(params), blob)?
+            .map(|chat| deserialize(&chat?))
+            .collect::<Result<Vec<Chat>, _>>()?;
+        // pages are taken from the end next to the bound, but returned in id order
+        if order == "DESC" {
+            chats.reverse();
+        }
+        Ok(chats)
+    }
+
+    fn get_chat(&self, id: MessageId) -> Result<Option<Chat>, StoreError> {
+        let chat = self
+            .conn
+            .query_row(
+                "SELECT chat FROM history WHERE id = ?1",
+                params![id.get() as i64],
+                blob,
+            )
+            .optional()?;
+        chat.map(|chat| deserialize(&chat)).transpose()
+    }
+
+    fn add_receipt(&mut self, sender: &User, receipt: &Receipt) -> Result<bool, StoreError> {
+        let tx = self.conn.transaction()?;
+        let added = tx.execute(
+            "INSERT OR IGNORE INTO readers (id, user) VALUES (?1, ?2)",
+            params![
+                receipt.get_message_id().get() as i64,
+                receipt.get_reader().to_string()
+            ],
+        )?;
+        if added == 0 {
+            return Ok(false);
+        }
+        tx.execute(
+            "INSERT INTO pending_receipts (user, receipt) VALUES (?1, ?2)",
+            params![sender.to_string(), serialize(receipt)?],
+        )?;
+        tx.commit()?;
+        Ok(true)
+    }
+
+    fn take_receipts(&mut self, sender: &User) -> Result<Vec<Receipt>, StoreError> {
+        let tx = self.conn.transaction()?;
+        let receipts = tx
+            .prepare("SELECT receipt FROM pending_receipts WHERE user = ?1 ORDER BY seq")?
+            .query_map(params![sender.to_string()], blob)?
+            .map(|receipt| deserialize(&receipt?))
+            .collect::<Result<Vec<Receipt>, _>>()?;
+        tx.execute(
+            "DELETE FROM pending_receipts WHERE user = ?1",
+            params![sender.to_string()],
+        )?;
+        tx.commit()?;
+        Ok(receipts)
+    }
+
+    fn create_password_hash(&mut self, user: &User, password_hash: &str) -> Result<(), StoreError> {
+        let created = self.conn.execute(
+            "INSERT OR IGNORE INTO password_hashes (user, password_hash) VALUES (?1, ?2)",
+            params![user.to_string(), password_hash],
+        )?;
+        if created == 0 {
+            return Err(StoreError::AlreadyExists(format!("user {}", user)));
+        }
+        Ok(())
+    }
+
+    fn get_password_hash(&self, user: &User) -> Result<Option<String>, StoreError> {
+        Ok(self
+            .conn
+            .query_row(
+                "SELECT password_hash FROM password_hashes WHERE user = ?1",
+                params![user.to_string()],
+                |row| row.get(0),
+            )
+            .optional()?)
+    }
+
+    fn create_session(&mut self, session: Session) -> Result<(), StoreError> {
+        let tx = self.conn.transaction()?;
+        // nothing else ever drops expired sessions
+        tx.execute(
+            "DELETE FROM sessions WHERE expires_at <= ?1",
+            params![Timestamp::now().get_millis() as i64],
+        )?;
+        tx.execute(
+            "INSERT OR REPLACE INTO sessions (token, expires_at, session) VALUES (?1, ?2, ?3)",
+            params![
+                session.get_token(),
+                session.get_expires_at().get_millis() as i64,
+                serialize(&session)?
+            ],
+        )?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn get_session(&self, token: &str) -> Result<Option<Session>, StoreError> {
+        let session = self
+            .conn
+            .query_row(
+                "SELECT session FROM sessions WHERE token = ?1",
+                params![token],
+                blob,
+            )
+            .optional()?;
+        session.map(|session| deserialize(&session)).transpose()
+    }
+
+    fn remove_session(&mut self, token: &str) -> Result<(), StoreError> {
+        let removed = self
+            .conn
+            .execute("DELETE FROM sessions WHERE token = ?1", params![token])?;
+        if removed == 0 {
+            return Err(StoreError::NotFound("session".into()));
+        }
+        Ok(())
+    }
+
+    fn get_read_cursors(
+        &self,
+        user: &User,
+    ) -> Result<HashMap<String, Option<MessageId>>, StoreError> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT device, last_read FROM read_cursors WHERE user = ?1")?;
+        let read_cursors = stmt
+            .query_map(params![user.to_string()], |row| {
+                let last_read: Option<i64> = row.get(1)?;
+                Ok((row.get(0)?, last_read.map(|id| MessageId::new(id as u64))))
+            })?
+            .collect::<Result<_, _>>()?;
+        Ok(read_cursors)
+    }
+
+    fn set_read_cursor(
+        &mut self,
+        user: &User,
+        device: &str,
+        cursor: Option<MessageId>,
+    ) -> Result<(), StoreError> {
+        self.conn.execute(
+            "INSERT OR REPLACE INTO read_cursors (user, device, last_read) VALUES (?1, ?2, ?3)",
+            params![user.to_string(), device, cursor.map(|id| id.get() as i64)],
+        )?;
+        Ok(())
+    }
+
+    fn remove_read_cursor(&mut self, user: &User, device: &str) -> Result<(), StoreError> {
+        self.conn.execute(
+            "DELETE FROM read_cursors WHERE user = ?1 AND device = ?2",
+            params![user.to_string(), device],
+        )?;
+        Ok(())
+    }
+
+    fn set_public_key(&mut self, user: &User, public_key: &PublicKey) -> Result<(), StoreError> {
+        self.conn.execute(
+            "INSERT OR REPLACE INTO public_keys (user, public_key) VALUES (?1, ?2)",
+            params![user.to_string(), &public_key.as_bytes()[..]],
+        )?;
+        Ok(())
+    }
+
+    fn get_public_key(&self, user: &User) -> Result<Option<PublicKey>, StoreError> {
+        let public_key = self
+            .conn
+            .query_row(
+                "SELECT public_key FROM public_keys WHERE user = ?1",
+                params![user.to_string()],
+                blob,
+            )
+            .optional()?;
+        let public_key = match public_key {
+            Some(public_key) => public_key,
+            None => return Ok(None),
+        };
+        if public_key.len() != KEY_SIZE {
+            return Err(StoreError::Serialization(format!(
+                "public key of {} is {} bytes long",
+                user,
+                public_key.len()
+            )));
+        }
+        let mut bytes = [0; KEY_SIZE];
+        bytes.copy_from_slice(&public_key);
+        Ok(Some(PublicKey::new(bytes)))
+    }
+
+    fn get_group_members(&self, group: &Group) -> Result<Vec<User>, StoreError> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT user FROM group_members WHERE grp = ?1")?;
+        let group_members = stmt
+            .query_map(params![group.to_string()], |row| Ok(User::new(row.get(0)?)))?
+            .collect::<Result<_, _>>()?;
+        Ok(group_members)
+    }
+
+    fn add_group_member(&mut self, user: User, group: &Group) -> Result<(), StoreError> {
+        self.conn.execute(
+            "INSERT OR IGNORE INTO group_members (grp, user) VALUES (?1, ?2)",
+            params![group.to_string(), user.to_string()],
+        )?;
+        Ok(())
+    }
+
+    fn remove_group_member(&mut self, user: &User, group: &Group) -> Result<(), StoreError> {
+        let tx = self.conn.transaction()?;
+        tx.execute(
+            "DELETE FROM group_members WHERE grp = ?1 AND user = ?2",
+            params![group.to_string(), user.to_string()],
+        )?;
+        tx.execute(
+            "DELETE FROM group_bans WHERE grp = ?1
+             AND NOT EXISTS (SELECT 1 FROM group_members WHERE grp = ?1)",
+            params![group.to_string()],
+        )?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn get_group_role(&self, user: &User, group: &Group) -> Result<Option<Role>, StoreError> {
+        let role: Option<i64> = self
+            .conn
+            .query_row(
+                "SELECT role FROM group_members WHERE grp = ?1 AND user = ?2",
+                params![group.to_string(), user.to_string()],
+                |row| row.get(0),
+            )
+            .optional()?;
+        role.map(to_role).transpose()
+    }
+
+    fn set_group_role(&mut self, user: &User, group: &Group, role: Role) -> Result<(), StoreError> {
+        let updated = self.conn.execute(
+            "UPDATE group_members SET role = ?3 WHERE grp = ?1 AND user = ?2",
+            params![group.to_string(), user.to_string(), from_role(role)],
+        )?;
+        if updated == 0 {
+            return Err(StoreError::NotFound(format!(
+                "member {} of group {}",
+                user, group
+            )));
+        }
+        Ok(())
+    }
+
+    fn is_banned(&self, user: &User, group: &Group) -> Result<bool, StoreError> {
+        let banned = self
+            .conn
+            .query_row(
+                "SELECT 1 FROM group_bans WHERE grp = ?1 AND user = ?2",
+                params![group.to_string(), user.to_string()],
+                |_| Ok(()),
+            )
+            .optional()?;
+        Ok(banned.is_some())
+    }
+
+    fn set_banned(&mut self, user: &User, group: &Group, banned: bool) -> Result<(), StoreError> {
+        let sql = if banned {
+            "INSERT OR IGNORE INTO group_bans (grp, user) VALUES (?1, ?2)"
+        } else {
+            "DELETE FROM group_bans WHERE grp = ?1 AND user = ?2"
+        };
+        self.conn
+            .execute(sql, params![group.to_string(), user.to_string()])?;
+        Ok(())
+    }
+}
+
+fn queue(conn: &Connection, user: &User, chat: &Chat) -> Result<(), StoreError> {
+    conn.execute(
+        "INSERT INTO pending_chats (user, id, chat) VALUES (?1, ?2, ?3)",
+        params![
+            user.to_string(),
+            chat.get_id().map(|id| id.get() as i64),
+            serialize(chat)?
+        ],
+    )?;
+    Ok(())
+}
+
+fn archive(conn: &Connection, chat: &Chat) -> Result<(), StoreError> {
+    let id = chat.get_id().map(MessageId::get).unwrap_or_default();
+    conn.execute(
+        "INSERT OR REPLACE INTO history (conversation, id, sent_at, chat) \
+         VALUES (?1, ?2, ?3, ?4)",
+        params![
+            Conversation::of(chat).to_string(),
+            id as i64,
+            chat.get_sent_at()
+                .map(|sent_at| sent_at.get_millis() as i64),
+            serialize(chat)?
+        ],
+    )?;
+    Ok(())
+}
+
+/// Reads the first column of a row as a blob, as serialized values are kept.
+fn blob(row: &Row) -> rusqlite::Result<Vec<u8>> {
+    row.get(0)
+}
+
+fn serialize<T: serde::Serialize>(value: &T) -> Result<Vec<u8>, StoreError> {
+    Ok(bincode::serialize(value)?)
+}
+
+fn deserialize<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, StoreError> {
+    Ok(bincode::deserialize(bytes)?)
+}
+
+fn from_role(role: Role) -> i64 {
+    match role {
+        Role::Member => 0,
+        Role::Admin => 1,
+        Role::Owner => 2,
+    }
+}
+
+fn to_role(role: i64) -> Result<Role, StoreError> {
+    match role {
+        0 => Ok(Role::Member),
+        1 => Ok(Role::Admin),
+        2 => Ok(Role::Owner),
+        role => Err(StoreError::Serialization(format!("unknown role {}", role))),
+    }
+}
+
+impl From<rusqlite::Error> for StoreError {
+    fn from(err: rusqlite::Error) -> Self {
+        match err {
+            rusqlite::Error::InvalidColumnType(..)
+            | rusqlite::Error::FromSqlConversionFailure(..) => {
+                StoreError::Serialization(err.to_string())
+            }
+            err => StoreError::Unavailable(err.to_string()),
+        }
+    }
+}
@@ -0,0 +1,91 @@
+use std::error::Error;
+use std::io::{self, prelude::*};
+use std::net::{Shutdown, TcpStream};
+use std::sync::Arc;
+
+use rustls::{ClientConfig, ClientConnection, ServerConfig, ServerConnection};
+
+use crate::tls::{self, TlsStream};
+
+/// A connection between a client and the server, either plain TCP, or TLS over TCP.
+pub enum Stream {
+    Tcp(TcpStream),
+    Tls(TlsStream),
+}
+
+impl Stream {
+    pub fn connect(host: &str, port: &str) -> io::Result<Self> {
+        let address = [host, port].join(":");
+        Ok(Stream::Tcp(TcpStream::connect(address)?))
+    }
+
+    /// Connects over TLS, verifying the server certificate against `host`, as `config` asks. The
+    /// TLS handshake itself happens along with the first read or write.
+    pub fn connect_tls(
+        host: &str,
+        port: &str,
+        config: Arc<ClientConfig>,
+    ) -> Result<Self, Box<dyn Error>> {
+        let address = [host, port].join(":");
+        let sock = TcpStream::connect(address)?;
+        let conn = ClientConnection::new(config, tls::server_name(host)?)?;
+        Ok(Stream::Tls(TlsStream::new(conn, sock)))
+    }
+
+    /// Wraps a stream accepted by the server, in TLS if it's configured.
+    pub fn accept(sock: TcpStream, config: Option<&Arc<ServerConfig>>) -> io::Result<Self> {
+        match config {
+            Some(config) => {
+                let conn = ServerConnection::new(Arc::clone(config)).map_err(io::Error::other)?;
+                Ok(Stream::Tls(TlsStream::new(conn, sock)))
+            }
+            None => Ok(Stream::Tcp(sock)),
+        }
+    }
+
+    pub fn get_ref(&self) -> &TcpStream {
+        match self {
+            Stream::Tcp(sock) => sock,
+            Stream::Tls(stream) => stream.get_ref(),
+        }
+    }
+
+    pub fn try_clone(&self) -> io::Result<Self> {
+        match self {
+            Stream::Tcp(sock) => Ok(Stream::Tcp(sock.try_clone()?)),
+            Stream::Tls(stream) => Ok(Stream::Tls(stream.try_clone()?)),
+        }
+    }
+
+    pub fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+        match self {
+            Stream::Tcp(sock) => sock.shutdown(how),
+            Stream::Tls(stream) => stream.shutdown(how),
+        }
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(sock) => sock.read(buf),
+            Stream::Tls(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(sock) => sock.write(buf),
+            Stream::Tls(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Stream::Tcp(sock) => sock.flush(),
+            Stream::Tls(stream) => stream.flush(),
+        }
+    }
+}
@@ -0,0 +1,253 @@
+use std::convert::TryFrom;
+use std::error::Error;
+use std::io::{self, prelude::*};
+use std::net::{Shutdown, TcpStream};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use ring::digest;
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::{self, CryptoProvider};
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime};
+use rustls::{
+    CertificateError, ClientConfig, Connection, DigitallySignedStruct, RootCertStore, ServerConfig,
+    SignatureScheme,
+};
+
+const READ_CHUNK_SIZE: usize = 16 * 1024;
+
+/// A TLS connection over TCP which, like `TcpStream`, can be cloned to be read from one thread
+/// while written to from another.
+///
+/// The clones share the TLS session, which is only locked to process bytes already received or
+/// to send some, never while waiting for the peer.
+pub struct TlsStream {
+    conn: Arc<Mutex<Connection>>,
+    sock: TcpStream,
+}
+
+impl TlsStream {
+    pub fn new(conn: impl Into<Connection>, sock: TcpStream) -> Self {
+        let mut conn = conn.into();
+        // every write is sent right away, so only data written before the handshake completes
+        // is ever buffered
+        conn.set_buffer_limit(None);
+        TlsStream {
+            conn: Arc::new(Mutex::new(conn)),
+            sock,
+        }
+    }
+
+    pub fn get_ref(&self) -> &TcpStream {
+        &self.sock
+    }
+
+    pub fn try_clone(&self) -> io::Result<Self> {
+        Ok(TlsStream {
+            conn: Arc::clone(&self.conn),
+            sock: self.sock.try_clone()?,
+        })
+    }
+
+    /// Tells the peer the connection is closing before shutting down the socket.
+    pub fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+        {
+            let mut conn = self.conn.lock().unwrap();
+            conn.send_close_notify();
+            let _ = self.send_tls(&mut conn);
+        }
+        self.sock.shutdown(how)
+    }
+
+    fn send_tls(&self, conn: &mut Connection) -> io::Result<()> {
+        while conn.wants_write() {
+            conn.write_tls(&mut &self.sock)?;
+        }
+        Ok(())
+    }
+}
+
+impl Read for TlsStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut chunk = [0; READ_CHUNK_SIZE];
+        loop {
+            {
+                let mut conn = self.conn.lock().unwrap();
+                match conn.reader().read(buf) {
+                    Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => {}
+                    result => return result,
+                }
+            }
+
+            let n = (&self.sock).read(&mut chunk)?;
+
+            let mut conn = self.conn.lock().unwrap();
+            let mut received = &chunk[..n];
+            loop {
+                conn.read_tls(&mut received)?;
+                if let Err(err) = conn.process_new_packets() {
+                    // the peer is told why, as far as possible
+                    let _ = self.send_tls(&mut conn);
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, err));
+                }
+                if received.is_empty() {
+                    break;
+                }
+            }
+            // handshake messages are answered as soon as they arrive
+            self.send_tls(&mut conn)?;
+        }
+    }
+}
+
+impl Write for TlsStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut conn = self.conn.lock().unwrap();
+        let n = conn.writer().write(buf)?;
+        self.send_tls(&mut conn)?;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let mut conn = self.conn.lock().unwrap();
+        conn.writer().flush()?;
+        self.send_tls(&mut conn)?;
+        (&self.sock).flush()
+    }
+}
+
+/// Loads the server certificate chain and private key from PEM files.
+pub fn load_server_config(
+    cert_path: impl AsRef<Path>,
+    key_path: impl AsRef<Path>,
+) -> Result<Arc<ServerConfig>, Box<dyn Error>> {
+    let certs = CertificateDer::pem_file_iter(cert_path)?.collect::<Result<Vec<_>, _>>()?;
+    let key = PrivateKeyDer::from_pem_file(key_path)?;
+    let config = ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)?;
+    Ok(Arc::new(config))
+}
+
+/// Trusts the server certificates issued by the CA certificates of a PEM file.
+pub fn load_client_config(ca_path: impl AsRef<Path>) -> Result<Arc<ClientConfig>, Box<dyn Error>> {
+    let mut roots = RootCertStore::empty();
+    for cert in CertificateDer::pem_file_iter(ca_path)? {
+        roots.add(cert?)?;
+    }
+    let config = ClientConfig::builder()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    Ok(Arc::new(config))
+}
+
+/// Trusts only the server certificate of the given fingerprint, as made by
+/// `certificate_fingerprint`, whoever issued it and whatever names it holds.
+pub fn pinned_client_config(fingerprint: &str) -> Result<Arc<ClientConfig>, Box<dyn Error>> {
+    let fingerprint = parse_fingerprint(fingerprint)?;
+    let provider = Arc::new(crypto::ring::default_provider());
+    let config = ClientConfig::builder_with_provider(Arc::clone(&provider))
+        .with_safe_default_protocol_versions()?
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(PinnedCertVerifier {
+            fingerprint,
+            provider,
+        }))
+        .with_no_client_auth();
+    Ok(Arc::new(config))
+}
+
+/// The SHA-256 digest of a DER-encoded certificate, in hex.
+pub fn certificate_fingerprint(cert: &[u8]) -> String {
+    let fingerprint = digest::digest(&digest::SHA256, cert);
+    fingerprint
+        .as_ref()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Loads the fingerprint of the first certificate of a PEM file.
+pub fn load_certificate_fingerprint(cert_path: impl AsRef<Path>) -> Result<String, Box<dyn Error>> {
+    let cert = CertificateDer::from_pem_file(cert_path)?;
+    Ok(certificate_fingerprint(&cert))
+}
+
+/// The name to verify the server certificate against, for a host name or an IP address.
+pub fn server_name(host: &str) -> Result<ServerName<'static>, Box<dyn Error>> {
+    Ok(ServerName::try_from(host.to_string())?)
+}
+
+fn parse_fingerprint(fingerprint: &str) -> Result<Vec<u8>, Box<dyn Error>> {
+    // fingerprints are commonly written with colons between bytes
+    let hex: String = fingerprint.chars().filter(|c| *c != ':').collect();
+    if hex.len() != 2 * digest::SHA256_OUTPUT_LEN || !hex.is_ascii() {
+        return Err("fingerprint is not a hex SHA-256 digest".into());
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).map_err(|err| err.into()))
+        .collect()
+}
+
+#[derive(Debug)]
+struct PinnedCertVerifier {
+    fingerprint: Vec<u8>,
+    provider: Arc<CryptoProvider>,
+}
+
+impl ServerCertVerifier for PinnedCertVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let fingerprint = digest::digest(&digest::SHA256, end_entity);
+        if fingerprint.as_ref() != &self.fingerprint[..] {
+            return Err(rustls::Error::InvalidCertificate(
+                CertificateError::ApplicationVerificationFailure,
+            ));
+        }
+        Ok(ServerCertVerified::assertion())
+    }
+
+    // the handshake must still be signed by the key of the pinned certificate
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        crypto::verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        crypto::verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider
+            .signature_verification_algorithms
+            .supported_schemes()
+    }
+}
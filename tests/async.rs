@@ -0,0 +1,129 @@
+#![cfg(feature = "async")]
+
+use tokio::runtime::Runtime;
+
+use conver::client::Client;
+use conver::message::{Message, ServerMessage};
+use conver::people::User;
+use conver::server::{AsyncServer, AsyncServerHandle};
+use conver::store::MemoryStore;
+
+mod common;
+
+/// Starts a server of the test's own on a free port, which serves as long as its runtime is kept.
+fn start_server() -> (Runtime, AsyncServerHandle) {
+    let runtime = Runtime::new().unwrap();
+    let server = AsyncServer::new(common::HOST, "0", Box::new(MemoryStore::new()));
+    let server = runtime.block_on(server.spawn()).unwrap();
+    (runtime, server)
+}
+
+fn create_client(server: &AsyncServerHandle, user: &User) -> Client {
+    common::create_client_on(&server.get_address().port().to_string(), user)
+}
+
+#[test]
+fn test_async_chat() {
+    let (_runtime, server) = start_server();
+
+    let first_user = common::generate_user();
+    let second_user = common::generate_user();
+
+    let mut first_client = create_client(&server, &first_user);
+    let mut second_client = create_client(&server, &second_user);
+
+    // First sends a chat, Second receives it
+    let chat = common::generate_chat(&first_user, &second_user);
+    first_client
+        .send_message(Message::Chat(chat.clone()))
+        .unwrap();
+    let sent = second_client.read_chat().unwrap();
+    common::assert_delivered(&chat, &sent);
+
+    // Second sends a reply, First receives it
+    let chat = common::generate_chat(&second_user, &first_user);
+    second_client
+        .send_message(Message::Chat(chat.clone()))
+        .unwrap();
+    let sent = first_client.read_chat().unwrap();
+    common::assert_delivered(&chat, &sent);
+}
+
+#[test]
+fn test_async_chat_pending() {
+    let (_runtime, server) = start_server();
+
+    let first_user = common::generate_user();
+    let second_user = common::generate_user();
+
+    // First sends a chat to Second
+    let mut first_client = create_client(&server, &first_user);
+    let chat = common::generate_chat(&first_user, &second_user);
+    first_client
+        .send_message(Message::Chat(chat.clone()))
+        .unwrap();
+
+    // The chat is queued once acknowledged, before Second connects
+    loop {
+        if let ServerMessage::Ack(Some(_)) = first_client.read_event().unwrap() {
+            break;
+        }
+    }
+
+    // Second only connects afterward, receives the chat anyway
+    let mut second_client = create_client(&server, &second_user);
+    let sent = second_client.read_chat().unwrap();
+    common::assert_delivered(&chat, &sent);
+}
+
+#[test]
+fn test_async_group() {
+    let (_runtime, server) = start_server();
+
+    let group = common::generate_group();
+
+    let first_user = common::generate_user();
+    let second_user = common::generate_user();
+    let third_user = common::generate_user();
+
+    let mut first_client = create_client(&server, &first_user);
+    let mut second_client = create_client(&server, &second_user);
+    let mut third_client = create_client(&server, &third_user);
+
+    // All join the group, which they're in once acknowledged
+    for client in [&mut first_client, &mut second_client, &mut third_client].iter_mut() {
+        client
+            .send_message(Message::Join(common::create_join(&group)))
+            .unwrap();
+        assert_eq!(ServerMessage::Ack(None), client.read_event().unwrap());
+    }
+
+    // First sends a chat to the group
+    let chat = common::generate_group_chat(&first_user, &group);
+    first_client
+        .send_message(Message::Chat(chat.clone()))
+        .unwrap();
+
+    // The rest receive the chat
+    let sent = second_client.read_chat().unwrap();
+    common::assert_delivered(&chat, &sent);
+    let sent = third_client.read_chat().unwrap();
+    common::assert_delivered(&chat, &sent);
+}
+
+#[test]
+fn test_async_shutdown() {
+    let (runtime, server) = start_server();
+    let port = server.get_address().port().to_string();
+    let user = common::generate_user();
+    let mut client = create_client(&server, &user);
+
+    // The client is told before its connection is closed, and no new one is accepted
+    runtime.block_on(server.shutdown());
+    match client.read_event().unwrap() {
+        ServerMessage::Notice(_) => {}
+        event => panic!("unexpected event: {:?}", event),
+    }
+    assert!(client.read_event().is_err());
+    assert!(Client::login(common::HOST, &port, user.get_username(), common::PASSWORD).is_err());
+}
@@ -0,0 +1,191 @@
+use std::env;
+use std::fs;
+use std::io::prelude::*;
+use std::process;
+use std::time::Duration;
+
+use conver::client::Client;
+use conver::frame::{Codec, FrameReader};
+use conver::message::{ErrorCode, Message, ServerMessage};
+use conver::server::Server;
+use conver::store::{LogStore, MemoryStore};
+
+mod common;
+
+use common::HOST;
+
+#[test]
+fn test_register_login() {
+    let server = common::start_server();
+    let port = common::get_port(&server);
+
+    let user = common::generate_user();
+    let username = user.get_username();
+
+    Client::register(HOST, &port, username, common::PASSWORD).unwrap();
+    Client::login(HOST, &port, username, common::PASSWORD).unwrap();
+
+    // A registered user can't be registered again
+    common::assert_rejected(
+        Client::register(HOST, &port, username, "another password"),
+        ErrorCode::AlreadyExists,
+    );
+}
+
+#[test]
+fn test_login_wrong_password() {
+    let server = common::start_server();
+    let port = common::get_port(&server);
+
+    let user = common::generate_user();
+    let username = user.get_username();
+
+    Client::register(HOST, &port, username, common::PASSWORD).unwrap();
+    common::assert_rejected(
+        Client::login(HOST, &port, username, "wrong password"),
+        ErrorCode::Unauthenticated,
+    );
+}
+
+#[test]
+fn test_login_unknown_user() {
+    let server = common::start_server();
+    let port = common::get_port(&server);
+
+    let user = common::generate_user();
+    common::assert_rejected(
+        Client::login(HOST, &port, user.get_username(), common::PASSWORD),
+        ErrorCode::Unauthenticated,
+    );
+}
+
+#[test]
+fn test_unauthenticated() {
+    let server = common::start_server();
+
+    // Naming a user is no longer enough to connect as them
+    let user = common::generate_user();
+    let mut stream = common::connect_raw(&server);
+    stream.write_all(&common::encode_frame(&user)).unwrap();
+
+    let mut reader = FrameReader::new(stream, Codec::default());
+    let event: ServerMessage = bincode::deserialize(&reader.read_frame().unwrap()).unwrap();
+    match event {
+        ServerMessage::Error(err) => assert_eq!(ErrorCode::Unauthenticated, err.get_code()),
+        event => panic!("unexpected event: {:?}", event),
+    }
+    // And the connection is closed right after
+    assert!(reader.read_frame().is_err());
+}
+
+#[test]
+fn test_session_resume() {
+    let server = common::start_server();
+    let port = common::get_port(&server);
+
+    let first_user = common::generate_user();
+    let second_user = common::generate_user();
+
+    let mut first_client = common::create_client(&server, &first_user);
+    let second_client = common::create_client(&server, &second_user);
+    let session = second_client.get_session().clone();
+    assert_eq!(&second_user, session.get_user());
+    drop(second_client);
+
+    // Chats sent while away are delivered to the resumed session
+    let chat = common::generate_chat(&first_user, &second_user);
+    first_client
+        .send_message(Message::Chat(chat.clone()))
+        .unwrap();
+    match first_client.read_event().unwrap() {
+        ServerMessage::Ack(Some(_)) => {}
+        event => panic!("unexpected event: {:?}", event),
+    }
+
+    let mut second_client = Client::resume(HOST, &port, session.get_token()).unwrap();
+    assert_eq!(&second_user, second_client.get_user());
+    assert_eq!(&session, second_client.get_session());
+    let sent = second_client.read_chat().unwrap();
+    common::assert_delivered(&chat, &sent);
+}
+
+#[test]
+fn test_session_revoke() {
+    let server = common::start_server();
+    let port = common::get_port(&server);
+
+    let first_user = common::generate_user();
+    let second_user = common::generate_user();
+
+    let mut first_client = common::create_client(&server, &first_user);
+    let second_client = common::create_client(&server, &second_user);
+    let first_token = first_client.get_session().get_token().to_string();
+    let second_token = second_client.get_session().get_token().to_string();
+    let mut other_client = Client::resume(HOST, &port, &first_token).unwrap();
+
+    // Nobody but its user gets to revoke a session
+    first_client
+        .send_message(Message::RevokeSession(second_token.clone()))
+        .unwrap();
+    match first_client.read_event().unwrap() {
+        ServerMessage::Error(err) => assert_eq!(ErrorCode::NotFound, err.get_code()),
+        event => panic!("unexpected event: {:?}", event),
+    }
+    Client::resume(HOST, &port, &second_token).unwrap();
+
+    first_client
+        .send_message(Message::RevokeSession(first_token.clone()))
+        .unwrap();
+    assert_eq!(ServerMessage::Ack(None), first_client.read_event().unwrap());
+    common::assert_rejected(
+        Client::resume(HOST, &port, &first_token),
+        ErrorCode::Unauthenticated,
+    );
+
+    // Connections made with the session are closed, the one revoking it right after the reply
+    assert!(first_client.read_event().is_err());
+    match other_client.read_event().unwrap() {
+        ServerMessage::Error(err) => assert_eq!(ErrorCode::Unauthenticated, err.get_code()),
+        event => panic!("unexpected event: {:?}", event),
+    }
+    assert!(other_client.read_event().is_err());
+}
+
+#[test]
+fn test_session_token_hashed() {
+    let dir = env::temp_dir().join(format!("conver-auth-{}-token_hashed", process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    let server = Server::new(HOST, "0", Box::new(LogStore::new(&dir).unwrap()));
+    let server = server.spawn().unwrap();
+    let port = common::get_port(&server);
+
+    let user = common::generate_user();
+    let client = common::create_client(&server, &user);
+    let token = client.get_session().get_token().to_string();
+    drop(client);
+
+    // Nothing stored gives the token away, while it still resumes the session
+    for entry in fs::read_dir(&dir).unwrap() {
+        let stored = fs::read(entry.unwrap().path()).unwrap();
+        assert!(!stored
+            .windows(token.len())
+            .any(|window| window == token.as_bytes()));
+    }
+    let client = Client::resume(HOST, &port, &token).unwrap();
+    assert_eq!(&user, client.get_user());
+}
+
+#[test]
+fn test_session_expired() {
+    let mut server = Server::new(HOST, "0", Box::new(MemoryStore::new()));
+    server.set_session_ttl(Duration::from_millis(0));
+    let server = server.spawn().unwrap();
+    let port = common::get_port(&server);
+
+    let user = common::generate_user();
+    let client = common::create_client_on(&port, &user);
+    common::assert_rejected(
+        Client::resume(HOST, &port, client.get_session().get_token()),
+        ErrorCode::Unauthenticated,
+    );
+}
@@ -1,18 +1,22 @@
+use std::io::prelude::*;
 use std::{thread, time};
 
-use conver::message::Message;
+use conver::client::Client;
+use conver::frame::{Codec, FrameReader};
+use conver::message::{ErrorCode, History, HistoryBound, Message, ServerMessage, Timestamp};
+use conver::people::People;
 
 mod common;
 
 #[test]
 fn test_chat() {
-    let _shared = common::TEST_LOCK.lock().unwrap();
+    let server = common::start_server();
 
     let first_user = common::generate_user();
     let second_user = common::generate_user();
 
-    let mut first_client = common::create_client(&first_user);
-    let mut second_client = common::create_client(&second_user);
+    let mut first_client = common::create_client(&server, &first_user);
+    let mut second_client = common::create_client(&server, &second_user);
 
     // First sends a chat, Second receives it
     let chat = common::generate_chat(&first_user, &second_user);
@@ -20,7 +24,7 @@ fn test_chat() {
         .send_message(Message::Chat(chat.clone()))
         .unwrap();
     let sent = second_client.read_chat().unwrap();
-    assert_eq!(chat, sent);
+    common::assert_delivered(&chat, &sent);
 
     // Second sends a reply, First receives it
     let chat = common::generate_chat(&second_user, &first_user);
@@ -28,32 +32,51 @@ fn test_chat() {
         .send_message(Message::Chat(chat.clone()))
         .unwrap();
     let sent = first_client.read_chat().unwrap();
-    assert_eq!(chat, sent);
+    common::assert_delivered(&chat, &sent);
+}
+
+#[test]
+fn test_chat_long() {
+    let server = common::start_server();
+
+    let first_user = common::generate_user();
+    let second_user = common::generate_user();
+
+    let mut first_client = common::create_client(&server, &first_user);
+    let mut second_client = common::create_client(&server, &second_user);
+
+    // First sends a chat far longer than the old fixed-size buffer, Second receives it whole
+    let chat = common::generate_long_chat(&first_user, &second_user);
+    first_client
+        .send_message(Message::Chat(chat.clone()))
+        .unwrap();
+    let sent = second_client.read_chat().unwrap();
+    common::assert_delivered(&chat, &sent);
 }
 
 #[test]
 fn test_chat_pending() {
-    let _shared = common::TEST_LOCK.lock().unwrap();
+    let server = common::start_server();
 
     let first_user = common::generate_user();
     let second_user = common::generate_user();
 
     // First sends a chat to Second
-    let mut first_client = common::create_client(&first_user);
+    let mut first_client = common::create_client(&server, &first_user);
     let chat = common::generate_chat(&first_user, &second_user);
     first_client
         .send_message(Message::Chat(chat.clone()))
         .unwrap();
 
     // Second only connects afterward, receives the chat anyway
-    let mut second_client = common::create_client(&second_user);
+    let mut second_client = common::create_client(&server, &second_user);
     let sent = second_client.read_chat().unwrap();
-    assert_eq!(chat, sent);
+    common::assert_delivered(&chat, &sent);
 }
 
 #[test]
 fn test_group() {
-    let _shared = common::TEST_LOCK.lock().unwrap();
+    let server = common::start_server();
 
     let group = common::generate_group();
 
@@ -61,19 +84,19 @@ fn test_group() {
     let second_user = common::generate_user();
     let third_user = common::generate_user();
 
-    let mut first_client = common::create_client(&first_user);
-    let mut second_client = common::create_client(&second_user);
-    let mut third_client = common::create_client(&third_user);
+    let mut first_client = common::create_client(&server, &first_user);
+    let mut second_client = common::create_client(&server, &second_user);
+    let mut third_client = common::create_client(&server, &third_user);
 
     // All join the group
     first_client
-        .send_message(Message::Join(common::create_join(&first_user, &group)))
+        .send_message(Message::Join(common::create_join(&group)))
         .unwrap();
     second_client
-        .send_message(Message::Join(common::create_join(&second_user, &group)))
+        .send_message(Message::Join(common::create_join(&group)))
         .unwrap();
     third_client
-        .send_message(Message::Join(common::create_join(&third_user, &group)))
+        .send_message(Message::Join(common::create_join(&group)))
         .unwrap();
 
     // This is to ensure all users have joined the group
@@ -87,37 +110,37 @@ fn test_group() {
 
     // The rest receive the chat
     let sent = second_client.read_chat().unwrap();
-    assert_eq!(chat, sent);
+    common::assert_delivered(&chat, &sent);
     let sent = third_client.read_chat().unwrap();
-    assert_eq!(chat, sent);
+    common::assert_delivered(&chat, &sent);
 }
 
 #[test]
 fn test_group_pending() {
-    let _shared = common::TEST_LOCK.lock().unwrap();
+    let server = common::start_server();
 
     let group = common::generate_group();
 
     let first_user = common::generate_user();
     let second_user = common::generate_user();
 
-    let mut first_client = common::create_client(&first_user);
-    let mut second_client = common::create_client(&second_user);
+    let mut first_client = common::create_client(&server, &first_user);
+    let mut second_client = common::create_client(&server, &second_user);
 
     // First and Second join the group
     first_client
-        .send_message(Message::Join(common::create_join(&first_user, &group)))
+        .send_message(Message::Join(common::create_join(&group)))
         .unwrap();
     second_client
-        .send_message(Message::Join(common::create_join(&second_user, &group)))
+        .send_message(Message::Join(common::create_join(&group)))
         .unwrap();
 
     // Third joins the group then disconnects
     let third_user = common::generate_user();
     {
-        let mut third_client = common::create_client(&third_user);
+        let mut third_client = common::create_client(&server, &third_user);
         third_client
-            .send_message(Message::Join(common::create_join(&third_user, &group)))
+            .send_message(Message::Join(common::create_join(&group)))
             .unwrap();
     }
 
@@ -131,7 +154,258 @@ fn test_group_pending() {
         .unwrap();
 
     // Third reconnects, receives the chat anyway
-    let mut third_client = common::create_client(&third_user);
+    let mut third_client = common::create_client(&server, &third_user);
+    let sent = third_client.read_chat().unwrap();
+    common::assert_delivered(&chat, &sent);
+}
+
+#[test]
+fn test_chat_timestamps() {
+    let server = common::start_server();
+
+    let first_user = common::generate_user();
+    let second_user = common::generate_user();
+
+    let mut first_client = common::create_client(&server, &first_user);
+    let mut second_client = common::create_client(&server, &second_user);
+
+    // The claimed time is kept as is, even far off, while the server stamps its own
+    let before = Timestamp::now();
+    let mut chat = common::generate_chat(&first_user, &second_user);
+    chat.set_claimed_at(Timestamp::new(0));
+    first_client
+        .send_message(Message::Chat(chat.clone()))
+        .unwrap();
+    let sent = second_client.read_chat().unwrap();
+    let after = Timestamp::now();
+
+    common::assert_delivered(&chat, &sent);
+    assert_eq!(Some(Timestamp::new(0)), sent.get_claimed_at());
+    let sent_at = sent.get_sent_at().unwrap();
+    assert!(before <= sent_at && sent_at <= after);
+}
+
+#[test]
+fn test_history() {
+    let server = common::start_server();
+
+    let first_user = common::generate_user();
+    let second_user = common::generate_user();
+
+    let mut first_client = common::create_client(&server, &first_user);
+    let mut second_client = common::create_client(&server, &second_user);
+
+    // Chats stay in the history once delivered
+    let mut chats = Vec::new();
+    for _ in 0..3 {
+        let chat = common::generate_chat(&first_user, &second_user);
+        first_client
+            .send_message(Message::Chat(chat.clone()))
+            .unwrap();
+        let sent = second_client.read_chat().unwrap();
+        common::assert_delivered(&chat, &sent);
+        chats.push(sent);
+    }
+
+    let with = People::User(first_user.clone());
+    second_client
+        .send_message(Message::History(History::new(with.clone(), 2)))
+        .unwrap();
+    assert_eq!(
+        ServerMessage::History(chats[1..].to_vec()),
+        second_client.read_event().unwrap()
+    );
+
+    let mut history = History::new(with, 2);
+    history.set_bound(HistoryBound::Before(chats[1].get_id().unwrap()));
+    second_client
+        .send_message(Message::History(history))
+        .unwrap();
+    assert_eq!(
+        ServerMessage::History(chats[..1].to_vec()),
+        second_client.read_event().unwrap()
+    );
+}
+
+#[test]
+fn test_history_not_member() {
+    let server = common::start_server();
+
+    let group = common::generate_group();
+    let first_user = common::generate_user();
+    let second_user = common::generate_user();
+
+    let mut first_client = common::create_client(&server, &first_user);
+    let mut second_client = common::create_client(&server, &second_user);
+
+    first_client
+        .send_message(Message::Join(common::create_join(&group)))
+        .unwrap();
+    let chat = common::generate_group_chat(&first_user, &group);
+    first_client.send_message(Message::Chat(chat)).unwrap();
+
+    // Only members get to read the history of a group
+    let history = History::new(People::Group(group.clone()), 10);
+    second_client
+        .send_message(Message::History(history))
+        .unwrap();
+    match second_client.read_event().unwrap() {
+        ServerMessage::Error(err) => assert_eq!(ErrorCode::NotFound, err.get_code()),
+        event => panic!("unexpected event: {:?}", event),
+    }
+}
+
+#[test]
+fn test_chat_impersonation() {
+    let server = common::start_server();
+
+    let first_user = common::generate_user();
+    let second_user = common::generate_user();
+    let third_user = common::generate_user();
+
+    let mut third_client = common::create_client(&server, &third_user);
+
+    // First connects over a raw socket, and sends a chat claiming to be from Second
+    let chat = common::generate_chat(&second_user, &third_user);
+    let mut stream = common::connect_raw(&server);
+    stream
+        .write_all(&common::encode_register(&first_user))
+        .unwrap();
+    stream
+        .write_all(&common::encode_frame(&Message::Chat(chat.clone())))
+        .unwrap();
+
+    // Third receives the chat as sent by First
     let sent = third_client.read_chat().unwrap();
-    assert_eq!(chat, sent);
+    assert_eq!(&first_user, sent.get_sender());
+    assert_eq!(chat.get_body(), sent.get_body());
+}
+
+#[test]
+fn test_ack() {
+    let server = common::start_server();
+
+    let group = common::generate_group();
+    let user = common::generate_user();
+    let mut client = common::create_client(&server, &user);
+
+    // Joining the group is acknowledged
+    client
+        .send_message(Message::Join(common::create_join(&group)))
+        .unwrap();
+    assert_eq!(ServerMessage::Ack(None), client.read_event().unwrap());
+
+    // Chats are acknowledged with the id they were given
+    let chat = common::generate_group_chat(&user, &group);
+    client.send_message(Message::Chat(chat)).unwrap();
+    match client.read_event().unwrap() {
+        ServerMessage::Ack(Some(_)) => {}
+        event => panic!("unexpected event: {:?}", event),
+    }
+}
+
+#[test]
+fn test_chat_unacknowledged() {
+    let server = common::start_server();
+
+    let first_user = common::generate_user();
+    let second_user = common::generate_user();
+
+    let mut first_client = common::create_client(&server, &first_user);
+    let chat = common::generate_chat(&first_user, &second_user);
+    first_client
+        .send_message(Message::Chat(chat.clone()))
+        .unwrap();
+
+    // The second user reads the chat, but disconnects before acknowledging it
+    let mut stream = common::connect_raw(&server);
+    stream
+        .write_all(&common::encode_register(&second_user))
+        .unwrap();
+    let mut reader = FrameReader::new(stream, Codec::default());
+    let event: ServerMessage = bincode::deserialize(&reader.read_frame().unwrap()).unwrap();
+    match event {
+        ServerMessage::Session(_) => {}
+        event => panic!("unexpected event: {:?}", event),
+    }
+    let event: ServerMessage = bincode::deserialize(&reader.read_frame().unwrap()).unwrap();
+    let first_delivery = match event {
+        ServerMessage::Chat(sent) => sent,
+        event => panic!("unexpected event: {:?}", event),
+    };
+    drop(reader);
+
+    // So the chat is delivered again, with the same id
+    let mut second_client = common::create_client(&server, &second_user);
+    let sent = second_client.read_chat().unwrap();
+    common::assert_delivered(&chat, &sent);
+    assert_eq!(first_delivery.get_id(), sent.get_id());
+}
+
+#[test]
+fn test_error_unknown_group() {
+    let server = common::start_server();
+
+    let group = common::generate_group();
+    let user = common::generate_user();
+    let mut client = common::create_client(&server, &user);
+
+    // Nobody ever joined the group, so the chat is rejected
+    let chat = common::generate_group_chat(&user, &group);
+    client.send_message(Message::Chat(chat)).unwrap();
+    match client.read_event().unwrap() {
+        ServerMessage::Error(err) => assert_eq!(ErrorCode::NotFound, err.get_code()),
+        event => panic!("unexpected event: {:?}", event),
+    }
+}
+
+#[test]
+fn test_error_malformed() {
+    let server = common::start_server();
+
+    let group = common::generate_group();
+    let user = common::generate_user();
+
+    // A garbage frame is rejected, without closing the connection
+    let mut stream = common::connect_raw(&server);
+    stream.write_all(&common::encode_register(&user)).unwrap();
+    stream
+        .write_all(&Codec::default().encode(&[0xff; 16]).unwrap())
+        .unwrap();
+    stream
+        .write_all(&common::encode_frame(&Message::Join(common::create_join(
+            &group,
+        ))))
+        .unwrap();
+
+    let mut reader = FrameReader::new(stream, Codec::default());
+    let event: ServerMessage = bincode::deserialize(&reader.read_frame().unwrap()).unwrap();
+    match event {
+        ServerMessage::Session(_) => {}
+        event => panic!("unexpected event: {:?}", event),
+    }
+    let event: ServerMessage = bincode::deserialize(&reader.read_frame().unwrap()).unwrap();
+    match event {
+        ServerMessage::Error(err) => assert_eq!(ErrorCode::Malformed, err.get_code()),
+        event => panic!("unexpected event: {:?}", event),
+    }
+    let event: ServerMessage = bincode::deserialize(&reader.read_frame().unwrap()).unwrap();
+    assert_eq!(ServerMessage::Ack(None), event);
+}
+
+#[test]
+fn test_server_shutdown() {
+    let server = common::start_server();
+    let port = common::get_port(&server);
+    let user = common::generate_user();
+    let mut client = common::create_client(&server, &user);
+
+    // The client is told before its connection is closed, and no new one is accepted
+    server.shutdown();
+    match client.read_event().unwrap() {
+        ServerMessage::Notice(_) => {}
+        event => panic!("unexpected event: {:?}", event),
+    }
+    assert!(client.read_event().is_err());
+    assert!(Client::login(common::HOST, &port, user.get_username(), common::PASSWORD).is_err());
 }
@@ -1,22 +1,70 @@
-use std::sync::Mutex;
+#![allow(dead_code)]
+
+use std::error::Error;
+use std::net::TcpStream;
 
-use lazy_static::lazy_static;
 use rand::{distributions::Alphanumeric, thread_rng, Rng};
+use serde::Serialize;
 
 use conver::client::Client;
-use conver::message::{Chat, Join};
+use conver::frame::Codec;
+use conver::message::{Chat, Credentials, ErrorCode, Handshake, Join, ServerError};
 use conver::people::{Group, People, User};
+use conver::server::{Server, ServerHandle};
+use conver::store::MemoryStore;
+
+pub const HOST: &str = "127.0.0.1";
+
+pub const PASSWORD: &str = "correct horse battery staple";
+
+/// Starts a server of the test's own on a free port, with an empty store.
+pub fn start_server() -> ServerHandle {
+    Server::new(HOST, "0", Box::new(MemoryStore::new()))
+        .spawn()
+        .unwrap()
+}
 
-lazy_static! {
-    // To ensure tests not run in parallel, as all clients connect to the same server process
-    pub static ref TEST_LOCK: Mutex<()> = Mutex::new(());
+pub fn get_port(server: &ServerHandle) -> String {
+    server.get_address().port().to_string()
 }
 
-const HOST: &str = "127.0.0.1";
-const PORT: &str = "7878";
+pub fn create_client(server: &ServerHandle, user: &User) -> Client {
+    create_client_on(&get_port(server), user)
+}
+
+/// Connects as `user`, registering them on their first connection.
+pub fn create_client_on(port: &str, user: &User) -> Client {
+    Client::register(HOST, port, user.get_username(), PASSWORD)
+        .or_else(|_| Client::login(HOST, port, user.get_username(), PASSWORD))
+        .unwrap()
+}
+
+pub fn connect_raw(server: &ServerHandle) -> TcpStream {
+    let stream = TcpStream::connect(server.get_address()).unwrap();
+    stream.set_nodelay(true).unwrap();
+    stream
+}
 
-pub fn create_client(user: &User) -> Client {
-    Client::new(HOST, PORT, user.get_username()).unwrap()
+pub fn encode_frame<T: Serialize>(value: &T) -> Vec<u8> {
+    let payload = bincode::serialize(value).unwrap();
+    Codec::default().encode(&payload).unwrap()
+}
+
+/// Asserts the server turned down the connection with an error of `code`.
+pub fn assert_rejected(result: Result<Client, Box<dyn Error>>, code: ErrorCode) {
+    match result {
+        Ok(_) => panic!("connection was not rejected"),
+        Err(err) => match err.downcast_ref::<ServerError>() {
+            Some(err) => assert_eq!(code, err.get_code()),
+            None => panic!("unexpected error: {}", err),
+        },
+    }
+}
+
+/// The handshake registering `user`, who must not be registered yet.
+pub fn encode_register(user: &User) -> Vec<u8> {
+    let credentials = Credentials::new(user.clone(), PASSWORD.into());
+    encode_frame(&Handshake::Register(credentials))
 }
 
 pub fn generate_user() -> User {
@@ -34,11 +82,29 @@ pub fn generate_chat(sender: &User, receiver: &User) -> Chat {
     Chat::new(sender.clone(), People::User(receiver.clone()), body)
 }
 
+pub fn generate_long_chat(sender: &User, receiver: &User) -> Chat {
+    let body = thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(64 * 1024)
+        .collect();
+    Chat::new(sender.clone(), People::User(receiver.clone()), body)
+}
+
 pub fn generate_group_chat(sender: &User, receiver: &Group) -> Chat {
     let body = thread_rng().sample_iter(&Alphanumeric).take(512).collect();
     Chat::new(sender.clone(), People::Group(receiver.clone()), body)
 }
 
-pub fn create_join(sender: &User, group: &Group) -> Join {
-    Join::new(sender.clone(), group.clone())
+pub fn create_join(group: &Group) -> Join {
+    Join::new(group.clone())
+}
+
+/// Asserts `received` is the delivery of `sent`, which only differs in what the server stamped.
+pub fn assert_delivered(sent: &Chat, received: &Chat) {
+    assert!(received.get_id().is_some());
+    assert!(received.get_sent_at().is_some());
+    assert_eq!(sent.get_claimed_at(), received.get_claimed_at());
+    assert_eq!(sent.get_sender(), received.get_sender());
+    assert_eq!(sent.get_receiver(), received.get_receiver());
+    assert_eq!(sent.get_body(), received.get_body());
 }
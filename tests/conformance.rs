@@ -0,0 +1,667 @@
+//! The behavior every `Store` must agree on, checked against every backend.
+//!
+//! The Redis backend runs against a `redis-server` of its own for every test. Its tests are
+//! ignored unless asked for with `cargo test -- --ignored`, and fail if no `redis-server` is found
+//! on the `PATH` then.
+
+use std::fs;
+use std::path::PathBuf;
+use std::process::{self, Child, Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::{thread, time};
+
+use ::redis::Commands;
+
+use conver::message::{HistoryBound, MessageId, Receipt, Role, Timestamp};
+use conver::people::{Group, People, User};
+use conver::store::{Conversation, LogStore, MemoryStore, RedisStore, Store, StoreError};
+
+mod common;
+
+// tests run at once, each with a redis-server of its own on the next port
+const FIRST_REDIS_PORT: usize = 7890;
+
+static NEXT_REDIS_PORT: AtomicUsize = AtomicUsize::new(FIRST_REDIS_PORT);
+
+/// A store to run a test against, along with the server it may talk to.
+struct Fixture<S: Store> {
+    store: S,
+    _server: Option<RedisServer>,
+}
+
+impl<S: Store> Fixture<S> {
+    fn new(store: S) -> Self {
+        Fixture {
+            store,
+            _server: None,
+        }
+    }
+}
+
+/// A `redis-server` process, killed once dropped.
+struct RedisServer {
+    child: Child,
+}
+
+impl RedisServer {
+    /// Starts a server that keeps nothing on disk.
+    fn start(port: usize) -> Self {
+        let child = Command::new("redis-server")
+            .args([
+                "--port",
+                &port.to_string(),
+                "--save",
+                "",
+                "--appendonly",
+                "no",
+            ])
+            .stdout(Stdio::null())
+            .spawn();
+        match child {
+            Ok(child) => RedisServer { child },
+            Err(err) => panic!("failed to start redis-server: {}", err),
+        }
+    }
+}
+
+impl Drop for RedisServer {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+fn open_memory(_: &str) -> Fixture<MemoryStore> {
+    Fixture::new(MemoryStore::new())
+}
+
+fn open_log(test: &str) -> Fixture<LogStore> {
+    let dir = temp_path(&format!("conformance-log-{}", test));
+    let _ = fs::remove_dir_all(&dir);
+    Fixture::new(LogStore::new(&dir).unwrap())
+}
+
+#[cfg(feature = "sqlite")]
+fn open_sqlite(test: &str) -> Fixture<conver::store::SqliteStore> {
+    let path = temp_path(&format!("conformance-{}.db", test));
+    let _ = fs::remove_file(&path);
+    let store = conver::store::SqliteStore::new(path.to_str().unwrap()).unwrap();
+    Fixture::new(store)
+}
+
+fn open_redis(_: &str) -> Fixture<RedisStore> {
+    let (server, url) = start_redis();
+    Fixture {
+        store: RedisStore::new(&url).unwrap(),
+        _server: Some(server),
+    }
+}
+
+/// Starts a `redis-server` on the next port, returning it along with its URL once it accepts
+/// connections.
+fn start_redis() -> (RedisServer, String) {
+    let port = NEXT_REDIS_PORT.fetch_add(1, Ordering::SeqCst);
+    let server = RedisServer::start(port);
+
+    // the server takes a moment to accept connections
+    let url = format!("redis://127.0.0.1:{}/", port);
+    for _ in 0..50 {
+        let client = ::redis::Client::open(url.as_str()).unwrap();
+        if client.get_connection().is_ok() {
+            return (server, url);
+        }
+        thread::sleep(time::Duration::from_millis(20));
+    }
+    panic!("redis-server on port {} never accepted connections", port);
+}
+
+fn temp_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("conver-{}-{}", process::id(), name))
+}
+
+fn sorted(mut users: Vec<User>) -> Vec<User> {
+    users.sort_by(|a, b| a.get_username().cmp(b.get_username()));
+    users
+}
+
+/// Runs every test of the suite against the store `$open` returns for the name of the test, with
+/// the attributes in brackets on every test.
+macro_rules! conformance {
+    ($(#[$attr:meta])* $backend:ident, $open:expr $(, [$(#[$test_attr:meta])*])?) => {
+        $(#[$attr])*
+        mod $backend {
+            conformance!(
+                @tests $open,
+                [$($(#[$test_attr])*)?],
+                queue_order,
+                unknown_user,
+                group_fan_out,
+                unknown_group,
+                join_idempotence,
+                leave_idempotence,
+                message_ids,
+                history,
+                sent_chats,
+                next_chat_after,
+                drop_chat,
+                read_cursors,
+                archived_chats,
+                receipts,
+                group_roles,
+                group_bans
+            );
+        }
+    };
+    (@tests $open:expr, $test_attrs:tt, $($test:ident),*) => {
+        $(conformance!(@test $open, $test_attrs, $test);)*
+    };
+    (@test $open:expr, [$(#[$test_attr:meta])*], $test:ident) => {
+        #[test]
+        $(#[$test_attr])*
+        fn $test() {
+            super::$test(&mut $open(stringify!($test)).store);
+        }
+    };
+}
+
+conformance!(memory, super::open_memory);
+conformance!(log, super::open_log);
+conformance!(
+    #[cfg(feature = "sqlite")]
+    sqlite,
+    super::open_sqlite
+);
+conformance!(
+    redis,
+    super::open_redis,
+    [#[ignore = "needs redis-server, run with --ignored"]]
+);
+
+fn queue_order(store: &mut impl Store) {
+    let first_user = common::generate_user();
+    let second_user = common::generate_user();
+
+    let chats: Vec<_> = (0..3)
+        .map(|_| common::generate_chat(&first_user, &second_user))
+        .collect();
+    for chat in chats.iter() {
+        store.queue_chat(&second_user, chat.clone()).unwrap();
+    }
+
+    // Chats come out in the order they were queued, and only for their receiver
+    assert_eq!(None, store.front_chat(&first_user).unwrap());
+    for chat in chats.iter() {
+        assert_eq!(Some(chat), store.front_chat(&second_user).unwrap().as_ref());
+        store.dequeue_chat(&second_user).unwrap();
+    }
+    assert_eq!(None, store.front_chat(&second_user).unwrap());
+    match store.dequeue_chat(&second_user) {
+        Err(StoreError::NotFound(_)) => {}
+        result => panic!("dequeued from an emptied queue: {:?}", result),
+    }
+}
+
+fn unknown_user(store: &mut impl Store) {
+    let user = common::generate_user();
+
+    assert_eq!(None, store.front_chat(&user).unwrap());
+    match store.dequeue_chat(&user) {
+        Err(StoreError::NotFound(_)) => {}
+        result => panic!("dequeued from an unknown queue: {:?}", result),
+    }
+    assert_eq!(None, store.get_password_hash(&user).unwrap());
+    assert_eq!(None, store.get_public_key(&user).unwrap());
+}
+
+fn group_fan_out(store: &mut impl Store) {
+    let users: Vec<_> = (0..3).map(|_| common::generate_user()).collect();
+    let group = common::generate_group();
+    for user in users.iter() {
+        store.add_group_member(user.clone(), &group).unwrap();
+    }
+    assert_eq!(
+        sorted(users.clone()),
+        sorted(store.get_group_members(&group).unwrap())
+    );
+
+    // Every member but the sender gets the chat, once
+    let chat = common::generate_group_chat(&users[0], &group);
+    store.queue_group_chat(&group, chat.clone()).unwrap();
+    assert_eq!(None, store.front_chat(&users[0]).unwrap());
+    for user in users[1..].iter() {
+        assert_eq!(Some(&chat), store.front_chat(user).unwrap().as_ref());
+        store.dequeue_chat(user).unwrap();
+        assert_eq!(None, store.front_chat(user).unwrap());
+    }
+}
+
+fn unknown_group(store: &mut impl Store) {
+    let user = common::generate_user();
+    let group = common::generate_group();
+
+    assert!(store.get_group_members(&group).unwrap().is_empty());
+    match store.queue_group_chat(&group, common::generate_group_chat(&user, &group)) {
+        Err(StoreError::NotFound(_)) => {}
+        result => panic!("queued a chat to an unknown group: {:?}", result),
+    }
+    store.remove_group_member(&user, &group).unwrap();
+    assert!(store.get_group_members(&group).unwrap().is_empty());
+}
+
+fn join_idempotence(store: &mut impl Store) {
+    let first_user = common::generate_user();
+    let second_user = common::generate_user();
+    let group = common::generate_group();
+
+    // Joining twice makes a single member, who gets every chat a single time
+    store.add_group_member(first_user.clone(), &group).unwrap();
+    store.add_group_member(second_user.clone(), &group).unwrap();
+    store.add_group_member(second_user.clone(), &group).unwrap();
+    assert_eq!(2, store.get_group_members(&group).unwrap().len());
+
+    let chat = common::generate_group_chat(&first_user, &group);
+    store.queue_group_chat(&group, chat).unwrap();
+    store.dequeue_chat(&second_user).unwrap();
+    assert_eq!(None, store.front_chat(&second_user).unwrap());
+}
+
+fn leave_idempotence(store: &mut impl Store) {
+    let first_user = common::generate_user();
+    let second_user = common::generate_user();
+    let group = common::generate_group();
+
+    store.add_group_member(first_user.clone(), &group).unwrap();
+    store.add_group_member(second_user.clone(), &group).unwrap();
+
+    // Leaving twice, or a group never joined, is no error
+    store.remove_group_member(&second_user, &group).unwrap();
+    store.remove_group_member(&second_user, &group).unwrap();
+    assert_eq!(
+        vec![first_user.clone()],
+        store.get_group_members(&group).unwrap()
+    );
+    let chat = common::generate_group_chat(&first_user, &group);
+    store.queue_group_chat(&group, chat).unwrap();
+    assert_eq!(None, store.front_chat(&second_user).unwrap());
+
+    // The group is gone along with its last member
+    store.remove_group_member(&first_user, &group).unwrap();
+    assert!(store.get_group_members(&group).unwrap().is_empty());
+    let chat = common::generate_group_chat(&first_user, &group);
+    match store.queue_group_chat(&group, chat) {
+        Err(StoreError::NotFound(_)) => {}
+        result => panic!("queued a chat to an abandoned group: {:?}", result),
+    }
+}
+
+fn message_ids(store: &mut impl Store) {
+    let mut last_id = store.next_message_id().unwrap();
+    for _ in 0..10 {
+        let id = store.next_message_id().unwrap();
+        assert!(id > last_id);
+        last_id = id;
+    }
+}
+
+fn history(store: &mut impl Store) {
+    let first_user = common::generate_user();
+    let second_user = common::generate_user();
+
+    let mut chats = Vec::new();
+    for i in 0..6 {
+        let mut chat = if i % 2 == 0 {
+            common::generate_chat(&first_user, &second_user)
+        } else {
+            common::generate_chat(&second_user, &first_user)
+        };
+        chat.set_id(store.next_message_id().unwrap());
+        chat.set_sent_at(Timestamp::new(1000 * (i + 1)));
+        store.archive_chat(&chat).unwrap();
+        chats.push(chat);
+    }
+    let conversation = Conversation::new(&first_user, &People::User(second_user.clone()));
+
+    let page = |bound| store.get_history(&conversation, bound, 2).unwrap();
+    let id = |i: usize| chats[i].get_id().unwrap();
+    assert_eq!(&chats[4..], &page(None)[..]);
+    assert_eq!(&chats[1..3], &page(Some(HistoryBound::Before(id(3))))[..]);
+    assert_eq!(&chats[4..], &page(Some(HistoryBound::After(id(3))))[..]);
+    let time = Timestamp::new(3000);
+    assert_eq!(&chats[..2], &page(Some(HistoryBound::BeforeTime(time)))[..]);
+    assert_eq!(&chats[3..5], &page(Some(HistoryBound::AfterTime(time)))[..]);
+}
+
+fn sent_chats(store: &mut impl Store) {
+    let users: Vec<_> = (0..3).map(|_| common::generate_user()).collect();
+    let group = common::generate_group();
+    for user in users.iter() {
+        store.add_group_member(user.clone(), &group).unwrap();
+    }
+
+    // A chat sent again, as after a failure, is queued and archived once
+    let mut chats = [
+        common::generate_chat(&users[0], &users[1]),
+        common::generate_group_chat(&users[0], &group),
+    ];
+    for chat in chats.iter_mut() {
+        chat.set_id(store.next_message_id().unwrap());
+        store.send_chat(chat).unwrap();
+        store.send_chat(chat).unwrap();
+        assert_eq!(
+            Some(&*chat),
+            store.get_chat(chat.get_id().unwrap()).unwrap().as_ref()
+        );
+    }
+    assert_eq!(None, store.front_chat(&users[0]).unwrap());
+    for chat in chats.iter() {
+        assert_eq!(Some(chat), store.front_chat(&users[1]).unwrap().as_ref());
+        store.dequeue_chat(&users[1]).unwrap();
+    }
+    assert_eq!(None, store.front_chat(&users[1]).unwrap());
+    assert_eq!(
+        Some(&chats[1]),
+        store.front_chat(&users[2]).unwrap().as_ref()
+    );
+    store.dequeue_chat(&users[2]).unwrap();
+    assert_eq!(None, store.front_chat(&users[2]).unwrap());
+
+    let conversation = Conversation::new(&users[0], &People::User(users[1].clone()));
+    assert_eq!(
+        &chats[..1],
+        &store.get_history(&conversation, None, 10).unwrap()[..]
+    );
+    match store.send_chat(&common::generate_group_chat(
+        &users[0],
+        &common::generate_group(),
+    )) {
+        Err(StoreError::NotFound(_)) => {}
+        result => panic!("sent a chat to an unknown group: {:?}", result),
+    }
+}
+
+fn next_chat_after(store: &mut impl Store) {
+    let first_user = common::generate_user();
+    let second_user = common::generate_user();
+
+    let mut chats = Vec::new();
+    for _ in 0..3 {
+        let mut chat = common::generate_chat(&first_user, &second_user);
+        chat.set_id(store.next_message_id().unwrap());
+        store.queue_chat(&second_user, chat.clone()).unwrap();
+        chats.push(chat);
+    }
+
+    // Pending chats are found past any id, queued or not, without being dequeued
+    let id = |i: usize| chats[i].get_id().unwrap();
+    let after = |id| store.next_chat_after(&second_user, id).unwrap();
+    assert_eq!(Some(&chats[0]), after(MessageId::new(0)).as_ref());
+    assert_eq!(Some(&chats[2]), after(id(1)).as_ref());
+    assert_eq!(None, after(id(2)));
+    assert_eq!(
+        Some(&chats[0]),
+        store.front_chat(&second_user).unwrap().as_ref()
+    );
+    assert_eq!(None, store.next_chat_after(&first_user, id(0)).unwrap());
+}
+
+fn drop_chat(store: &mut impl Store) {
+    let first_user = common::generate_user();
+    let second_user = common::generate_user();
+
+    // enough chats for a store to look past the first few it fetches
+    let mut chats = Vec::new();
+    for _ in 0..100 {
+        let mut chat = common::generate_chat(&first_user, &second_user);
+        chat.set_id(store.next_message_id().unwrap());
+        store.queue_chat(&second_user, chat.clone()).unwrap();
+        chats.push(chat);
+    }
+
+    // Only the chat past the id is dropped, or the front one without an id
+    let id = |i: usize| chats[i].get_id().unwrap();
+    store.drop_chat(&second_user, Some(id(80))).unwrap();
+    assert_eq!(
+        Some(&chats[82]),
+        store
+            .next_chat_after(&second_user, id(80))
+            .unwrap()
+            .as_ref()
+    );
+    assert_eq!(
+        Some(&chats[80]),
+        store
+            .next_chat_after(&second_user, id(79))
+            .unwrap()
+            .as_ref()
+    );
+    store.drop_chat(&second_user, None).unwrap();
+    assert_eq!(
+        Some(&chats[1]),
+        store.front_chat(&second_user).unwrap().as_ref()
+    );
+
+    match store.drop_chat(&second_user, Some(id(99))) {
+        Err(StoreError::NotFound(_)) => {}
+        result => panic!("dropped a chat past the last one: {:?}", result),
+    }
+    match store.drop_chat(&first_user, None) {
+        Err(StoreError::NotFound(_)) => {}
+        result => panic!("dropped a chat from an empty queue: {:?}", result),
+    }
+}
+
+fn read_cursors(store: &mut impl Store) {
+    let first_user = common::generate_user();
+    let second_user = common::generate_user();
+    let id = MessageId::new(42);
+
+    assert!(store.get_read_cursors(&first_user).unwrap().is_empty());
+
+    // Cursors are kept per device, and per user
+    store.set_read_cursor(&first_user, "phone", None).unwrap();
+    store.set_read_cursor(&first_user, "laptop", None).unwrap();
+    store
+        .set_read_cursor(&first_user, "laptop", Some(id))
+        .unwrap();
+    store
+        .set_read_cursor(&second_user, "phone", Some(id))
+        .unwrap();
+    let read_cursors = store.get_read_cursors(&first_user).unwrap();
+    assert_eq!(2, read_cursors.len());
+    assert_eq!(Some(&None), read_cursors.get("phone"));
+    assert_eq!(Some(&Some(id)), read_cursors.get("laptop"));
+
+    // Removing a device, twice or never added, is no error
+    store.remove_read_cursor(&first_user, "phone").unwrap();
+    store.remove_read_cursor(&first_user, "phone").unwrap();
+    store.remove_read_cursor(&first_user, "tablet").unwrap();
+    let read_cursors = store.get_read_cursors(&first_user).unwrap();
+    assert_eq!(vec!["laptop"], read_cursors.keys().collect::<Vec<_>>());
+    assert_eq!(1, store.get_read_cursors(&second_user).unwrap().len());
+}
+
+fn archived_chats(store: &mut impl Store) {
+    let first_user = common::generate_user();
+    let second_user = common::generate_user();
+    let group = common::generate_group();
+
+    let mut chats = Vec::new();
+    for mut chat in [
+        common::generate_chat(&first_user, &second_user),
+        common::generate_group_chat(&first_user, &group),
+    ] {
+        chat.set_id(store.next_message_id().unwrap());
+        store.archive_chat(&chat).unwrap();
+        chats.push(chat);
+    }
+
+    // Archived chats are found by id, whatever conversation they're in
+    for chat in chats.iter() {
+        assert_eq!(
+            Some(chat),
+            store.get_chat(chat.get_id().unwrap()).unwrap().as_ref()
+        );
+    }
+    let unknown_id = store.next_message_id().unwrap();
+    assert_eq!(None, store.get_chat(unknown_id).unwrap());
+}
+
+fn receipts(store: &mut impl Store) {
+    let first_user = common::generate_user();
+    let second_user = common::generate_user();
+    let third_user = common::generate_user();
+    let id = store.next_message_id().unwrap();
+
+    assert!(store.take_receipts(&first_user).unwrap().is_empty());
+
+    // A reader makes a single receipt of a chat, which is taken once
+    let receipt = |reader: &User, millis| Receipt::new(id, reader.clone(), Timestamp::new(millis));
+    let receipts = vec![receipt(&second_user, 1000), receipt(&third_user, 2000)];
+    assert!(store.add_receipt(&first_user, &receipts[0]).unwrap());
+    assert!(!store
+        .add_receipt(&first_user, &receipt(&second_user, 1500))
+        .unwrap());
+    assert!(store.add_receipt(&first_user, &receipts[1]).unwrap());
+    assert!(store.take_receipts(&second_user).unwrap().is_empty());
+    assert_eq!(receipts, store.take_receipts(&first_user).unwrap());
+    assert!(store.take_receipts(&first_user).unwrap().is_empty());
+    assert!(!store.add_receipt(&first_user, &receipts[0]).unwrap());
+}
+
+fn group_roles(store: &mut impl Store) {
+    let first_user = common::generate_user();
+    let second_user = common::generate_user();
+    let group = common::generate_group();
+
+    // Members join as plain members, and keep their role when joining again
+    assert_eq!(None, store.get_group_role(&first_user, &group).unwrap());
+    store.add_group_member(first_user.clone(), &group).unwrap();
+    store.add_group_member(second_user.clone(), &group).unwrap();
+    assert_eq!(
+        Some(Role::Member),
+        store.get_group_role(&first_user, &group).unwrap()
+    );
+    store
+        .set_group_role(&first_user, &group, Role::Owner)
+        .unwrap();
+    store
+        .set_group_role(&second_user, &group, Role::Admin)
+        .unwrap();
+    store.add_group_member(first_user.clone(), &group).unwrap();
+    assert_eq!(
+        Some(Role::Owner),
+        store.get_group_role(&first_user, &group).unwrap()
+    );
+    store
+        .set_group_role(&second_user, &group, Role::Member)
+        .unwrap();
+    assert_eq!(
+        Some(Role::Member),
+        store.get_group_role(&second_user, &group).unwrap()
+    );
+
+    // The role goes along with the member
+    store.remove_group_member(&first_user, &group).unwrap();
+    assert_eq!(None, store.get_group_role(&first_user, &group).unwrap());
+    match store.set_group_role(&first_user, &group, Role::Admin) {
+        Err(StoreError::NotFound(_)) => {}
+        result => panic!("gave a role to a non-member: {:?}", result),
+    }
+    store.add_group_member(first_user.clone(), &group).unwrap();
+    assert_eq!(
+        Some(Role::Member),
+        store.get_group_role(&first_user, &group).unwrap()
+    );
+}
+
+fn group_bans(store: &mut impl Store) {
+    let first_user = common::generate_user();
+    let second_user = common::generate_user();
+    let group = common::generate_group();
+
+    // Bans keep members as they are, and don't need the user to be a member
+    store.add_group_member(first_user.clone(), &group).unwrap();
+    assert!(!store.is_banned(&second_user, &group).unwrap());
+    store.set_banned(&second_user, &group, true).unwrap();
+    store.set_banned(&second_user, &group, true).unwrap();
+    assert!(store.is_banned(&second_user, &group).unwrap());
+    store.set_banned(&first_user, &group, true).unwrap();
+    assert_eq!(
+        vec![first_user.clone()],
+        store.get_group_members(&group).unwrap()
+    );
+    store.set_banned(&first_user, &group, false).unwrap();
+    assert!(!store.is_banned(&first_user, &group).unwrap());
+
+    // The bans go along with the last member
+    store.remove_group_member(&first_user, &group).unwrap();
+    assert!(!store.is_banned(&second_user, &group).unwrap());
+}
+
+// Groups joined before roles were stored, which went without an owner, are handed to their first
+// member by username once a store that keeps them is opened again.
+
+fn assert_first_owns(store: &impl Store, group: &Group, users: &[User]) {
+    let owner = users.iter().min_by_key(|user| user.get_username()).unwrap();
+    for user in users.iter() {
+        let role = if user == owner {
+            Role::Owner
+        } else {
+            Role::Member
+        };
+        assert_eq!(Some(role), store.get_group_role(user, group).unwrap());
+    }
+}
+
+#[cfg(feature = "sqlite")]
+#[test]
+fn sqlite_ownerless_groups() {
+    let path = temp_path("conformance-ownerless_groups.db");
+    let _ = fs::remove_file(&path);
+    let path = path.to_str().unwrap();
+    let users: Vec<_> = (0..3).map(|_| common::generate_user()).collect();
+    let group = common::generate_group();
+    {
+        let mut store = conver::store::SqliteStore::new(path).unwrap();
+        for user in users.iter() {
+            store.add_group_member(user.clone(), &group).unwrap();
+        }
+    }
+
+    // Takes the database back to before group roles
+    let conn = rusqlite::Connection::open(path).unwrap();
+    conn.execute_batch(
+        "DROP INDEX pending_chats_id;
+         ALTER TABLE pending_chats DROP COLUMN id;
+         DROP TABLE group_bans;
+         ALTER TABLE group_members DROP COLUMN role;
+         PRAGMA user_version = 3;",
+    )
+    .unwrap();
+    drop(conn);
+
+    let store = conver::store::SqliteStore::new(path).unwrap();
+    assert_first_owns(&store, &group, &users);
+}
+
+#[test]
+#[ignore = "needs redis-server, run with --ignored"]
+fn redis_ownerless_groups() {
+    let (_server, url) = start_redis();
+    let users: Vec<_> = (0..3).map(|_| common::generate_user()).collect();
+    let group = common::generate_group();
+
+    // Keys as written before group roles, which had no schema version either
+    let mut conn = ::redis::Client::open(url.as_str())
+        .unwrap()
+        .get_connection()
+        .unwrap();
+    for user in users.iter() {
+        let _: () = conn
+            .sadd(format!("group_members:{}", group), user.clone())
+            .unwrap();
+    }
+
+    let store = RedisStore::new(&url).unwrap();
+    assert_first_owns(&store, &group, &users);
+}
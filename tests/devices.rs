@@ -0,0 +1,81 @@
+use conver::client::Client;
+use conver::message::{Message, ServerMessage};
+
+mod common;
+
+use common::HOST;
+
+#[test]
+fn test_devices_fan_out() {
+    let server = common::start_server();
+    let port = common::get_port(&server);
+
+    let first_user = common::generate_user();
+    let second_user = common::generate_user();
+
+    let mut first_client = common::create_client(&server, &first_user);
+    let mut phone = common::create_client(&server, &second_user);
+    let mut laptop =
+        Client::login(HOST, &port, second_user.get_username(), common::PASSWORD).unwrap();
+
+    // Every device the user is connected from gets every chat, in order
+    let chats: Vec<_> = (0..3)
+        .map(|_| common::generate_chat(&first_user, &second_user))
+        .collect();
+    for chat in chats.iter() {
+        first_client
+            .send_message(Message::Chat(chat.clone()))
+            .unwrap();
+    }
+    for chat in chats.iter() {
+        common::assert_delivered(chat, &phone.read_chat().unwrap());
+        common::assert_delivered(chat, &laptop.read_chat().unwrap());
+    }
+}
+
+#[test]
+fn test_devices_catch_up() {
+    let server = common::start_server();
+    let port = common::get_port(&server);
+
+    let first_user = common::generate_user();
+    let second_user = common::generate_user();
+
+    let mut first_client = common::create_client(&server, &first_user);
+    let phone = common::create_client(&server, &second_user);
+    let phone_token = phone.get_session().get_token().to_string();
+    drop(phone);
+    let mut laptop =
+        Client::login(HOST, &port, second_user.get_username(), common::PASSWORD).unwrap();
+
+    // The laptop reads the chats sent while the phone is offline
+    let chats: Vec<_> = (0..2)
+        .map(|_| common::generate_chat(&first_user, &second_user))
+        .collect();
+    for chat in chats.iter() {
+        first_client
+            .send_message(Message::Chat(chat.clone()))
+            .unwrap();
+        common::assert_delivered(chat, &laptop.read_chat().unwrap());
+    }
+    // replies come in order, so the acknowledgements were handled once the join is
+    let join = Message::Join(common::create_join(&common::generate_group()));
+    laptop.send_message(join).unwrap();
+    assert_eq!(ServerMessage::Ack(None), laptop.read_event().unwrap());
+
+    // Which the phone catches up on once back
+    let mut phone = Client::resume(HOST, &port, &phone_token).unwrap();
+    for chat in chats.iter() {
+        common::assert_delivered(chat, &phone.read_chat().unwrap());
+    }
+
+    // While a new device starts from the laptop's cursor, rather than being sent the chats read
+    // already
+    let mut tablet =
+        Client::login(HOST, &port, second_user.get_username(), common::PASSWORD).unwrap();
+    let chat = common::generate_chat(&first_user, &second_user);
+    first_client
+        .send_message(Message::Chat(chat.clone()))
+        .unwrap();
+    common::assert_delivered(&chat, &tablet.read_chat().unwrap());
+}
@@ -0,0 +1,135 @@
+use conver::client::Client;
+use conver::e2e::{self, KeyPair};
+use conver::message::{History, Message, ServerMessage};
+use conver::people::{People, User};
+use conver::server::ServerHandle;
+
+mod common;
+
+fn create_encrypting_client(server: &ServerHandle, user: &User) -> (Client, KeyPair) {
+    let mut client = common::create_client(server, user);
+    let key_pair = KeyPair::generate().unwrap();
+    let secret = *key_pair.get_secret();
+    client.enable_encryption(key_pair).unwrap();
+    // the key is only published once the server acknowledges it
+    while client.read_event().unwrap() != ServerMessage::Ack(None) {}
+    (client, KeyPair::from_secret(secret).unwrap())
+}
+
+fn fetch_public_key(client: &mut Client, user: &User) {
+    client.request_public_key(user).unwrap();
+    loop {
+        if let ServerMessage::PublicKey(ref owner, _) = client.read_event().unwrap() {
+            if owner == user {
+                return;
+            }
+        }
+    }
+}
+
+#[test]
+fn test_e2e_seal() {
+    let sender = KeyPair::generate().unwrap();
+    let sender_key = sender.get_public_key();
+    let key_pair = KeyPair::generate().unwrap();
+    let sealed = e2e::seal(&sender, key_pair.get_public_key(), "Hello, Bob!").unwrap();
+    assert!(!sealed.windows(5).any(|window| window == b"Hello"));
+    assert_eq!("Hello, Bob!", key_pair.open(sender_key, &sealed).unwrap());
+
+    // Nobody else can open it, nor can it be tampered with
+    assert!(KeyPair::generate()
+        .unwrap()
+        .open(sender_key, &sealed)
+        .is_err());
+    let mut tampered = sealed.clone();
+    *tampered.last_mut().unwrap() ^= 1;
+    assert!(key_pair.open(sender_key, &tampered).is_err());
+
+    // Nor passed off as sealed by anyone else, who can't pass theirs off as the sender's either
+    let other = KeyPair::generate().unwrap();
+    assert!(key_pair.open(other.get_public_key(), &sealed).is_err());
+    let forged = e2e::seal(&other, key_pair.get_public_key(), "Hello, Bob!").unwrap();
+    assert!(key_pair.open(sender_key, &forged).is_err());
+
+    // A key pair restored from its secret is the same
+    let restored = KeyPair::from_secret(*key_pair.get_secret()).unwrap();
+    assert_eq!(key_pair.get_public_key(), restored.get_public_key());
+    assert_eq!("Hello, Bob!", restored.open(sender_key, &sealed).unwrap());
+}
+
+#[test]
+fn test_e2e_chat() {
+    let server = common::start_server();
+
+    let first_user = common::generate_user();
+    let second_user = common::generate_user();
+
+    let (mut first_client, first_key_pair) = create_encrypting_client(&server, &first_user);
+    let (mut second_client, second_key_pair) = create_encrypting_client(&server, &second_user);
+
+    // First fetches the key Second published, which is the one Second has
+    fetch_public_key(&mut first_client, &second_user);
+    let public_key = first_client.get_public_key(&second_user).unwrap();
+    assert_eq!(second_key_pair.get_public_key(), &public_key);
+    assert_eq!(
+        second_key_pair.get_public_key().fingerprint(),
+        public_key.fingerprint()
+    );
+
+    // First sends a chat, Second reads it decrypted, having fetched the key of First to make
+    // sure First sealed it
+    let chat = common::generate_chat(&first_user, &second_user);
+    first_client
+        .send_message(Message::Chat(chat.clone()))
+        .unwrap();
+    common::assert_delivered(&chat, &second_client.read_chat().unwrap());
+
+    // The server only has the ciphertext, which is what First finds in their history
+    let history = History::new(People::User(second_user.clone()), 1);
+    first_client
+        .send_message(Message::History(history))
+        .unwrap();
+    let archived = loop {
+        if let ServerMessage::History(chats) = first_client.read_event().unwrap() {
+            break chats;
+        }
+    };
+    assert_eq!(1, archived.len());
+    assert_eq!("", archived[0].get_body());
+    let sealed_body = archived[0].get_sealed_body().unwrap();
+    let first_public_key = first_key_pair.get_public_key();
+    assert_eq!(
+        chat.get_body(),
+        second_key_pair.open(first_public_key, sealed_body).unwrap()
+    );
+}
+
+#[test]
+fn test_e2e_unknown_key() {
+    let server = common::start_server();
+
+    let first_user = common::generate_user();
+    let second_user = common::generate_user();
+
+    let (mut first_client, _) = create_encrypting_client(&server, &first_user);
+    let _second_client = common::create_client(&server, &second_user);
+
+    // First can't send anything before knowing Second's key
+    let chat = common::generate_chat(&first_user, &second_user);
+    assert!(first_client
+        .send_message(Message::Chat(chat.clone()))
+        .is_err());
+
+    // Second never published one, so First still can't
+    fetch_public_key(&mut first_client, &second_user);
+    assert_eq!(None, first_client.get_public_key(&second_user));
+    assert!(first_client.send_message(Message::Chat(chat)).is_err());
+
+    // Chats to groups aren't encrypted
+    let group = common::generate_group();
+    first_client
+        .send_message(Message::Join(common::create_join(&group)))
+        .unwrap();
+    let chat = common::generate_group_chat(&first_user, &group);
+    first_client.send_message(Message::Chat(chat)).unwrap();
+}
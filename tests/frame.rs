@@ -0,0 +1,181 @@
+use std::cmp;
+use std::io::{self, prelude::*};
+use std::{thread, time};
+
+use conver::frame::{Codec, FrameError, FrameReader, FrameWriter};
+use conver::message::Message;
+
+mod common;
+
+/// Hands out at most `chunk_size` bytes per read, failing every other read with `WouldBlock`.
+struct ChunkedReader {
+    data: Vec<u8>,
+    position: usize,
+    chunk_size: usize,
+    would_block: bool,
+}
+
+impl Read for ChunkedReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.would_block = !self.would_block;
+        if self.would_block {
+            return Err(io::Error::new(io::ErrorKind::WouldBlock, "try again"));
+        }
+        let n = cmp::min(
+            cmp::min(self.chunk_size, buf.len()),
+            self.data.len() - self.position,
+        );
+        buf[..n].copy_from_slice(&self.data[self.position..self.position + n]);
+        self.position += n;
+        Ok(n)
+    }
+}
+
+/// Accepts at most `chunk_size` bytes per write, failing every other write with `WouldBlock`.
+struct ChunkedWriter {
+    data: Vec<u8>,
+    chunk_size: usize,
+    would_block: bool,
+}
+
+impl Write for ChunkedWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.would_block = !self.would_block;
+        if self.would_block {
+            return Err(io::Error::new(io::ErrorKind::WouldBlock, "try again"));
+        }
+        let n = cmp::min(self.chunk_size, buf.len());
+        self.data.extend_from_slice(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn read_frame_retrying<R: Read>(reader: &mut FrameReader<R>) -> Vec<u8> {
+    loop {
+        match reader.read_frame() {
+            Ok(payload) => return payload,
+            Err(FrameError::Io(ref err)) if err.kind() == io::ErrorKind::WouldBlock => continue,
+            Err(err) => panic!("{}", err),
+        }
+    }
+}
+
+#[test]
+fn test_reader_partial_reads() {
+    let codec = Codec::default();
+    let payloads: Vec<Vec<u8>> = vec![b"hello".to_vec(), vec![], vec![42; 10_000]];
+
+    for chunk_size in &[1, 3, 7, 4096] {
+        let data = payloads
+            .iter()
+            .flat_map(|payload| codec.encode(payload).unwrap())
+            .collect();
+        let mut reader = FrameReader::new(
+            ChunkedReader {
+                data,
+                position: 0,
+                chunk_size: *chunk_size,
+                would_block: false,
+            },
+            codec,
+        );
+
+        for payload in payloads.iter() {
+            assert_eq!(payload, &read_frame_retrying(&mut reader));
+        }
+    }
+}
+
+#[test]
+fn test_reader_too_large() {
+    let data = Codec::new(1024).encode(&[0; 1024]).unwrap();
+    let mut reader = FrameReader::new(&data[..], Codec::new(512));
+    match reader.read_frame() {
+        Err(FrameError::TooLarge { size, .. }) => assert_eq!(1024, size),
+        _ => panic!("frame larger than the maximum was accepted"),
+    }
+}
+
+#[test]
+fn test_writer_partial_writes() {
+    let codec = Codec::default();
+    let payloads: Vec<Vec<u8>> = vec![b"hello".to_vec(), vec![], vec![42; 10_000]];
+
+    let mut writer = FrameWriter::new(
+        ChunkedWriter {
+            data: Vec::new(),
+            chunk_size: 7,
+            would_block: false,
+        },
+        codec,
+    );
+    for payload in payloads.iter() {
+        let mut result = writer.write_frame(payload);
+        while result.is_err() {
+            result = writer.flush();
+        }
+    }
+
+    let mut reader = FrameReader::new(&writer.get_ref().data[..], codec);
+    for payload in payloads.iter() {
+        assert_eq!(payload, &reader.read_frame().unwrap());
+    }
+}
+
+#[test]
+fn test_server_byte_by_byte() {
+    let server = common::start_server();
+
+    let first_user = common::generate_user();
+    let second_user = common::generate_user();
+
+    let mut second_client = common::create_client(&server, &second_user);
+
+    // First connects over a raw socket, and sends the handshake and a chat one byte at a time
+    let chat = common::generate_chat(&first_user, &second_user);
+    let mut bytes = common::encode_register(&first_user);
+    bytes.extend(common::encode_frame(&Message::Chat(chat.clone())));
+
+    let mut stream = common::connect_raw(&server);
+    for byte in bytes.iter() {
+        stream.write_all(&[*byte]).unwrap();
+        thread::sleep(time::Duration::from_micros(50));
+    }
+
+    let sent = second_client.read_chat().unwrap();
+    common::assert_delivered(&chat, &sent);
+}
+
+#[test]
+fn test_server_odd_chunks() {
+    let server = common::start_server();
+
+    let first_user = common::generate_user();
+    let second_user = common::generate_user();
+
+    let mut second_client = common::create_client(&server, &second_user);
+
+    // First sends the handshake and several chats, split in chunks straddling frame boundaries
+    let chats: Vec<_> = (0..3)
+        .map(|_| common::generate_chat(&first_user, &second_user))
+        .collect();
+    let mut bytes = common::encode_register(&first_user);
+    for chat in chats.iter() {
+        bytes.extend(common::encode_frame(&Message::Chat(chat.clone())));
+    }
+
+    let mut stream = common::connect_raw(&server);
+    for chunk in bytes.chunks(333) {
+        stream.write_all(chunk).unwrap();
+        thread::sleep(time::Duration::from_millis(1));
+    }
+
+    for chat in chats.iter() {
+        let sent = second_client.read_chat().unwrap();
+        common::assert_delivered(chat, &sent);
+    }
+}
@@ -0,0 +1,143 @@
+use conver::client::Client;
+use conver::message::{ErrorCode, Leave, Message, Moderate, Moderation, ServerMessage, Typing};
+use conver::people::{Group, People, User};
+
+mod common;
+
+fn send(client: &mut Client, message: Message) -> ServerMessage {
+    client.send_message(message).unwrap();
+    client.read_event().unwrap()
+}
+
+fn join(client: &mut Client, group: &Group) -> ServerMessage {
+    send(client, Message::Join(common::create_join(group)))
+}
+
+fn moderate(
+    client: &mut Client,
+    group: &Group,
+    user: &User,
+    moderation: Moderation,
+) -> ServerMessage {
+    let moderate = Moderate::new(group.clone(), user.clone(), moderation);
+    send(client, Message::Moderate(moderate))
+}
+
+fn assert_error(code: ErrorCode, reply: ServerMessage) {
+    match reply {
+        ServerMessage::Error(err) => assert_eq!(code, err.get_code()),
+        event => panic!("unexpected event: {:?}", event),
+    }
+}
+
+/// Asserts `client` may neither chat nor type to the group.
+fn assert_shut_out(client: &mut Client, user: &User, group: &Group) {
+    let chat = common::generate_group_chat(user, group);
+    assert_error(ErrorCode::Forbidden, send(client, Message::Chat(chat)));
+    let typing = Typing::new(People::Group(group.clone()));
+    assert_error(ErrorCode::Forbidden, send(client, Message::Typing(typing)));
+}
+
+fn assert_removed(client: &mut Client, group: &Group) {
+    match client.read_event().unwrap() {
+        ServerMessage::Notice(notice) => assert!(notice.contains(&group.to_string())),
+        event => panic!("unexpected event: {:?}", event),
+    }
+}
+
+#[test]
+fn test_groups_moderation() {
+    let server = common::start_server();
+    let users: Vec<_> = (0..4).map(|_| common::generate_user()).collect();
+    let mut clients: Vec<_> = users
+        .iter()
+        .map(|user| common::create_client(&server, user))
+        .collect();
+    let group = common::generate_group();
+
+    // First starts the group, so owns it
+    for client in clients[..3].iter_mut() {
+        assert_eq!(ServerMessage::Ack(None), join(client, &group));
+    }
+    let reply = moderate(&mut clients[1], &group, &users[2], Moderation::Kick);
+    assert_error(ErrorCode::Forbidden, reply);
+    let reply = moderate(&mut clients[3], &group, &users[2], Moderation::Kick);
+    assert_error(ErrorCode::NotFound, reply);
+
+    // Admins moderate plain members, but not the owner
+    let reply = moderate(&mut clients[0], &group, &users[1], Moderation::Promote);
+    assert_eq!(ServerMessage::Ack(None), reply);
+    let reply = moderate(&mut clients[1], &group, &users[2], Moderation::Kick);
+    assert_eq!(ServerMessage::Ack(None), reply);
+    assert_removed(&mut clients[2], &group);
+    let subscribe = Message::Subscribe(People::Group(group.clone()));
+    assert_error(ErrorCode::NotFound, send(&mut clients[2], subscribe));
+    let reply = moderate(&mut clients[1], &group, &users[0], Moderation::Demote);
+    assert_error(ErrorCode::Forbidden, reply);
+    let reply = moderate(&mut clients[1], &group, &users[3], Moderation::Promote);
+    assert_error(ErrorCode::NotFound, reply);
+
+    // Nor once demoted
+    let reply = moderate(&mut clients[0], &group, &users[1], Moderation::Demote);
+    assert_eq!(ServerMessage::Ack(None), reply);
+    assert_eq!(ServerMessage::Ack(None), join(&mut clients[2], &group));
+    let reply = moderate(&mut clients[1], &group, &users[2], Moderation::Kick);
+    assert_error(ErrorCode::Forbidden, reply);
+}
+
+#[test]
+fn test_groups_bans() {
+    let server = common::start_server();
+    let first_user = common::generate_user();
+    let second_user = common::generate_user();
+    let third_user = common::generate_user();
+    let mut first_client = common::create_client(&server, &first_user);
+    let mut second_client = common::create_client(&server, &second_user);
+    let mut third_client = common::create_client(&server, &third_user);
+    let group = common::generate_group();
+
+    assert_eq!(ServerMessage::Ack(None), join(&mut first_client, &group));
+    assert_eq!(ServerMessage::Ack(None), join(&mut second_client, &group));
+    assert_shut_out(&mut third_client, &third_user, &group);
+
+    // Banned users are removed, and kept out, whether they were members or not
+    let reply = moderate(&mut first_client, &group, &second_user, Moderation::Ban);
+    assert_eq!(ServerMessage::Ack(None), reply);
+    assert_removed(&mut second_client, &group);
+    assert_error(ErrorCode::Forbidden, join(&mut second_client, &group));
+    assert_shut_out(&mut second_client, &second_user, &group);
+    let reply = moderate(&mut first_client, &group, &third_user, Moderation::Ban);
+    assert_eq!(ServerMessage::Ack(None), reply);
+    assert_error(ErrorCode::Forbidden, join(&mut third_client, &group));
+
+    // Until the ban is lifted
+    let reply = moderate(&mut first_client, &group, &second_user, Moderation::Unban);
+    assert_eq!(ServerMessage::Ack(None), reply);
+    assert_eq!(ServerMessage::Ack(None), join(&mut second_client, &group));
+}
+
+#[test]
+fn test_groups_ownership() {
+    let server = common::start_server();
+    let first_user = common::generate_user();
+    let second_user = common::generate_user();
+    let third_user = common::generate_user();
+    let mut first_client = common::create_client(&server, &first_user);
+    let mut second_client = common::create_client(&server, &second_user);
+    let mut third_client = common::create_client(&server, &third_user);
+    let group = common::generate_group();
+
+    for client in [&mut first_client, &mut second_client, &mut third_client] {
+        assert_eq!(ServerMessage::Ack(None), join(client, &group));
+    }
+    let reply = moderate(&mut first_client, &group, &second_user, Moderation::Promote);
+    assert_eq!(ServerMessage::Ack(None), reply);
+
+    // The owner leaving hands the group down to the admin, who may now demote admins
+    let leave = Message::Leave(Leave::new(group.clone()));
+    assert_eq!(ServerMessage::Ack(None), send(&mut first_client, leave));
+    let reply = moderate(&mut second_client, &group, &third_user, Moderation::Promote);
+    assert_eq!(ServerMessage::Ack(None), reply);
+    let reply = moderate(&mut second_client, &group, &third_user, Moderation::Demote);
+    assert_eq!(ServerMessage::Ack(None), reply);
+}
@@ -0,0 +1,357 @@
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, OpenOptions};
+use std::io::prelude::*;
+use std::path::PathBuf;
+use std::process;
+
+use conver::e2e::PublicKey;
+use conver::message::{
+    Chat, HistoryBound, Message, MessageId, Role, ServerMessage, Session, Timestamp,
+};
+use conver::people::{Group, People, User};
+use conver::server::Server;
+use conver::store::log::FsyncPolicy;
+use conver::store::{Conversation, LogStore, Store};
+
+mod common;
+
+/// A directory of its own for every test, which starts out missing.
+fn log_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("conver-log-{}-{}", process::id(), name));
+    let _ = fs::remove_dir_all(&dir);
+    dir
+}
+
+#[test]
+fn test_log_recovery() {
+    let dir = log_dir("recovery");
+    let first_user = common::generate_user();
+    let second_user = common::generate_user();
+    let group = common::generate_group();
+
+    let mut chats = Vec::new();
+    {
+        let mut store = LogStore::new(&dir).unwrap();
+        store.add_group_member(first_user.clone(), &group).unwrap();
+        store.add_group_member(second_user.clone(), &group).unwrap();
+        for _ in 0..3 {
+            let mut chat = common::generate_chat(&first_user, &second_user);
+            chat.set_id(store.next_message_id().unwrap());
+            store.queue_chat(&second_user, chat.clone()).unwrap();
+            store.archive_chat(&chat).unwrap();
+            chats.push(chat);
+        }
+        store.dequeue_chat(&second_user).unwrap();
+        store.remove_group_member(&first_user, &group).unwrap();
+        store.create_password_hash(&first_user, "hash").unwrap();
+    }
+
+    // The state is rebuilt from the log, as it was left
+    let mut store = LogStore::new(&dir).unwrap();
+    assert_eq!(
+        Some(chats[1].clone()),
+        store.front_chat(&second_user).unwrap()
+    );
+    assert_eq!(
+        vec![second_user.clone()],
+        store.get_group_members(&group).unwrap()
+    );
+    assert_eq!(
+        Some("hash".to_string()),
+        store.get_password_hash(&first_user).unwrap()
+    );
+    let conversation = Conversation::new(&first_user, &People::User(second_user.clone()));
+    assert_eq!(chats, store.get_history(&conversation, None, 10).unwrap());
+    assert_eq!(4, store.next_message_id().unwrap().get());
+}
+
+#[test]
+fn test_log_torn_record() {
+    let dir = log_dir("torn_record");
+    let first_user = common::generate_user();
+    let second_user = common::generate_user();
+
+    let chat = common::generate_chat(&first_user, &second_user);
+    {
+        let mut store = LogStore::new(&dir).unwrap();
+        store.queue_chat(&second_user, chat.clone()).unwrap();
+    }
+
+    // The server crashed halfway through appending a record
+    let mut log = OpenOptions::new()
+        .append(true)
+        .open(dir.join("log"))
+        .unwrap();
+    log.write_all(&[0, 0, 1, 0, 42, 42]).unwrap();
+    drop(log);
+
+    // The whole records are kept, and the torn one is cut off for new ones to follow
+    let other_chat = common::generate_chat(&first_user, &second_user);
+    {
+        let mut store = LogStore::new(&dir).unwrap();
+        assert_eq!(Some(chat), store.front_chat(&second_user).unwrap());
+        store.dequeue_chat(&second_user).unwrap();
+        store.queue_chat(&second_user, other_chat.clone()).unwrap();
+    }
+    let store = LogStore::new(&dir).unwrap();
+    assert_eq!(Some(other_chat), store.front_chat(&second_user).unwrap());
+}
+
+/// Frames `payload` the way the log does, with its length and checksum ahead of it.
+fn log_record(payload: &[u8]) -> Vec<u8> {
+    let mut crc = !0u32;
+    for byte in payload {
+        crc ^= u32::from(*byte);
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    let mut record = (payload.len() as u32).to_be_bytes().to_vec();
+    record.extend_from_slice(&(!crc).to_be_bytes());
+    record.extend_from_slice(payload);
+    record
+}
+
+#[test]
+fn test_log_old_records() {
+    let dir = log_dir("old_records");
+    let first_user = common::generate_user();
+    let second_user = common::generate_user();
+    let group = common::generate_group();
+
+    let mut chat = common::generate_chat(&first_user, &second_user);
+    chat.set_id(MessageId::new(1));
+
+    // A log written before read cursors, receipts and group roles, whose operations are named by
+    // their index: 0 for NextMessageId, 1 for QueueChat, 4 for ArchiveChat, 5 for
+    // CreatePasswordHash and 9 for AddGroupMember
+    let mut log = Vec::new();
+    log.extend(log_record(&bincode::serialize(&(1u64, 0u32)).unwrap()));
+    log.extend(log_record(
+        &bincode::serialize(&(2u64, 1u32, &second_user, &chat)).unwrap(),
+    ));
+    log.extend(log_record(
+        &bincode::serialize(&(3u64, 4u32, &chat)).unwrap(),
+    ));
+    log.extend(log_record(
+        &bincode::serialize(&(4u64, 5u32, &first_user, "hash")).unwrap(),
+    ));
+    log.extend(log_record(
+        &bincode::serialize(&(5u64, 9u32, &first_user, &group)).unwrap(),
+    ));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("log"), &log).unwrap();
+
+    // Is replayed as it was written, and kept whole
+    let mut store = LogStore::new(&dir).unwrap();
+    assert_eq!(Some(chat.clone()), store.front_chat(&second_user).unwrap());
+    assert_eq!(
+        Some(chat.clone()),
+        store.get_chat(MessageId::new(1)).unwrap()
+    );
+    assert_eq!(
+        Some("hash".to_string()),
+        store.get_password_hash(&first_user).unwrap()
+    );
+    assert_eq!(
+        vec![first_user.clone()],
+        store.get_group_members(&group).unwrap()
+    );
+    assert_eq!(2, store.next_message_id().unwrap().get());
+    drop(store);
+    assert!(fs::read(dir.join("log")).unwrap().starts_with(&log));
+}
+
+#[test]
+fn test_log_unreadable_record() {
+    let dir = log_dir("unreadable_record");
+    let first_user = common::generate_user();
+    let second_user = common::generate_user();
+
+    let chat = common::generate_chat(&first_user, &second_user);
+    {
+        let mut store = LogStore::new(&dir).unwrap();
+        store.queue_chat(&second_user, chat).unwrap();
+    }
+
+    // A whole record of an operation this server doesn't know of
+    let mut log = OpenOptions::new()
+        .append(true)
+        .open(dir.join("log"))
+        .unwrap();
+    log.write_all(&log_record(&bincode::serialize(&(2u64, 999u32)).unwrap()))
+        .unwrap();
+    drop(log);
+    let len = fs::metadata(dir.join("log")).unwrap().len();
+
+    // Fails the store rather than being cut off as if torn
+    assert!(LogStore::new(&dir).is_err());
+    assert_eq!(len, fs::metadata(dir.join("log")).unwrap().len());
+}
+
+#[test]
+fn test_log_compaction() {
+    let dir = log_dir("compaction");
+    let first_user = common::generate_user();
+    let second_user = common::generate_user();
+
+    let mut chats = Vec::new();
+    {
+        let mut store = LogStore::new(&dir).unwrap();
+        store.set_compaction_threshold(10);
+        store.set_fsync_policy(FsyncPolicy::Never);
+        for i in 0..25 {
+            let mut chat = common::generate_chat(&first_user, &second_user);
+            chat.set_id(store.next_message_id().unwrap());
+            chat.set_sent_at(Timestamp::new(i));
+            store.queue_chat(&second_user, chat.clone()).unwrap();
+            chats.push(chat);
+        }
+    }
+
+    // Most operations moved to the snapshot, but none of them got lost
+    assert!(dir.join("snapshot").exists());
+    let log_len = fs::metadata(dir.join("log")).unwrap().len();
+    assert!(log_len < fs::metadata(dir.join("snapshot")).unwrap().len());
+
+    let mut store = LogStore::new(&dir).unwrap();
+    for chat in chats.iter() {
+        assert_eq!(Some(chat.clone()), store.front_chat(&second_user).unwrap());
+        store.dequeue_chat(&second_user).unwrap();
+    }
+    assert_eq!(None, store.front_chat(&second_user).unwrap());
+
+    // Compacting on demand leaves an empty log, which is fine to start from
+    store.compact().unwrap();
+    assert_eq!(0, fs::metadata(dir.join("log")).unwrap().len());
+    let mut store = LogStore::new(&dir).unwrap();
+    assert_eq!(26, store.next_message_id().unwrap().get());
+    let conversation = Conversation::new(&first_user, &People::User(second_user.clone()));
+    let page = store
+        .get_history(
+            &conversation,
+            Some(HistoryBound::After(chats[0].get_id().unwrap())),
+            1,
+        )
+        .unwrap();
+    assert!(page.is_empty());
+}
+
+#[test]
+fn test_log_old_snapshot() {
+    let dir = log_dir("old_snapshot");
+    let first_user = common::generate_user();
+    let second_user = common::generate_user();
+    let group = common::generate_group();
+
+    let mut chats = Vec::new();
+    for id in 1..=3 {
+        let mut chat = common::generate_chat(&first_user, &second_user);
+        chat.set_id(MessageId::new(id));
+        chats.push(chat);
+    }
+    let conversation = Conversation::new(&first_user, &People::User(second_user.clone()));
+
+    // A snapshot of version 1, from before read cursors, receipts and group roles, which laid the
+    // state out field by field
+    let members: HashSet<_> = vec![first_user.clone(), second_user.clone()]
+        .into_iter()
+        .collect();
+    let state = (
+        3u64,
+        vec![(group.clone(), members)]
+            .into_iter()
+            .collect::<HashMap<Group, HashSet<User>>>(),
+        vec![(second_user.clone(), chats[1..].to_vec())]
+            .into_iter()
+            .collect::<HashMap<User, Vec<Chat>>>(),
+        vec![(conversation.clone(), chats.clone())]
+            .into_iter()
+            .collect::<HashMap<Conversation, Vec<Chat>>>(),
+        vec![(first_user.clone(), "hash".to_string())]
+            .into_iter()
+            .collect::<HashMap<User, String>>(),
+        HashMap::<String, Session>::new(),
+        HashMap::<User, PublicKey>::new(),
+    );
+    let mut snapshot = b"conversn".to_vec();
+    snapshot.extend_from_slice(&1u32.to_be_bytes());
+    snapshot.extend(bincode::serialize(&(3u64, state)).unwrap());
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("snapshot"), snapshot).unwrap();
+
+    // Is migrated, with the fields it lacked filled in
+    let mut store = LogStore::new(&dir).unwrap();
+    assert_eq!(
+        Some(chats[1].clone()),
+        store.front_chat(&second_user).unwrap()
+    );
+    assert_eq!(chats, store.get_history(&conversation, None, 10).unwrap());
+    assert_eq!(
+        Some(chats[0].clone()),
+        store.get_chat(chats[0].get_id().unwrap()).unwrap()
+    );
+    assert_eq!(
+        Some("hash".to_string()),
+        store.get_password_hash(&first_user).unwrap()
+    );
+    assert!(store.get_read_cursors(&second_user).unwrap().is_empty());
+    let owner = if first_user.get_username() < second_user.get_username() {
+        &first_user
+    } else {
+        &second_user
+    };
+    assert_eq!(
+        Some(Role::Owner),
+        store.get_group_role(owner, &group).unwrap()
+    );
+    assert_eq!(4, store.next_message_id().unwrap().get());
+
+    // And written back in the current version
+    store.compact().unwrap();
+    let store = LogStore::new(&dir).unwrap();
+    assert_eq!(
+        Some(chats[1].clone()),
+        store.front_chat(&second_user).unwrap()
+    );
+
+    // While a version this server doesn't know of is refused
+    let mut snapshot = b"conversn".to_vec();
+    snapshot.extend_from_slice(&99u32.to_be_bytes());
+    fs::write(dir.join("snapshot"), snapshot).unwrap();
+    assert!(LogStore::new(&dir).is_err());
+}
+
+#[test]
+fn test_log_server() {
+    let dir = log_dir("server");
+    let store = LogStore::new(&dir).unwrap();
+    let server = Server::new(common::HOST, "0", Box::new(store))
+        .spawn()
+        .unwrap();
+
+    let first_user = common::generate_user();
+    let second_user = common::generate_user();
+
+    // Second registers, then goes offline before First sends them a chat
+    drop(common::create_client(&server, &second_user));
+    let mut first_client = common::create_client(&server, &first_user);
+    let chat = common::generate_chat(&first_user, &second_user);
+    first_client
+        .send_message(Message::Chat(chat.clone()))
+        .unwrap();
+    loop {
+        if let ServerMessage::Ack(Some(_)) = first_client.read_event().unwrap() {
+            break;
+        }
+    }
+
+    // Once acknowledged, the chat is on disk, as a server started over would find it
+    let recovered = LogStore::new(&dir).unwrap();
+    let pending = recovered.front_chat(&second_user).unwrap().unwrap();
+    common::assert_delivered(&chat, &pending);
+}
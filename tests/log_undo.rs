@@ -0,0 +1,53 @@
+//! Kept apart from the other log tests, as the file size limit it sets is the process's.
+
+use std::fs;
+use std::process;
+
+use conver::store::{LogStore, Store};
+
+mod common;
+
+#[cfg(unix)]
+fn limit_file_size(limit: libc::rlim_t) {
+    let limit = libc::rlimit {
+        rlim_cur: limit,
+        rlim_max: libc::RLIM_INFINITY,
+    };
+    unsafe {
+        // writes past the limit fail instead of killing the process
+        libc::signal(libc::SIGXFSZ, libc::SIG_IGN);
+        assert_eq!(0, libc::setrlimit(libc::RLIMIT_FSIZE, &limit));
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn test_log_failed_append() {
+    let dir = std::env::temp_dir().join(format!("conver-log-{}-failed_append", process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    let first_user = common::generate_user();
+    let second_user = common::generate_user();
+
+    let chat = common::generate_chat(&first_user, &second_user);
+    let other_chat = common::generate_chat(&first_user, &second_user);
+    let mut store = LogStore::new(&dir).unwrap();
+    store.queue_chat(&second_user, chat.clone()).unwrap();
+
+    // The disk fills up partway through the next record
+    let len = fs::metadata(dir.join("log")).unwrap().len();
+    limit_file_size(len + 4);
+    assert!(store.queue_chat(&second_user, other_chat.clone()).is_err());
+    limit_file_size(libc::RLIM_INFINITY);
+
+    // The operation is undone, and the part of its record written cut off
+    assert_eq!(len, fs::metadata(dir.join("log")).unwrap().len());
+    assert_eq!(Some(chat.clone()), store.front_chat(&second_user).unwrap());
+    store.dequeue_chat(&second_user).unwrap();
+    assert_eq!(None, store.front_chat(&second_user).unwrap());
+
+    // So it may be tried again once there's room
+    store.queue_chat(&second_user, other_chat.clone()).unwrap();
+    drop(store);
+    let store = LogStore::new(&dir).unwrap();
+    assert_eq!(Some(other_chat), store.front_chat(&second_user).unwrap());
+}
@@ -0,0 +1,170 @@
+use conver::client::Client;
+use conver::message::{ErrorCode, Message, Presence, ServerMessage, Status};
+use conver::people::People;
+
+mod common;
+
+use common::HOST;
+
+/// Reads events until the next presence, which must be of a single user.
+fn read_presence(client: &mut Client) -> Presence {
+    loop {
+        match client.read_event().unwrap() {
+            ServerMessage::Presence(mut presences) => {
+                assert_eq!(1, presences.len());
+                return presences.remove(0);
+            }
+            ServerMessage::Ack(_) | ServerMessage::Session(_) => {}
+            event => panic!("unexpected event: {:?}", event),
+        }
+    }
+}
+
+#[test]
+fn test_presence_lifecycle() {
+    let server = common::start_server();
+    let port = common::get_port(&server);
+
+    let first_user = common::generate_user();
+    let second_user = common::generate_user();
+    let mut first_client = common::create_client(&server, &first_user);
+    let mut phone = common::create_client(&server, &second_user);
+
+    let subscribe = Message::Subscribe(People::User(second_user.clone()));
+    first_client.send_message(subscribe).unwrap();
+    let presence = read_presence(&mut first_client);
+    assert_eq!(&second_user, presence.get_user());
+    assert_eq!(Status::Online, presence.get_status());
+
+    // The user is away while every connection of theirs is
+    phone.send_message(Message::SetAway(true)).unwrap();
+    assert_eq!(Status::Away, read_presence(&mut first_client).get_status());
+    let laptop = Client::login(HOST, &port, second_user.get_username(), common::PASSWORD).unwrap();
+    assert_eq!(
+        Status::Online,
+        read_presence(&mut first_client).get_status()
+    );
+    drop(laptop);
+    assert_eq!(Status::Away, read_presence(&mut first_client).get_status());
+
+    // And offline once they're all gone, since when is told
+    drop(phone);
+    let presence = read_presence(&mut first_client);
+    assert_eq!(Status::Offline, presence.get_status());
+    assert!(presence.get_last_seen().is_some());
+}
+
+#[test]
+fn test_presence_group() {
+    let server = common::start_server();
+    let users: Vec<_> = (0..3).map(|_| common::generate_user()).collect();
+    let group = common::generate_group();
+
+    let mut clients: Vec<_> = users
+        .iter()
+        .map(|user| common::create_client(&server, user))
+        .collect();
+    for client in clients[..2].iter_mut() {
+        let join = Message::Join(common::create_join(&group));
+        client.send_message(join).unwrap();
+        assert_eq!(ServerMessage::Ack(None), client.read_event().unwrap());
+    }
+
+    // Only members are told about the other members
+    let subscribe = Message::Subscribe(People::Group(group.clone()));
+    clients[2].send_message(subscribe.clone()).unwrap();
+    match clients[2].read_event().unwrap() {
+        ServerMessage::Error(err) => assert_eq!(ErrorCode::NotFound, err.get_code()),
+        event => panic!("unexpected event: {:?}", event),
+    }
+    clients[0].send_message(subscribe).unwrap();
+    match clients[0].read_event().unwrap() {
+        ServerMessage::Presence(presences) => {
+            assert_eq!(1, presences.len());
+            assert_eq!(&users[1], presences[0].get_user());
+            assert_eq!(Status::Online, presences[0].get_status());
+        }
+        event => panic!("unexpected event: {:?}", event),
+    }
+
+    let second_client = clients.remove(1);
+    drop(second_client);
+    let presence = read_presence(&mut clients[0]);
+    assert_eq!(&users[1], presence.get_user());
+    assert_eq!(Status::Offline, presence.get_status());
+}
+
+#[test]
+fn test_presence_resubscribe() {
+    let server = common::start_server();
+    let port = common::get_port(&server);
+
+    let first_user = common::generate_user();
+    let second_user = common::generate_user();
+    let third_user = common::generate_user();
+    drop(common::create_client(&server, &first_user));
+    let mut second_client = common::create_client(&server, &second_user);
+    let subscribe = Message::Subscribe(People::User(first_user.clone()));
+    second_client.send_message(subscribe).unwrap();
+    assert_eq!(&first_user, read_presence(&mut second_client).get_user());
+
+    // Every connection subscribes anew, and its subscription goes along with it, by the time
+    // it's seen offline
+    let subscribe = Message::Subscribe(People::User(second_user.clone()));
+    for _ in 0..50 {
+        let mut first_client = common::create_client(&server, &first_user);
+        assert_eq!(
+            Status::Online,
+            read_presence(&mut second_client).get_status()
+        );
+        first_client.send_message(subscribe.clone()).unwrap();
+        assert_eq!(&second_user, read_presence(&mut first_client).get_user());
+        drop(first_client);
+        assert_eq!(
+            Status::Offline,
+            read_presence(&mut second_client).get_status()
+        );
+    }
+    let mut first_client =
+        Client::login(HOST, &port, first_user.get_username(), common::PASSWORD).unwrap();
+    assert_eq!(
+        Status::Online,
+        read_presence(&mut second_client).get_status()
+    );
+    second_client.send_message(Message::SetAway(true)).unwrap();
+    assert_eq!(
+        ServerMessage::Ack(None),
+        second_client.read_event().unwrap()
+    );
+
+    // So a connection that never subscribed isn't told, as the first presence it reads is the
+    // reply to its own subscription
+    let subscribe = Message::Subscribe(People::User(third_user.clone()));
+    first_client.send_message(subscribe).unwrap();
+    assert_eq!(&third_user, read_presence(&mut first_client).get_user());
+}
+
+#[test]
+fn test_presence_other_device() {
+    let server = common::start_server();
+    let port = common::get_port(&server);
+
+    let first_user = common::generate_user();
+    let second_user = common::generate_user();
+    let third_user = common::generate_user();
+    let mut phone = common::create_client(&server, &first_user);
+    let mut laptop =
+        Client::login(HOST, &port, first_user.get_username(), common::PASSWORD).unwrap();
+    let mut second_client = common::create_client(&server, &second_user);
+
+    // Only the device that subscribed is told
+    let subscribe = Message::Subscribe(People::User(second_user.clone()));
+    phone.send_message(subscribe).unwrap();
+    assert_eq!(Status::Online, read_presence(&mut phone).get_status());
+    second_client.send_message(Message::SetAway(true)).unwrap();
+    assert_eq!(Status::Away, read_presence(&mut phone).get_status());
+
+    let subscribe = Message::Subscribe(People::User(third_user.clone()));
+    laptop.send_message(subscribe).unwrap();
+    assert_eq!(&third_user, read_presence(&mut laptop).get_user());
+}
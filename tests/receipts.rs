@@ -0,0 +1,145 @@
+use conver::client::Client;
+use conver::message::{ErrorCode, Message, MessageId, Read, Receipt, ServerMessage, Status};
+use conver::people::{People, User};
+
+mod common;
+
+/// Sends a chat, returning the id the server assigned to it.
+fn send_chat(client: &mut Client, message: Message) -> MessageId {
+    client.send_message(message).unwrap();
+    loop {
+        if let ServerMessage::Ack(Some(id)) = client.read_event().unwrap() {
+            return id;
+        }
+    }
+}
+
+fn send_read(client: &mut Client, id: MessageId) -> ServerMessage {
+    client.send_message(Message::Read(Read::new(id))).unwrap();
+    loop {
+        match client.read_event().unwrap() {
+            ServerMessage::Chat(_) => {}
+            reply => return reply,
+        }
+    }
+}
+
+fn read_receipts(client: &mut Client) -> Vec<Receipt> {
+    match client.read_event().unwrap() {
+        ServerMessage::Receipts(receipts) => receipts,
+        event => panic!("unexpected event: {:?}", event),
+    }
+}
+
+fn assert_receipt(receipt: &Receipt, id: MessageId, reader: &User) {
+    assert_eq!(id, receipt.get_message_id());
+    assert_eq!(reader, receipt.get_reader());
+}
+
+#[test]
+fn test_receipts_direct() {
+    let server = common::start_server();
+    let first_user = common::generate_user();
+    let second_user = common::generate_user();
+    let third_user = common::generate_user();
+
+    let mut first_client = common::create_client(&server, &first_user);
+    let mut second_client = common::create_client(&server, &second_user);
+    let mut third_client = common::create_client(&server, &third_user);
+
+    let chat = common::generate_chat(&first_user, &second_user);
+    let id = send_chat(&mut first_client, Message::Chat(chat));
+    second_client.read_chat().unwrap();
+
+    // Only the receiver reads a chat, and only once
+    for client in [&mut first_client, &mut third_client] {
+        match send_read(client, id) {
+            ServerMessage::Error(err) => assert_eq!(ErrorCode::NotFound, err.get_code()),
+            event => panic!("unexpected event: {:?}", event),
+        }
+    }
+    assert_eq!(ServerMessage::Ack(None), send_read(&mut second_client, id));
+    assert_eq!(ServerMessage::Ack(None), send_read(&mut second_client, id));
+
+    let receipts = read_receipts(&mut first_client);
+    assert_eq!(1, receipts.len());
+    assert_receipt(&receipts[0], id, &second_user);
+
+    // So the next receipt is of the next chat
+    let chat = common::generate_chat(&first_user, &second_user);
+    let other_id = send_chat(&mut first_client, Message::Chat(chat));
+    assert_eq!(
+        ServerMessage::Ack(None),
+        send_read(&mut second_client, other_id)
+    );
+    assert_receipt(&read_receipts(&mut first_client)[0], other_id, &second_user);
+}
+
+#[test]
+fn test_receipts_offline_sender() {
+    let server = common::start_server();
+    let first_user = common::generate_user();
+    let second_user = common::generate_user();
+
+    let mut first_client = common::create_client(&server, &first_user);
+    let mut second_client = common::create_client(&server, &second_user);
+    let mut ids = Vec::new();
+    for _ in 0..2 {
+        let chat = common::generate_chat(&first_user, &second_user);
+        ids.push(send_chat(&mut first_client, Message::Chat(chat)));
+    }
+    let subscribe = Message::Subscribe(People::User(first_user.clone()));
+    second_client.send_message(subscribe).unwrap();
+    drop(first_client);
+    loop {
+        match second_client.read_event().unwrap() {
+            ServerMessage::Presence(ref presences)
+                if presences[0].get_status() == Status::Offline =>
+            {
+                break
+            }
+            _ => {}
+        }
+    }
+
+    // Receipts of chats read while the sender is away are kept for them
+    for &id in ids.iter() {
+        assert_eq!(ServerMessage::Ack(None), send_read(&mut second_client, id));
+    }
+    let mut first_client = common::create_client(&server, &first_user);
+    let receipts = read_receipts(&mut first_client);
+    assert_eq!(2, receipts.len());
+    assert_receipt(&receipts[0], ids[0], &second_user);
+    assert_receipt(&receipts[1], ids[1], &second_user);
+}
+
+#[test]
+fn test_receipts_group() {
+    let server = common::start_server();
+    let users: Vec<_> = (0..3).map(|_| common::generate_user()).collect();
+    let group = common::generate_group();
+
+    let mut clients: Vec<_> = users
+        .iter()
+        .map(|user| common::create_client(&server, user))
+        .collect();
+    for client in clients.iter_mut() {
+        let join = Message::Join(common::create_join(&group));
+        client.send_message(join).unwrap();
+        assert_eq!(ServerMessage::Ack(None), client.read_event().unwrap());
+    }
+
+    // Every member who reads the chat makes a receipt of their own
+    let chat = common::generate_group_chat(&users[0], &group);
+    let id = send_chat(&mut clients[0], Message::Chat(chat));
+    for i in [2, 1, 2] {
+        assert_eq!(ServerMessage::Ack(None), send_read(&mut clients[i], id));
+    }
+    let mut receipts = read_receipts(&mut clients[0]);
+    if receipts.len() < 2 {
+        receipts.extend(read_receipts(&mut clients[0]));
+    }
+    assert_eq!(2, receipts.len());
+    assert_receipt(&receipts[0], id, &users[2]);
+    assert_receipt(&receipts[1], id, &users[1]);
+}
@@ -0,0 +1,416 @@
+use std::collections::HashMap;
+use std::io::prelude::*;
+use std::net::Shutdown;
+
+use rand::{thread_rng, Rng, RngCore};
+
+use conver::client::Client;
+use conver::e2e::PublicKey;
+use conver::frame::{Codec, FrameReader};
+use conver::message::{
+    Chat, Credentials, ErrorCode, Handshake, History, HistoryBound, Message, MessageId, Receipt,
+    Role, ServerMessage, Session,
+};
+use conver::people::{Group, People, User};
+use conver::server::{Server, ServerHandle};
+use conver::store::{Conversation, MemoryStore, Store, StoreError};
+
+mod common;
+
+/// How a `FaultyStore` fails.
+enum Fault {
+    /// Panics when asked for history, as a buggy backend could.
+    Panic,
+    /// Sends the first chat, then fails as if it timed out before telling so.
+    TimeOut { timed_out: bool },
+    /// Can't read the chat with the id back once it's queued.
+    Corrupt(MessageId),
+}
+
+struct FaultyStore(MemoryStore, Fault);
+
+impl FaultyStore {
+    fn read(&self, chat: Option<Chat>) -> Result<Option<Chat>, StoreError> {
+        match (chat, &self.1) {
+            (Some(ref chat), Fault::Corrupt(id)) if chat.get_id() == Some(*id) => {
+                Err(StoreError::Serialization("chat is corrupt".into()))
+            }
+            (chat, _) => Ok(chat),
+        }
+    }
+}
+
+impl Store for FaultyStore {
+    fn next_message_id(&mut self) -> Result<MessageId, StoreError> {
+        self.0.next_message_id()
+    }
+
+    fn front_chat(&self, user: &User) -> Result<Option<Chat>, StoreError> {
+        self.read(self.0.front_chat(user)?)
+    }
+
+    fn next_chat_after(&self, user: &User, after: MessageId) -> Result<Option<Chat>, StoreError> {
+        self.read(self.0.next_chat_after(user, after)?)
+    }
+
+    fn queue_chat(&mut self, user: &User, chat: Chat) -> Result<(), StoreError> {
+        self.0.queue_chat(user, chat)
+    }
+
+    fn dequeue_chat(&mut self, user: &User) -> Result<(), StoreError> {
+        self.0.dequeue_chat(user)
+    }
+
+    fn drop_chat(&mut self, user: &User, after: Option<MessageId>) -> Result<(), StoreError> {
+        self.0.drop_chat(user, after)
+    }
+
+    fn queue_group_chat(&mut self, group: &Group, chat: Chat) -> Result<(), StoreError> {
+        self.0.queue_group_chat(group, chat)
+    }
+
+    fn archive_chat(&mut self, chat: &Chat) -> Result<(), StoreError> {
+        self.0.archive_chat(chat)
+    }
+
+    fn get_history(
+        &self,
+        conversation: &Conversation,
+        bound: Option<HistoryBound>,
+        limit: usize,
+    ) -> Result<Vec<Chat>, StoreError> {
+        if let Fault::Panic = self.1 {
+            panic!("history is broken");
+        }
+        self.0.get_history(conversation, bound, limit)
+    }
+
+    fn send_chat(&mut self, chat: &Chat) -> Result<(), StoreError> {
+        self.0.send_chat(chat)?;
+        if let Fault::TimeOut { ref mut timed_out } = self.1 {
+            if !*timed_out {
+                *timed_out = true;
+                return Err(StoreError::Unavailable("timed out".into()));
+            }
+        }
+        Ok(())
+    }
+
+    fn get_chat(&self, id: MessageId) -> Result<Option<Chat>, StoreError> {
+        self.0.get_chat(id)
+    }
+
+    fn add_receipt(&mut self, sender: &User, receipt: &Receipt) -> Result<bool, StoreError> {
+        self.0.add_receipt(sender, receipt)
+    }
+
+    fn take_receipts(&mut self, sender: &User) -> Result<Vec<Receipt>, StoreError> {
+        self.0.take_receipts(sender)
+    }
+
+    fn create_password_hash(&mut self, user: &User, password_hash: &str) -> Result<(), StoreError> {
+        self.0.create_password_hash(user, password_hash)
+    }
+
+    fn get_password_hash(&self, user: &User) -> Result<Option<String>, StoreError> {
+        self.0.get_password_hash(user)
+    }
+
+    fn create_session(&mut self, session: Session) -> Result<(), StoreError> {
+        self.0.create_session(session)
+    }
+
+    fn get_session(&self, token: &str) -> Result<Option<Session>, StoreError> {
+        self.0.get_session(token)
+    }
+
+    fn remove_session(&mut self, token: &str) -> Result<(), StoreError> {
+        self.0.remove_session(token)
+    }
+
+    fn get_read_cursors(
+        &self,
+        user: &User,
+    ) -> Result<HashMap<String, Option<MessageId>>, StoreError> {
+        self.0.get_read_cursors(user)
+    }
+
+    fn set_read_cursor(
+        &mut self,
+        user: &User,
+        device: &str,
+        cursor: Option<MessageId>,
+    ) -> Result<(), StoreError> {
+        self.0.set_read_cursor(user, device, cursor)
+    }
+
+    fn remove_read_cursor(&mut self, user: &User, device: &str) -> Result<(), StoreError> {
+        self.0.remove_read_cursor(user, device)
+    }
+
+    fn set_public_key(&mut self, user: &User, public_key: &PublicKey) -> Result<(), StoreError> {
+        self.0.set_public_key(user, public_key)
+    }
+
+    fn get_public_key(&self, user: &User) -> Result<Option<PublicKey>, StoreError> {
+        self.0.get_public_key(user)
+    }
+
+    fn get_group_members(&self, group: &Group) -> Result<Vec<User>, StoreError> {
+        self.0.get_group_members(group)
+    }
+
+    fn add_group_member(&mut self, user: User, group: &Group) -> Result<(), StoreError> {
+        self.0.add_group_member(user, group)
+    }
+
+    fn remove_group_member(&mut self, user: &User, group: &Group) -> Result<(), StoreError> {
+        self.0.remove_group_member(user, group)
+    }
+
+    fn get_group_role(&self, user: &User, group: &Group) -> Result<Option<Role>, StoreError> {
+        self.0.get_group_role(user, group)
+    }
+
+    fn set_group_role(&mut self, user: &User, group: &Group, role: Role) -> Result<(), StoreError> {
+        self.0.set_group_role(user, group, role)
+    }
+
+    fn is_banned(&self, user: &User, group: &Group) -> Result<bool, StoreError> {
+        self.0.is_banned(user, group)
+    }
+
+    fn set_banned(&mut self, user: &User, group: &Group, banned: bool) -> Result<(), StoreError> {
+        self.0.set_banned(user, group, banned)
+    }
+}
+
+fn random_bytes(len: usize) -> Vec<u8> {
+    let mut bytes = vec![0; len];
+    thread_rng().fill_bytes(&mut bytes);
+    bytes
+}
+
+/// Asserts the server still delivers chats between two users connecting afresh.
+fn assert_serving(server: &ServerHandle) {
+    let first_user = common::generate_user();
+    let second_user = common::generate_user();
+    let mut first_client = common::create_client(server, &first_user);
+    let mut second_client = common::create_client(server, &second_user);
+
+    let chat = common::generate_chat(&first_user, &second_user);
+    first_client
+        .send_message(Message::Chat(chat.clone()))
+        .unwrap();
+    common::assert_delivered(&chat, &second_client.read_chat().unwrap());
+}
+
+#[test]
+fn test_garbage_frames() {
+    let server = common::start_server();
+    let user = common::generate_user();
+    let group = common::generate_group();
+
+    // Frames of random bytes are each rejected, and the connection carries on
+    let mut stream = common::connect_raw(&server);
+    stream.write_all(&common::encode_register(&user)).unwrap();
+    let codec = Codec::default();
+    let garbage_count = 50;
+    for _ in 0..garbage_count {
+        let len = thread_rng().gen::<u8>() as usize;
+        stream
+            .write_all(&codec.encode(&random_bytes(len)).unwrap())
+            .unwrap();
+    }
+    let join = Message::Join(common::create_join(&group));
+    stream.write_all(&common::encode_frame(&join)).unwrap();
+
+    let mut reader = FrameReader::new(stream, codec);
+    let mut read_event =
+        || -> ServerMessage { bincode::deserialize(&reader.read_frame().unwrap()).unwrap() };
+    match read_event() {
+        ServerMessage::Session(_) => {}
+        event => panic!("unexpected event: {:?}", event),
+    }
+    // some random payloads may happen to be valid messages, which are replied to all the same
+    for _ in 0..garbage_count {
+        if let ServerMessage::Error(err) = read_event() {
+            assert_eq!(ErrorCode::Malformed, err.get_code());
+        }
+    }
+    assert_eq!(ServerMessage::Ack(None), read_event());
+
+    assert_serving(&server);
+}
+
+#[test]
+fn test_garbage_handshakes() {
+    let server = common::start_server();
+    let codec = Codec::default();
+
+    for _ in 0..20 {
+        let mut stream = common::connect_raw(&server);
+        let len = thread_rng().gen::<u8>() as usize;
+        stream
+            .write_all(&codec.encode(&random_bytes(len)).unwrap())
+            .unwrap();
+
+        // The connection is turned down, and closed
+        let mut reader = FrameReader::new(stream, codec);
+        let event: ServerMessage = bincode::deserialize(&reader.read_frame().unwrap()).unwrap();
+        match event {
+            ServerMessage::Error(err) => assert_eq!(ErrorCode::Unauthenticated, err.get_code()),
+            event => panic!("unexpected event: {:?}", event),
+        }
+        assert!(reader.read_frame().is_err());
+    }
+
+    // Unframed bytes are no better, be it a frame too large or no frame at all
+    let mut stream = common::connect_raw(&server);
+    stream.write_all(&[0xff; 64]).unwrap();
+    let mut reader = FrameReader::new(stream, codec);
+    assert!(reader.read_frame().is_err());
+
+    assert_serving(&server);
+}
+
+#[test]
+fn test_abrupt_disconnects() {
+    let server = common::start_server();
+    let first_user = common::generate_user();
+    let second_user = common::generate_user();
+    let mut second_client = common::create_client(&server, &second_user);
+
+    // Connections drop halfway through the handshake, halfway through a frame, and right after
+    // sending a chat
+    let handshake = common::encode_register(&common::generate_user());
+    let mut stream = common::connect_raw(&server);
+    stream.write_all(&handshake[..handshake.len() / 2]).unwrap();
+    stream.shutdown(Shutdown::Both).unwrap();
+
+    let mut stream = common::connect_raw(&server);
+    stream
+        .write_all(&common::encode_register(&first_user))
+        .unwrap();
+    let mut reader = FrameReader::new(stream.try_clone().unwrap(), Codec::default());
+    reader.read_frame().unwrap();
+    let chat = common::generate_chat(&first_user, &second_user);
+    let frame = common::encode_frame(&Message::Chat(chat.clone()));
+    stream.write_all(&frame[..frame.len() / 2]).unwrap();
+    drop(stream);
+
+    let mut stream = common::connect_raw(&server);
+    let credentials = Credentials::new(first_user.clone(), common::PASSWORD.into());
+    let credentials = common::encode_frame(&Handshake::Login(credentials));
+    stream.write_all(&credentials).unwrap();
+    stream.write_all(&frame).unwrap();
+    stream.shutdown(Shutdown::Both).unwrap();
+
+    // Only the chat sent whole got through
+    common::assert_delivered(&chat, &second_client.read_chat().unwrap());
+    assert_serving(&server);
+}
+
+#[test]
+fn test_store_panic() {
+    let store = FaultyStore(MemoryStore::new(), Fault::Panic);
+    let server = Server::new(common::HOST, "0", Box::new(store))
+        .spawn()
+        .unwrap();
+    let port = common::get_port(&server);
+    let user = common::generate_user();
+
+    // The connection that made the store panic is closed
+    let mut client = common::create_client(&server, &user);
+    let history = History::new(People::User(common::generate_user()), 10);
+    client.send_message(Message::History(history)).unwrap();
+    assert!(client.read_event().is_err());
+
+    // But the store is still there for everyone, including the same user
+    Client::login(common::HOST, &port, user.get_username(), common::PASSWORD).unwrap();
+    assert_serving(&server);
+}
+
+#[test]
+fn test_store_retry() {
+    let store = FaultyStore(MemoryStore::new(), Fault::TimeOut { timed_out: false });
+    let server = Server::new(common::HOST, "0", Box::new(store))
+        .spawn()
+        .unwrap();
+    let first_user = common::generate_user();
+    let second_user = common::generate_user();
+    let mut first_client = common::create_client(&server, &first_user);
+    let mut second_client = common::create_client(&server, &second_user);
+
+    // The chat whose sending timed out is sent again, yet delivered and archived once
+    let chats: Vec<_> = (0..2)
+        .map(|_| common::generate_chat(&first_user, &second_user))
+        .collect();
+    for chat in chats.iter() {
+        first_client
+            .send_message(Message::Chat(chat.clone()))
+            .unwrap();
+        common::assert_delivered(chat, &second_client.read_chat().unwrap());
+    }
+    let history = History::new(People::User(second_user.clone()), 10);
+    first_client
+        .send_message(Message::History(history))
+        .unwrap();
+    loop {
+        match first_client.read_event().unwrap() {
+            ServerMessage::History(page) => {
+                assert_eq!(2, page.len());
+                break;
+            }
+            ServerMessage::Ack(_) => {}
+            event => panic!("unexpected event: {:?}", event),
+        }
+    }
+}
+
+#[test]
+fn test_store_corrupt_chat() {
+    let store = FaultyStore(MemoryStore::new(), Fault::Corrupt(MessageId::new(2)));
+    let server = Server::new(common::HOST, "0", Box::new(store))
+        .spawn()
+        .unwrap();
+    let port = common::get_port(&server);
+    let first_user = common::generate_user();
+    let second_user = common::generate_user();
+    let mut first_client = common::create_client(&server, &first_user);
+    let mut phone = common::create_client(&server, &second_user);
+    let laptop = Client::login(
+        common::HOST,
+        &port,
+        second_user.get_username(),
+        common::PASSWORD,
+    )
+    .unwrap();
+    let laptop_token = laptop.get_session().get_token().to_string();
+    drop(laptop);
+
+    let chats: Vec<_> = (0..3)
+        .map(|_| common::generate_chat(&first_user, &second_user))
+        .collect();
+    first_client
+        .send_message(Message::Chat(chats[0].clone()))
+        .unwrap();
+    common::assert_delivered(&chats[0], &phone.read_chat().unwrap());
+    // replies come in order, so the acknowledgement was handled once the join is
+    let join = Message::Join(common::create_join(&common::generate_group()));
+    phone.send_message(join).unwrap();
+    assert_eq!(ServerMessage::Ack(None), phone.read_event().unwrap());
+
+    // The phone, ahead of the laptop, skips the corrupt chat
+    for chat in chats[1..].iter() {
+        first_client
+            .send_message(Message::Chat(chat.clone()))
+            .unwrap();
+    }
+    common::assert_delivered(&chats[2], &phone.read_chat().unwrap());
+
+    // Which drops only that chat, rather than the one the laptop hasn't read yet
+    let mut laptop = Client::resume(common::HOST, &port, &laptop_token).unwrap();
+    common::assert_delivered(&chats[0], &laptop.read_chat().unwrap());
+    common::assert_delivered(&chats[2], &laptop.read_chat().unwrap());
+}
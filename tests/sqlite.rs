@@ -0,0 +1,230 @@
+#![cfg(feature = "sqlite")]
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use conver::e2e::KeyPair;
+use conver::message::{HistoryBound, Message, Session, Timestamp};
+use conver::people::People;
+use conver::server::Server;
+use conver::store::{Conversation, SqliteStore, Store, StoreError};
+
+mod common;
+
+/// A database file of its own for every test, which starts out missing.
+fn database_path(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("conver-{}-{}.db", process::id(), name));
+    let _ = fs::remove_file(&path);
+    path
+}
+
+fn open(path: &Path) -> SqliteStore {
+    SqliteStore::new(path.to_str().unwrap()).unwrap()
+}
+
+#[test]
+fn test_sqlite_persistence() {
+    let path = database_path("persistence");
+    let first_user = common::generate_user();
+    let second_user = common::generate_user();
+    let group = common::generate_group();
+    let key_pair = KeyPair::generate().unwrap();
+    let session = Session::new(
+        "token".into(),
+        first_user.clone(),
+        Timestamp::new(u64::MAX / 2),
+    );
+
+    let mut chat = common::generate_chat(&first_user, &second_user);
+    {
+        let mut store = open(&path);
+        chat.set_id(store.next_message_id().unwrap());
+        store.queue_chat(&second_user, chat.clone()).unwrap();
+        store.add_group_member(first_user.clone(), &group).unwrap();
+        store.create_password_hash(&first_user, "hash").unwrap();
+        store.create_session(session.clone()).unwrap();
+        store
+            .set_public_key(&first_user, key_pair.get_public_key())
+            .unwrap();
+    }
+
+    // Everything is still there once the database is opened again
+    let mut store = open(&path);
+    assert_eq!(Some(chat), store.front_chat(&second_user).unwrap());
+    assert_eq!(
+        vec![first_user.clone()],
+        store.get_group_members(&group).unwrap()
+    );
+    assert_eq!(
+        Some("hash".to_string()),
+        store.get_password_hash(&first_user).unwrap()
+    );
+    assert_eq!(Some(session), store.get_session("token").unwrap());
+    assert_eq!(
+        Some(*key_pair.get_public_key()),
+        store.get_public_key(&first_user).unwrap()
+    );
+    assert_eq!(2, store.next_message_id().unwrap().get());
+
+    match store.create_password_hash(&first_user, "other hash") {
+        Err(StoreError::AlreadyExists(_)) => {}
+        result => panic!("registered a user twice: {:?}", result),
+    }
+    store.dequeue_chat(&second_user).unwrap();
+    match store.dequeue_chat(&second_user) {
+        Err(StoreError::NotFound(_)) => {}
+        result => panic!("dequeued from an empty queue: {:?}", result),
+    }
+    store.remove_session("token").unwrap();
+    assert_eq!(None, store.get_session("token").unwrap());
+}
+
+#[test]
+fn test_sqlite_group_chat() {
+    let path = database_path("group_chat");
+    let mut store = open(&path);
+    let first_user = common::generate_user();
+    let second_user = common::generate_user();
+    let group = common::generate_group();
+
+    let chat = common::generate_group_chat(&first_user, &group);
+    match store.queue_group_chat(&group, chat.clone()) {
+        Err(StoreError::NotFound(_)) => {}
+        result => panic!("queued a chat to an unknown group: {:?}", result),
+    }
+
+    // Every member but the sender gets the chat
+    store.add_group_member(first_user.clone(), &group).unwrap();
+    store.add_group_member(second_user.clone(), &group).unwrap();
+    store.queue_group_chat(&group, chat.clone()).unwrap();
+    assert_eq!(None, store.front_chat(&first_user).unwrap());
+    assert_eq!(Some(chat), store.front_chat(&second_user).unwrap());
+
+    // The group is gone along with its last member
+    store.remove_group_member(&first_user, &group).unwrap();
+    store.remove_group_member(&second_user, &group).unwrap();
+    assert!(store.get_group_members(&group).unwrap().is_empty());
+}
+
+#[test]
+fn test_sqlite_history() {
+    let path = database_path("history");
+    let mut store = open(&path);
+    let first_user = common::generate_user();
+    let second_user = common::generate_user();
+
+    let mut chats = Vec::new();
+    for i in 0..10 {
+        let mut chat = if i % 2 == 0 {
+            common::generate_chat(&first_user, &second_user)
+        } else {
+            common::generate_chat(&second_user, &first_user)
+        };
+        chat.set_id(store.next_message_id().unwrap());
+        chat.set_sent_at(Timestamp::new(1000 * (i + 1)));
+        store.archive_chat(&chat).unwrap();
+        chats.push(chat);
+    }
+    let conversation = Conversation::new(&first_user, &People::User(second_user.clone()));
+
+    let page = |bound| store.get_history(&conversation, bound, 3).unwrap();
+    let id = |i: usize| chats[i].get_id().unwrap();
+    assert_eq!(&chats[7..], &page(None)[..]);
+    assert_eq!(&chats[2..5], &page(Some(HistoryBound::Before(id(5))))[..]);
+    assert_eq!(&chats[..2], &page(Some(HistoryBound::Before(id(2))))[..]);
+    assert_eq!(&chats[6..9], &page(Some(HistoryBound::After(id(5))))[..]);
+    assert_eq!(&chats[8..], &page(Some(HistoryBound::After(id(7))))[..]);
+    let time = Timestamp::new(5000);
+    assert_eq!(
+        &chats[1..4],
+        &page(Some(HistoryBound::BeforeTime(time)))[..]
+    );
+    assert_eq!(&chats[5..8], &page(Some(HistoryBound::AfterTime(time)))[..]);
+}
+
+#[test]
+fn test_sqlite_migrations() {
+    let path = database_path("migrations");
+    let version = open(&path).get_schema_version().unwrap();
+    assert!(version > 0);
+
+    // Migrations already applied are not applied again
+    assert_eq!(version, open(&path).get_schema_version().unwrap());
+}
+
+#[test]
+fn test_sqlite_pending_ids_migration() {
+    let path = database_path("pending_ids_migration");
+    let first_user = common::generate_user();
+    let second_user = common::generate_user();
+
+    let mut chats = Vec::new();
+    {
+        let mut store = open(&path);
+        for _ in 0..3 {
+            let mut chat = common::generate_chat(&first_user, &second_user);
+            chat.set_id(store.next_message_id().unwrap());
+            store.queue_chat(&second_user, chat.clone()).unwrap();
+            chats.push(chat);
+        }
+    }
+    // Takes the database back to before pending chats had an id column, with a chat that can't
+    // be read among them
+    let conn = rusqlite::Connection::open(&path).unwrap();
+    conn.execute_batch(
+        "DROP INDEX pending_chats_id;
+         ALTER TABLE pending_chats DROP COLUMN id;
+         DROP TABLE read_cursors;
+         DROP INDEX history_id;
+         DROP TABLE readers;
+         DROP TABLE pending_receipts;
+         DROP TABLE group_bans;
+         ALTER TABLE group_members DROP COLUMN role;
+         PRAGMA user_version = 1;",
+    )
+    .unwrap();
+    conn.execute(
+        "INSERT INTO pending_chats (user, chat) VALUES (?1, x'00')",
+        [second_user.to_string()],
+    )
+    .unwrap();
+    drop(conn);
+
+    // The ids of the chats already pending are filled in by the migration
+    let store = open(&path);
+    let id = |i: usize| chats[i].get_id().unwrap();
+    assert_eq!(
+        Some(chats[1].clone()),
+        store.next_chat_after(&second_user, id(0)).unwrap()
+    );
+    assert_eq!(None, store.next_chat_after(&second_user, id(2)).unwrap());
+
+    // While the chat that can't be read, which has no id to fill in, is dropped
+    let conn = rusqlite::Connection::open(&path).unwrap();
+    let count: i64 = conn
+        .query_row("SELECT COUNT(*) FROM pending_chats", [], |row| row.get(0))
+        .unwrap();
+    assert_eq!(3, count);
+}
+
+#[test]
+fn test_sqlite_server() {
+    let path = database_path("server");
+    let store = open(&path);
+    let server = Server::new(common::HOST, "0", Box::new(store))
+        .spawn()
+        .unwrap();
+
+    let first_user = common::generate_user();
+    let second_user = common::generate_user();
+
+    let mut first_client = common::create_client(&server, &first_user);
+    let mut second_client = common::create_client(&server, &second_user);
+
+    let chat = common::generate_chat(&first_user, &second_user);
+    first_client
+        .send_message(Message::Chat(chat.clone()))
+        .unwrap();
+    common::assert_delivered(&chat, &second_client.read_chat().unwrap());
+}
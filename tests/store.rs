@@ -0,0 +1,225 @@
+use std::collections::HashMap;
+
+use conver::client::Client;
+use conver::e2e::PublicKey;
+use conver::message::{
+    Chat, ErrorCode, HistoryBound, MessageId, Receipt, Role, Session, Timestamp,
+};
+use conver::people::{Group, People, User};
+use conver::server::Server;
+use conver::store::{Conversation, MemoryStore, Store, StoreError};
+
+mod common;
+
+/// A store whose backend is never reachable.
+struct UnavailableStore;
+
+impl UnavailableStore {
+    fn error<T>() -> Result<T, StoreError> {
+        Err(StoreError::Unavailable("backend is down".into()))
+    }
+}
+
+impl Store for UnavailableStore {
+    fn next_message_id(&mut self) -> Result<MessageId, StoreError> {
+        UnavailableStore::error()
+    }
+
+    fn front_chat(&self, _: &User) -> Result<Option<Chat>, StoreError> {
+        UnavailableStore::error()
+    }
+
+    fn next_chat_after(&self, _: &User, _: MessageId) -> Result<Option<Chat>, StoreError> {
+        UnavailableStore::error()
+    }
+
+    fn queue_chat(&mut self, _: &User, _: Chat) -> Result<(), StoreError> {
+        UnavailableStore::error()
+    }
+
+    fn dequeue_chat(&mut self, _: &User) -> Result<(), StoreError> {
+        UnavailableStore::error()
+    }
+
+    fn drop_chat(&mut self, _: &User, _: Option<MessageId>) -> Result<(), StoreError> {
+        UnavailableStore::error()
+    }
+
+    fn queue_group_chat(&mut self, _: &Group, _: Chat) -> Result<(), StoreError> {
+        UnavailableStore::error()
+    }
+
+    fn archive_chat(&mut self, _: &Chat) -> Result<(), StoreError> {
+        UnavailableStore::error()
+    }
+
+    fn get_history(
+        &self,
+        _: &Conversation,
+        _: Option<HistoryBound>,
+        _: usize,
+    ) -> Result<Vec<Chat>, StoreError> {
+        UnavailableStore::error()
+    }
+
+    fn get_chat(&self, _: MessageId) -> Result<Option<Chat>, StoreError> {
+        UnavailableStore::error()
+    }
+
+    fn add_receipt(&mut self, _: &User, _: &Receipt) -> Result<bool, StoreError> {
+        UnavailableStore::error()
+    }
+
+    fn take_receipts(&mut self, _: &User) -> Result<Vec<Receipt>, StoreError> {
+        UnavailableStore::error()
+    }
+
+    fn create_password_hash(&mut self, _: &User, _: &str) -> Result<(), StoreError> {
+        UnavailableStore::error()
+    }
+
+    fn get_password_hash(&self, _: &User) -> Result<Option<String>, StoreError> {
+        UnavailableStore::error()
+    }
+
+    fn create_session(&mut self, _: Session) -> Result<(), StoreError> {
+        UnavailableStore::error()
+    }
+
+    fn get_session(&self, _: &str) -> Result<Option<Session>, StoreError> {
+        UnavailableStore::error()
+    }
+
+    fn remove_session(&mut self, _: &str) -> Result<(), StoreError> {
+        UnavailableStore::error()
+    }
+
+    fn get_read_cursors(&self, _: &User) -> Result<HashMap<String, Option<MessageId>>, StoreError> {
+        UnavailableStore::error()
+    }
+
+    fn set_read_cursor(
+        &mut self,
+        _: &User,
+        _: &str,
+        _: Option<MessageId>,
+    ) -> Result<(), StoreError> {
+        UnavailableStore::error()
+    }
+
+    fn remove_read_cursor(&mut self, _: &User, _: &str) -> Result<(), StoreError> {
+        UnavailableStore::error()
+    }
+
+    fn set_public_key(&mut self, _: &User, _: &PublicKey) -> Result<(), StoreError> {
+        UnavailableStore::error()
+    }
+
+    fn get_public_key(&self, _: &User) -> Result<Option<PublicKey>, StoreError> {
+        UnavailableStore::error()
+    }
+
+    fn get_group_members(&self, _: &Group) -> Result<Vec<User>, StoreError> {
+        UnavailableStore::error()
+    }
+
+    fn add_group_member(&mut self, _: User, _: &Group) -> Result<(), StoreError> {
+        UnavailableStore::error()
+    }
+
+    fn remove_group_member(&mut self, _: &User, _: &Group) -> Result<(), StoreError> {
+        UnavailableStore::error()
+    }
+
+    fn get_group_role(&self, _: &User, _: &Group) -> Result<Option<Role>, StoreError> {
+        UnavailableStore::error()
+    }
+
+    fn set_group_role(&mut self, _: &User, _: &Group, _: Role) -> Result<(), StoreError> {
+        UnavailableStore::error()
+    }
+
+    fn is_banned(&self, _: &User, _: &Group) -> Result<bool, StoreError> {
+        UnavailableStore::error()
+    }
+
+    fn set_banned(&mut self, _: &User, _: &Group, _: bool) -> Result<(), StoreError> {
+        UnavailableStore::error()
+    }
+}
+
+#[test]
+fn test_memory_not_found() {
+    let mut store = MemoryStore::new();
+    let user = common::generate_user();
+    let group = common::generate_group();
+
+    match store.dequeue_chat(&user) {
+        Err(StoreError::NotFound(_)) => {}
+        result => panic!("dequeued from an empty queue: {:?}", result),
+    }
+    match store.queue_group_chat(&group, common::generate_group_chat(&user, &group)) {
+        Err(StoreError::NotFound(_)) => {}
+        result => panic!("queued a chat to an unknown group: {:?}", result),
+    }
+}
+
+#[test]
+fn test_memory_history() {
+    let mut store = MemoryStore::new();
+    let first_user = common::generate_user();
+    let second_user = common::generate_user();
+
+    // Chats both ways end up in the same conversation
+    let mut chats = Vec::new();
+    for i in 0..10 {
+        let mut chat = if i % 2 == 0 {
+            common::generate_chat(&first_user, &second_user)
+        } else {
+            common::generate_chat(&second_user, &first_user)
+        };
+        let id = store.next_message_id().unwrap();
+        chat.set_id(id);
+        chat.set_sent_at(Timestamp::new(1000 * (i + 1)));
+        store.archive_chat(&chat).unwrap();
+        chats.push(chat);
+    }
+    let conversation = Conversation::new(&first_user, &People::User(second_user.clone()));
+    assert_eq!(
+        conversation,
+        Conversation::new(&second_user, &People::User(first_user.clone()))
+    );
+
+    let page = |bound| store.get_history(&conversation, bound, 3).unwrap();
+    let id = |i: usize| chats[i].get_id().unwrap();
+    assert_eq!(&chats[7..], &page(None)[..]);
+    assert_eq!(&chats[2..5], &page(Some(HistoryBound::Before(id(5))))[..]);
+    assert_eq!(&chats[..2], &page(Some(HistoryBound::Before(id(2))))[..]);
+    assert_eq!(&chats[6..9], &page(Some(HistoryBound::After(id(5))))[..]);
+    assert_eq!(&chats[8..], &page(Some(HistoryBound::After(id(7))))[..]);
+    let time = Timestamp::new(5000);
+    assert_eq!(
+        &chats[1..4],
+        &page(Some(HistoryBound::BeforeTime(time)))[..]
+    );
+    assert_eq!(&chats[5..8], &page(Some(HistoryBound::AfterTime(time)))[..]);
+}
+
+#[test]
+fn test_store_unavailable() {
+    let server = Server::new(common::HOST, "0", Box::new(UnavailableStore))
+        .spawn()
+        .unwrap();
+
+    // Not even the credentials can be stored, so nobody gets to connect
+    let user = common::generate_user();
+    common::assert_rejected(
+        Client::register(
+            common::HOST,
+            &common::get_port(&server),
+            user.get_username(),
+            common::PASSWORD,
+        ),
+        ErrorCode::Unavailable,
+    );
+}
@@ -0,0 +1,189 @@
+use std::fs;
+use std::path::PathBuf;
+use std::process;
+
+use lazy_static::lazy_static;
+use rcgen::{BasicConstraints, CertificateParams, IsCa, KeyPair};
+
+use conver::client::Client;
+use conver::message::{Credentials, Handshake, Message};
+use conver::people::User;
+use conver::server::{Server, ServerHandle};
+use conver::store::MemoryStore;
+use conver::stream::Stream;
+use conver::tls;
+
+mod common;
+
+const HOST: &str = "localhost";
+
+/// PEM files of a CA, and of a server certificate it issued, generated for this test run.
+struct Certificates {
+    ca_path: PathBuf,
+    cert_path: PathBuf,
+    key_path: PathBuf,
+}
+
+lazy_static! {
+    static ref CERTIFICATES: Certificates = generate_certificates("trusted");
+}
+
+fn generate_certificates(name: &str) -> Certificates {
+    let dir = std::env::temp_dir().join(format!("conver-tls-{}-{}", process::id(), name));
+    fs::create_dir_all(&dir).unwrap();
+
+    let mut ca_params = CertificateParams::new(Vec::new()).unwrap();
+    ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+    let ca_key = KeyPair::generate().unwrap();
+    let ca_cert = ca_params.self_signed(&ca_key).unwrap();
+
+    let params = CertificateParams::new(vec![HOST.into(), "127.0.0.1".into()]).unwrap();
+    let key = KeyPair::generate().unwrap();
+    let cert = params.signed_by(&key, &ca_cert, &ca_key).unwrap();
+
+    let certificates = Certificates {
+        ca_path: dir.join("ca.pem"),
+        cert_path: dir.join("cert.pem"),
+        key_path: dir.join("key.pem"),
+    };
+    fs::write(&certificates.ca_path, ca_cert.pem()).unwrap();
+    fs::write(&certificates.cert_path, cert.pem()).unwrap();
+    fs::write(&certificates.key_path, key.serialize_pem()).unwrap();
+    certificates
+}
+
+fn start_server() -> ServerHandle {
+    let config = tls::load_server_config(&CERTIFICATES.cert_path, &CERTIFICATES.key_path).unwrap();
+    let mut server = Server::new(common::HOST, "0", Box::new(MemoryStore::new()));
+    server.set_tls(config);
+    server.spawn().unwrap()
+}
+
+fn register(user: &User, stream: Stream) -> Result<Client, Box<dyn std::error::Error>> {
+    let credentials = Credentials::new(user.clone(), common::PASSWORD.into());
+    Client::connect(stream, Handshake::Register(credentials))
+}
+
+fn create_verified_client(port: &str, user: &User) -> Client {
+    let config = tls::load_client_config(&CERTIFICATES.ca_path).unwrap();
+    register(user, Stream::connect_tls(HOST, port, config).unwrap()).unwrap()
+}
+
+#[test]
+fn test_tls_chat() {
+    let server = start_server();
+    let port = common::get_port(&server);
+
+    let first_user = common::generate_user();
+    let second_user = common::generate_user();
+
+    let mut first_client = create_verified_client(&port, &first_user);
+    let mut second_client = create_verified_client(&port, &second_user);
+
+    // First sends a long chat over TLS, Second receives it whole
+    let chat = common::generate_long_chat(&first_user, &second_user);
+    first_client
+        .send_message(Message::Chat(chat.clone()))
+        .unwrap();
+    common::assert_delivered(&chat, &second_client.read_chat().unwrap());
+
+    // Second answers back
+    let chat = common::generate_chat(&second_user, &first_user);
+    second_client
+        .send_message(Message::Chat(chat.clone()))
+        .unwrap();
+    common::assert_delivered(&chat, &first_client.read_chat().unwrap());
+}
+
+#[test]
+fn test_tls_pinned() {
+    let server = start_server();
+    let port = common::get_port(&server);
+
+    let first_user = common::generate_user();
+    let second_user = common::generate_user();
+
+    // First trusts the server certificate by its fingerprint alone
+    let fingerprint = tls::load_certificate_fingerprint(&CERTIFICATES.cert_path).unwrap();
+    let config = tls::pinned_client_config(&fingerprint).unwrap();
+    let mut first_client = register(
+        &first_user,
+        Stream::connect_tls(HOST, &port, config).unwrap(),
+    )
+    .unwrap();
+    let mut second_client = create_verified_client(&port, &second_user);
+
+    let chat = common::generate_chat(&second_user, &first_user);
+    second_client
+        .send_message(Message::Chat(chat.clone()))
+        .unwrap();
+    common::assert_delivered(&chat, &first_client.read_chat().unwrap());
+}
+
+#[test]
+fn test_tls_untrusted_ca() {
+    let server = start_server();
+    let port = common::get_port(&server);
+
+    // The server certificate wasn't issued by this CA
+    let untrusted = generate_certificates("untrusted");
+    let config = tls::load_client_config(&untrusted.ca_path).unwrap();
+    let stream = Stream::connect_tls(HOST, &port, config).unwrap();
+    assert!(register(&common::generate_user(), stream).is_err());
+}
+
+#[test]
+fn test_tls_wrong_pin() {
+    let server = start_server();
+    let port = common::get_port(&server);
+
+    let untrusted = generate_certificates("pinned");
+    let fingerprint = tls::load_certificate_fingerprint(&untrusted.cert_path).unwrap();
+    let config = tls::pinned_client_config(&fingerprint).unwrap();
+    let stream = Stream::connect_tls(HOST, &port, config).unwrap();
+    assert!(register(&common::generate_user(), stream).is_err());
+}
+
+#[test]
+fn test_tls_plaintext_rejected() {
+    let server = start_server();
+    let port = common::get_port(&server);
+
+    // A client not speaking TLS gets nowhere
+    let result = Client::register(
+        common::HOST,
+        &port,
+        common::generate_user().get_username(),
+        common::PASSWORD,
+    );
+    assert!(result.is_err());
+}
+
+#[cfg(feature = "async")]
+#[test]
+fn test_tls_async_chat() {
+    use conver::server::AsyncServer;
+
+    let config = tls::load_server_config(&CERTIFICATES.cert_path, &CERTIFICATES.key_path).unwrap();
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let mut server = AsyncServer::new(common::HOST, "0", Box::new(MemoryStore::new()));
+    server.set_tls(config);
+    let server = runtime.block_on(server.spawn()).unwrap();
+    let port = server.get_address().port().to_string();
+
+    let first_user = common::generate_user();
+    let second_user = common::generate_user();
+
+    let connect = |user: &User| {
+        let config = tls::load_client_config(&CERTIFICATES.ca_path).unwrap();
+        register(user, Stream::connect_tls(HOST, &port, config).unwrap()).unwrap()
+    };
+    let mut first_client = connect(&first_user);
+    let mut second_client = connect(&second_user);
+
+    let chat = common::generate_long_chat(&first_user, &second_user);
+    first_client
+        .send_message(Message::Chat(chat.clone()))
+        .unwrap();
+    common::assert_delivered(&chat, &second_client.read_chat().unwrap());
+}
@@ -0,0 +1,96 @@
+use conver::client::Client;
+use conver::message::{ErrorCode, Message, ServerMessage, Typing};
+use conver::people::{People, User};
+
+mod common;
+
+fn send_typing(client: &mut Client, receiver: People) {
+    client
+        .send_message(Message::Typing(Typing::new(receiver)))
+        .unwrap();
+    assert_eq!(ServerMessage::Ack(None), client.read_event().unwrap());
+}
+
+fn assert_typing(client: &mut Client, sender: &User, receiver: &People) {
+    match client.read_event().unwrap() {
+        ServerMessage::Typing(user, people) => {
+            assert_eq!(sender, &user);
+            assert_eq!(receiver, &people);
+        }
+        event => panic!("unexpected event: {:?}", event),
+    }
+}
+
+#[test]
+fn test_typing_direct() {
+    let server = common::start_server();
+    let first_user = common::generate_user();
+    let second_user = common::generate_user();
+    let third_user = common::generate_user();
+    drop(common::create_client(&server, &third_user));
+
+    let mut first_client = common::create_client(&server, &first_user);
+    let mut second_client = common::create_client(&server, &second_user);
+    let receiver = People::User(second_user.clone());
+
+    // Typing again right away is not passed on, so the chat is what comes next
+    send_typing(&mut first_client, receiver.clone());
+    send_typing(&mut first_client, receiver.clone());
+    assert_typing(&mut second_client, &first_user, &receiver);
+    let chat = common::generate_chat(&first_user, &second_user);
+    first_client
+        .send_message(Message::Chat(chat.clone()))
+        .unwrap();
+    match second_client.read_event().unwrap() {
+        ServerMessage::Chat(sent) => common::assert_delivered(&chat, &sent),
+        event => panic!("unexpected event: {:?}", event),
+    }
+    match first_client.read_event().unwrap() {
+        ServerMessage::Ack(Some(_)) => {}
+        event => panic!("unexpected event: {:?}", event),
+    }
+
+    // Nor is it kept for a receiver offline at the time
+    send_typing(&mut first_client, People::User(third_user.clone()));
+    let chat = common::generate_chat(&first_user, &third_user);
+    first_client
+        .send_message(Message::Chat(chat.clone()))
+        .unwrap();
+    let mut third_client = common::create_client(&server, &third_user);
+    match third_client.read_event().unwrap() {
+        ServerMessage::Chat(sent) => common::assert_delivered(&chat, &sent),
+        event => panic!("unexpected event: {:?}", event),
+    }
+}
+
+#[test]
+fn test_typing_group() {
+    let server = common::start_server();
+    let users: Vec<_> = (0..3).map(|_| common::generate_user()).collect();
+    let group = common::generate_group();
+    let receiver = People::Group(group.clone());
+
+    let mut clients: Vec<_> = users
+        .iter()
+        .map(|user| common::create_client(&server, user))
+        .collect();
+    // Nobody is typing to a group nobody joined
+    let typing = Message::Typing(Typing::new(receiver.clone()));
+    clients[0].send_message(typing).unwrap();
+    match clients[0].read_event().unwrap() {
+        ServerMessage::Error(err) => assert_eq!(ErrorCode::NotFound, err.get_code()),
+        event => panic!("unexpected event: {:?}", event),
+    }
+    for client in clients.iter_mut() {
+        let join = Message::Join(common::create_join(&group));
+        client.send_message(join).unwrap();
+        assert_eq!(ServerMessage::Ack(None), client.read_event().unwrap());
+    }
+
+    // Every other member is told, but not the sender
+    send_typing(&mut clients[0], receiver.clone());
+    assert_typing(&mut clients[1], &users[0], &receiver);
+    assert_typing(&mut clients[2], &users[0], &receiver);
+    send_typing(&mut clients[2], receiver.clone());
+    assert_typing(&mut clients[0], &users[2], &receiver);
+}